@@ -1,16 +1,38 @@
+use crate::connector::Judgement;
 use crate::primitives::{ChainAddress, ChainName, IdentityContext, JudgementStateBlanked};
-use crate::Database;
+use crate::{Database, NetworkConfig};
 use std::str::FromStr;
 
 pub type Result<T> = std::result::Result<T, Response>;
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum Command {
-    Status(ChainAddress),
-    Verify(ChainAddress, Vec<RawFieldName>),
+    Status(IdentityAddress),
+    Verify(IdentityAddress, Vec<RawFieldName>),
+    Unlock(IdentityAddress),
+    Erroneous(IdentityAddress),
+    Level(IdentityAddress, Judgement),
     Help,
 }
 
+/// The address of an identity as specified in a command, optionally prefixed
+/// with the network (`<CHAIN>:<ADDR>`). The network is detected based on the
+/// SS58 prefix of the address if not specified.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct IdentityAddress {
+    pub address: ChainAddress,
+    pub chain: Option<ChainName>,
+}
+
+impl From<ChainAddress> for IdentityAddress {
+    fn from(address: ChainAddress) -> Self {
+        IdentityAddress {
+            address,
+            chain: None,
+        }
+    }
+}
+
 impl FromStr for Command {
     type Err = Response;
 
@@ -24,7 +46,7 @@ impl FromStr for Command {
                 return Err(Response::UnknownCommand);
             }
 
-            Ok(Command::Status(ChainAddress::from(parts[0].to_string())))
+            Ok(Command::Status(parse_address(parts[0])?))
         } else if s.starts_with("verify") {
             let parts: Vec<&str> = s.split(' ').skip(1).collect();
             if parts.len() < 2 {
@@ -32,12 +54,36 @@ impl FromStr for Command {
             }
 
             Ok(Command::Verify(
-                ChainAddress::from(parts[0].to_string()),
+                parse_address(parts[0])?,
                 parts[1..]
                     .iter()
                     .map(|s| RawFieldName::from_str(s))
                     .collect::<Result<Vec<RawFieldName>>>()?,
             ))
+        } else if s.starts_with("unlock") {
+            let parts: Vec<&str> = s.split(' ').skip(1).collect();
+            if parts.len() != 1 {
+                return Err(Response::UnknownCommand);
+            }
+
+            Ok(Command::Unlock(parse_address(parts[0])?))
+        } else if s.starts_with("erroneous") {
+            let parts: Vec<&str> = s.split(' ').skip(1).collect();
+            if parts.len() != 1 {
+                return Err(Response::UnknownCommand);
+            }
+
+            Ok(Command::Erroneous(parse_address(parts[0])?))
+        } else if s.starts_with("level") {
+            let parts: Vec<&str> = s.split(' ').skip(1).collect();
+            if parts.len() != 2 {
+                return Err(Response::UnknownCommand);
+            }
+
+            Ok(Command::Level(
+                parse_address(parts[0])?,
+                parse_judgement(parts[1])?,
+            ))
         } else if s.starts_with("help") {
             let count = s.split(' ').count();
 
@@ -54,10 +100,17 @@ impl FromStr for Command {
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum Response {
-    Status(JudgementStateBlanked),
+    Status(Box<JudgementStateBlanked>),
     Verified(ChainAddress, Vec<RawFieldName>),
+    Unlocked(ChainAddress),
+    MarkedErroneous(ChainAddress),
+    JudgementAlreadySubmitted(ChainAddress),
+    AlreadyErroneous(ChainAddress),
+    JudgementLevel(ChainAddress, Judgement),
+    UnsupportedJudgement(ChainName, Judgement),
     UnknownCommand,
     IdentityNotFound,
+    InvalidAddress(String, String),
     InvalidSyntax(Option<String>),
     FullyVerified(ChainAddress),
     InternalError,
@@ -82,10 +135,36 @@ impl std::fmt::Display for Response {
                     all
                 })
             }
+            Response::Unlocked(_) => "Lifted the lockout of all fields".to_string(),
+            Response::MarkedErroneous(_) => {
+                "Identity has been marked as erroneous. The extrinsic will be submitted in a couple of minutes".to_string()
+            }
+            Response::JudgementAlreadySubmitted(_) => {
+                "A judgement has already been submitted for this identity".to_string()
+            }
+            Response::AlreadyErroneous(_) => {
+                "Identity has been marked as erroneous, its judgement level can no longer be changed".to_string()
+            }
+            Response::JudgementLevel(_, judgement) => {
+                format!(
+                    "The {} judgement will be issued once the identity is fully verified",
+                    judgement.as_str()
+                )
+            }
+            Response::UnsupportedJudgement(chain, judgement) => {
+                format!(
+                    "The {} judgement is not supported by the watcher of the {} network",
+                    judgement.as_str(),
+                    chain.as_str()
+                )
+            }
             Response::UnknownCommand => "The provided command is unknown".to_string(),
             Response::IdentityNotFound => {
                 "Identity was not found or invalid query executed".to_string()
             }
+            Response::InvalidAddress(input, reason) => {
+                format!("Invalid address '{}': {}", input, reason)
+            }
             Response::InvalidSyntax(input) => {
                 format!(
                     "Invalid input{}",
@@ -101,6 +180,11 @@ impl std::fmt::Display for Response {
             Response::Help => "\
                 status <ADDR>\t\t\tShow the current verification status of the specified address.\n\
                 verify <ADDR> <FIELD>...\tVerify one or multiple fields of the specified address.\n\
+                unlock <ADDR>\t\t\tLift the lockout after too many failed attempts.\n\
+                erroneous <ADDR>\t\tIssue an erroneous judgement for a fraudulent identity.\n\
+                level <ADDR> <LEVEL>\t\tSet the judgement level (reasonable, known_good, out_of_date, low_quality).\n\
+                \n\
+                <ADDR> can be prefixed with the network, e.g. kusama:<ADDR>, which is required if the network cannot be detected from the address.\n\
                 "
             .to_string(),
             Response::FullyVerified(_) => {
@@ -120,6 +204,11 @@ pub enum RawFieldName {
     Web,
     Twitter,
     Matrix,
+    PGPFingerprint,
+    Image,
+    Additional,
+    Discord,
+    GitHub,
     // Represents the full identity
     All,
 }
@@ -134,6 +223,11 @@ impl std::fmt::Display for RawFieldName {
                 RawFieldName::Web => "web",
                 RawFieldName::Twitter => "twitter",
                 RawFieldName::Matrix => "matrix",
+                RawFieldName::PGPFingerprint => "pgp_fingerprint",
+                RawFieldName::Image => "image",
+                RawFieldName::Additional => "additional",
+                RawFieldName::Discord => "discord",
+                RawFieldName::GitHub => "github",
                 RawFieldName::All => "all",
             }
         })
@@ -154,6 +248,11 @@ impl FromStr for RawFieldName {
             "web" => RawFieldName::Web,
             "twitter" => RawFieldName::Twitter,
             "matrix" => RawFieldName::Matrix,
+            "pgpfingerprint" | "pgp" => RawFieldName::PGPFingerprint,
+            "image" => RawFieldName::Image,
+            "additional" => RawFieldName::Additional,
+            "discord" => RawFieldName::Discord,
+            "github" => RawFieldName::GitHub,
             "all" => RawFieldName::All,
             _ => return Err(Response::InvalidSyntax(Some(s.to_string()))),
         };
@@ -163,21 +262,32 @@ impl FromStr for RawFieldName {
 }
 
 #[allow(clippy::needless_lifetimes)]
-pub async fn process_admin<'a>(db: &'a Database, command: Command) -> Response {
+pub async fn process_admin<'a>(
+    db: &'a Database,
+    networks: &'a [NetworkConfig],
+    command: Command,
+) -> Response {
     let local = |db: &'a Database, command: Command| async move {
         match command {
             Command::Status(addr) => {
-                let context = create_context(addr);
+                let context = match create_context(addr, networks) {
+                    Ok(context) => context,
+                    Err(resp) => return Ok(resp),
+                };
                 let state = db.fetch_judgement_state(&context).await?;
 
                 // Determine response based on database lookup.
                 match state {
-                    Some(state) => Ok(Response::Status(state.into())),
+                    Some(state) => Ok(Response::Status(Box::new(state.into()))),
                     None => Ok(Response::IdentityNotFound),
                 }
             }
             Command::Verify(addr, fields) => {
-                let context = create_context(addr.clone());
+                let context = match create_context(addr, networks) {
+                    Ok(context) => context,
+                    Err(resp) => return Ok(resp),
+                };
+                let addr = context.address.clone();
 
                 // Check if _all_ should be verified (respectively the full identity)
                 #[allow(clippy::collapsible_if)]
@@ -198,6 +308,63 @@ pub async fn process_admin<'a>(db: &'a Database, command: Command) -> Response {
 
                 Ok(Response::Verified(addr, fields))
             }
+            Command::Unlock(addr) => {
+                let context = match create_context(addr, networks) {
+                    Ok(context) => context,
+                    Err(resp) => return Ok(resp),
+                };
+                let addr = context.address.clone();
+
+                if db.unlock(&context).await? {
+                    Ok(Response::Unlocked(addr))
+                } else {
+                    Ok(Response::IdentityNotFound)
+                }
+            }
+            Command::Erroneous(addr) => {
+                let context = match create_context(addr, networks) {
+                    Ok(context) => context,
+                    Err(resp) => return Ok(resp),
+                };
+                let addr = context.address.clone();
+
+                match db.mark_erroneous(&context).await? {
+                    Some(true) => Ok(Response::MarkedErroneous(addr)),
+                    Some(false) => Ok(Response::JudgementAlreadySubmitted(addr)),
+                    None => Ok(Response::IdentityNotFound),
+                }
+            }
+            Command::Level(addr, judgement) => {
+                let context = match create_context(addr, networks) {
+                    Ok(context) => context,
+                    Err(resp) => return Ok(resp),
+                };
+                let addr = context.address.clone();
+
+                // Reject judgements which the watcher of the network cannot
+                // submit.
+                let watcher_version = networks
+                    .iter()
+                    .find(|network| network.name == context.chain)
+                    .map(|network| network.watcher_version())
+                    .unwrap_or(1);
+
+                if judgement.min_watcher_version() > watcher_version {
+                    return Ok(Response::UnsupportedJudgement(context.chain, judgement));
+                }
+
+                if db.set_judgement_level(&context, judgement).await? {
+                    return Ok(Response::JudgementLevel(addr, judgement));
+                }
+
+                match db.fetch_judgement_state(&context).await? {
+                    Some(state) if state.judgement_submitted => {
+                        Ok(Response::JudgementAlreadySubmitted(addr))
+                    }
+                    Some(_) => Ok(Response::AlreadyErroneous(addr)),
+                    None => Ok(Response::IdentityNotFound),
+                }
+            }
             Command::Help => Ok(Response::Help),
         }
     };
@@ -212,36 +379,83 @@ pub async fn process_admin<'a>(db: &'a Database, command: Command) -> Response {
     }
 }
 
-/// Convenience function for creating a full identity context when only the
-/// address itself is present. Only supports Kusama and Polkadot for now.
-pub fn create_context(address: ChainAddress) -> IdentityContext {
-    let chain = if address.as_str().starts_with('1') {
-        ChainName::Polkadot
-    } else {
-        ChainName::Kusama
+/// Parses the judgement level. Erroneous judgements are issued via the
+/// `erroneous` command instead.
+fn parse_judgement(s: &str) -> Result<Judgement> {
+    // `fee_paid` is deliberately unsupported, registrars cannot issue it.
+    let judgement = match s.replace(['-', '_'], "").to_lowercase().as_str() {
+        "reasonable" => Judgement::Reasonable,
+        "knowngood" => Judgement::KnownGood,
+        "outofdate" => Judgement::OutOfDate,
+        "lowquality" => Judgement::LowQuality,
+        _ => return Err(Response::InvalidSyntax(Some(s.to_string()))),
     };
 
-    IdentityContext { address, chain }
+    Ok(judgement)
+}
+
+/// Parses the address, optionally prefixed with the network, rejecting invalid
+/// SS58 addresses. Whether the network is supported is checked when
+/// processing the command.
+fn parse_address(s: &str) -> Result<IdentityAddress> {
+    // SS58 addresses never contain a colon.
+    let (chain, address) = match s.split_once(':') {
+        Some((chain, address)) => (Some(ChainName::from(chain)), address),
+        None => (None, s),
+    };
+
+    let address = ChainAddress::from(address.to_string());
+    address
+        .decode()
+        .map_err(|err| Response::InvalidAddress(s.to_string(), err.to_string()))?;
+
+    Ok(IdentityAddress { address, chain })
+}
+
+/// Convenience function for creating a full identity context. The network is
+/// detected based on the SS58 prefix of the address if not specified, which
+/// requires the prefix to be unique among the configured networks.
+pub fn create_context(
+    address: IdentityAddress,
+    networks: &[NetworkConfig],
+) -> Result<IdentityContext> {
+    let invalid = |err: anyhow::Error| {
+        Response::InvalidAddress(address.address.as_str().to_string(), err.to_string())
+    };
+
+    let chain = match &address.chain {
+        Some(chain) => chain.clone(),
+        None => address.address.chain(networks).map_err(invalid)?,
+    };
+
+    let context = IdentityContext {
+        address: address.address.clone(),
+        chain,
+    };
+    context.validate(networks).map_err(invalid)?;
+
+    Ok(context)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::primitives::JudgementState;
+    use crate::primitives::{ChainName, JudgementState};
+
+    const ALICE: &str = "15oF4uVJwmo4TdGW7VfQxNLavjCXviqxT9S1MgbjMNHr6Sp5";
+    const ALICE_KUSAMA: &str = "HNZata7iMYWmk5RvZRTiAsSDhV8366zq2YGb3tLH5Upf74F";
+
+    fn address(address: &str) -> IdentityAddress {
+        ChainAddress::from(address.to_string()).into()
+    }
 
     #[test]
     fn command_status() {
-        let resp = Command::from_str("status Alice").unwrap();
-        assert_eq!(
-            resp,
-            Command::Status(ChainAddress::from("Alice".to_string()))
-        );
+        let resp = Command::from_str(&format!("status {}", ALICE)).unwrap();
+        assert_eq!(resp, Command::Status(address(ALICE)));
 
-        let resp = Command::from_str("status  Alice").unwrap();
-        assert_eq!(
-            resp,
-            Command::Status(ChainAddress::from("Alice".to_string()))
-        );
+        let resp = Command::from_str(&format!("status  {}", ALICE)).unwrap();
+        assert_eq!(resp, Command::Status(address(ALICE)));
 
         let resp = Command::from_str("status");
         assert!(resp.is_err())
@@ -249,46 +463,138 @@ mod tests {
 
     #[test]
     fn command_verify() {
-        let resp = Command::from_str("verify Alice email").unwrap();
+        let resp = Command::from_str(&format!("verify {} email", ALICE)).unwrap();
         assert_eq!(
             resp,
-            Command::Verify(
-                ChainAddress::from("Alice".to_string()),
-                vec![RawFieldName::Email]
-            )
+            Command::Verify(address(ALICE), vec![RawFieldName::Email])
         );
 
-        let resp = Command::from_str("verify Alice email displayname").unwrap();
+        let resp = Command::from_str(&format!("verify {} email displayname", ALICE)).unwrap();
         assert_eq!(
             resp,
             Command::Verify(
-                ChainAddress::from("Alice".to_string()),
+                address(ALICE),
                 vec![RawFieldName::Email, RawFieldName::DisplayName]
             )
         );
 
-        let resp = Command::from_str("verify Alice email display_name").unwrap();
+        let resp = Command::from_str(&format!("verify {} email display_name", ALICE)).unwrap();
         assert_eq!(
             resp,
             Command::Verify(
-                ChainAddress::from("Alice".to_string()),
+                address(ALICE),
                 vec![RawFieldName::Email, RawFieldName::DisplayName]
             )
         );
 
-        let resp = Command::from_str("verify Alice all").unwrap();
+        let resp = Command::from_str(&format!("verify {} all", ALICE)).unwrap();
         assert_eq!(
             resp,
-            Command::Verify(
-                ChainAddress::from("Alice".to_string()),
-                vec![RawFieldName::All]
-            )
+            Command::Verify(address(ALICE), vec![RawFieldName::All])
         );
 
-        let resp = Command::from_str("verify Alice");
+        let resp = Command::from_str(&format!("verify {}", ALICE));
+        assert!(resp.is_err());
+    }
+
+    #[test]
+    fn command_unlock() {
+        let resp = Command::from_str(&format!("unlock {}", ALICE)).unwrap();
+        assert_eq!(resp, Command::Unlock(address(ALICE)));
+
+        let resp = Command::from_str("unlock");
+        assert!(resp.is_err());
+
+        let resp = Command::from_str(&format!("unlock {} Bob", ALICE));
+        assert!(resp.is_err());
+    }
+
+    #[test]
+    fn command_erroneous() {
+        let resp = Command::from_str(&format!("erroneous {}", ALICE)).unwrap();
+        assert_eq!(resp, Command::Erroneous(address(ALICE)));
+
+        let resp = Command::from_str("erroneous");
+        assert!(resp.is_err());
+    }
+
+    #[test]
+    fn command_level() {
+        let resp = Command::from_str(&format!("level {} known_good", ALICE)).unwrap();
+        assert_eq!(resp, Command::Level(address(ALICE), Judgement::KnownGood));
+
+        let resp = Command::from_str(&format!("level {} LowQuality", ALICE)).unwrap();
+        assert_eq!(resp, Command::Level(address(ALICE), Judgement::LowQuality));
+
+        let resp = Command::from_str(&format!("level {} erroneous", ALICE));
+        assert!(matches!(resp, Err(Response::InvalidSyntax(_))));
+
+        let resp = Command::from_str(&format!("level {}", ALICE));
         assert!(resp.is_err());
     }
 
+    #[test]
+    fn command_invalid_address() {
+        let resp = Command::from_str("status Alice");
+        assert!(matches!(resp, Err(Response::InvalidAddress(_, _))));
+
+        // Modified last character, invalidating the checksum.
+        let resp =
+            Command::from_str("verify 15oF4uVJwmo4TdGW7VfQxNLavjCXviqxT9S1MgbjMNHr6Sp6 email");
+        assert!(matches!(resp, Err(Response::InvalidAddress(_, _))));
+    }
+
+    #[test]
+    fn create_context_detects_chain() {
+        let networks = crate::tests::networks();
+
+        let context = create_context(address(ALICE), &networks).unwrap();
+        assert_eq!(context.chain, ChainName::from("polkadot"));
+
+        let context = create_context(address(ALICE_KUSAMA), &networks).unwrap();
+        assert_eq!(context.chain, ChainName::from("kusama"));
+
+        // Unsupported network.
+        let resp = create_context(
+            address("5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY"),
+            &networks,
+        );
+        assert!(matches!(resp, Err(Response::InvalidAddress(_, _))));
+
+        // The network must be specified if the prefix is shared.
+        let mut networks = networks;
+        networks.push(NetworkConfig {
+            name: ChainName::from("polkadot_people"),
+            ..networks[0].clone()
+        });
+
+        let resp = create_context(address(ALICE), &networks);
+        assert!(matches!(resp, Err(Response::InvalidAddress(_, _))));
+
+        let command = Command::from_str(&format!("status polkadot_people:{}", ALICE)).unwrap();
+        let target = match command {
+            Command::Status(target) => target,
+            _ => panic!(),
+        };
+        assert_eq!(target.chain, Some(ChainName::from("polkadot_people")));
+
+        let context = create_context(target, &networks).unwrap();
+        assert_eq!(context.address, ChainAddress::from(ALICE.to_string()));
+        assert_eq!(context.chain, ChainName::from("polkadot_people"));
+
+        // The address must belong to the specified network.
+        let resp = create_context(
+            Command::from_str(&format!("status kusama:{}", ALICE))
+                .map(|command| match command {
+                    Command::Status(target) => target,
+                    _ => panic!(),
+                })
+                .unwrap(),
+            &networks,
+        );
+        assert!(matches!(resp, Err(Response::InvalidAddress(_, _))));
+    }
+
     #[test]
     fn command_help() {
         let resp = Command::from_str("help").unwrap();
@@ -304,7 +610,7 @@ mod tests {
     #[test]
     #[ignore]
     fn response_status_debug() {
-        let resp = Response::Status(JudgementState::alice().into());
+        let resp = Response::Status(Box::new(JudgementState::alice().into()));
         println!("{}", resp);
     }
 
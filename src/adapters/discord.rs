@@ -0,0 +1,613 @@
+use crate::adapters::admin::RawFieldName;
+use crate::adapters::Adapter;
+use crate::primitives::{ExternalMessage, ExternalMessageType, IdentityFieldValue, Timestamp};
+use crate::{Database, Result};
+use reqwest::header::{self, HeaderMap, HeaderValue};
+use reqwest::Client;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+const DEFAULT_API_BASE: &str = "https://discord.com/api/v10";
+// Maximum amount of messages per request, as specified by Discord.
+const MESSAGE_LIMIT: usize = 100;
+
+pub struct DiscordBuilder {
+    db: Database,
+    token: Option<String>,
+    guild_id: Option<String>,
+    api_base: Option<String>,
+}
+
+impl DiscordBuilder {
+    pub fn new(db: Database) -> Self {
+        DiscordBuilder {
+            db,
+            token: None,
+            guild_id: None,
+            api_base: None,
+        }
+    }
+    pub fn token(mut self, token: String) -> Self {
+        self.token = Some(token);
+        self
+    }
+    pub fn guild_id(mut self, guild_id: String) -> Self {
+        self.guild_id = Some(guild_id);
+        self
+    }
+    /// Defaults to `https://discord.com/api/v10`.
+    pub fn api_base(mut self, api_base: Option<String>) -> Self {
+        self.api_base = api_base;
+        self
+    }
+    pub fn build(self) -> Result<DiscordClient> {
+        let token = self
+            .token
+            .ok_or_else(|| anyhow!("bot token not specified"))?;
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_str(&format!("Bot {}", token))?,
+        );
+
+        Ok(DiscordClient {
+            client: Client::builder().default_headers(headers).build()?,
+            db: self.db,
+            guild_id: self
+                .guild_id
+                .ok_or_else(|| anyhow!("guild id not specified"))?,
+            api_base: self
+                .api_base
+                .unwrap_or_else(|| DEFAULT_API_BASE.to_string())
+                .trim_end_matches('/')
+                .to_string(),
+            user_ids: HashMap::new(),
+            channels: HashMap::new(),
+            last_message: HashMap::new(),
+        })
+    }
+}
+
+/// Receives direct messages sent to the bot account. Discord does not allow
+/// bots to look up users globally, so the users must be members of the
+/// configured guild (e.g. the community server).
+pub struct DiscordClient {
+    client: Client,
+    db: Database,
+    guild_id: String,
+    api_base: String,
+    // Discord handle -> user Id.
+    user_ids: HashMap<String, DiscordId>,
+    // User Id -> DM channel Id.
+    channels: HashMap<DiscordId, DiscordId>,
+    // DM channel Id -> Id of the last processed message. Persisted in the
+    // database, so messages are not processed again after a restart.
+    last_message: HashMap<DiscordId, DiscordId>,
+}
+
+/// Snowflake Id, as used by Discord for users, channels and messages.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct DiscordId(u64);
+
+impl DiscordId {
+    pub fn as_u64(&self) -> u64 {
+        self.0
+    }
+}
+
+impl From<u64> for DiscordId {
+    fn from(val: u64) -> Self {
+        DiscordId(val)
+    }
+}
+
+// Ids are encoded as strings by the API.
+impl<'de> Deserialize<'de> for DiscordId {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map(DiscordId)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+impl Serialize for DiscordId {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ApiUser {
+    id: DiscordId,
+    username: String,
+    // Legacy discriminator, `0` for users with unique usernames.
+    #[serde(default)]
+    discriminator: Option<String>,
+}
+
+impl ApiUser {
+    /// The handle as specified in the identity, e.g. `alice` or `alice#1234`.
+    fn handle(&self) -> String {
+        match self.discriminator.as_deref() {
+            Some(disc) if !disc.is_empty() && disc != "0" => {
+                format!("{}#{}", self.username, disc).to_lowercase()
+            }
+            _ => self.username.to_lowercase(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ApiGuildMember {
+    user: ApiUser,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ApiChannel {
+    id: DiscordId,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ApiMessage {
+    id: DiscordId,
+    author: ApiUser,
+    content: String,
+}
+
+/// The key of the message cursor of the DM channel in the database.
+fn cursor_key(channel_id: DiscordId) -> String {
+    format!("discord:{}", channel_id.as_u64())
+}
+
+/// Converts the handle into the format as used by `ApiUser::handle`.
+pub fn normalize_handle(handle: &str) -> String {
+    handle.trim().trim_start_matches('@').to_lowercase()
+}
+
+impl DiscordClient {
+    async fn get_request<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        params: &[(&str, &str)],
+    ) -> Result<T> {
+        self.client
+            .get(format!("{}{}", self.api_base, path))
+            .query(params)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .map_err(|err| err.into())
+    }
+    async fn post_request<T: DeserializeOwned, B: Serialize>(
+        &self,
+        path: &str,
+        body: &B,
+    ) -> Result<T> {
+        self.client
+            .post(format!("{}{}", self.api_base, path))
+            .json(body)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .map_err(|err| err.into())
+    }
+    async fn lookup_user_id(&mut self, handle: &str) -> Result<Option<DiscordId>> {
+        if let Some(id) = self.user_ids.get(handle) {
+            return Ok(Some(*id));
+        }
+
+        // The search only considers the username, without discriminator.
+        let username = handle.split('#').next().unwrap_or(handle);
+
+        let members: Vec<ApiGuildMember> = self
+            .get_request(
+                &format!("/guilds/{}/members/search", self.guild_id),
+                &[("query", username), ("limit", "100")],
+            )
+            .await?;
+
+        let id = members
+            .into_iter()
+            .find(|member| member.user.handle() == handle)
+            .map(|member| member.user.id);
+
+        if let Some(id) = id {
+            self.user_ids.insert(handle.to_string(), id);
+        }
+
+        Ok(id)
+    }
+    async fn open_dm_channel(&mut self, user_id: DiscordId) -> Result<DiscordId> {
+        if let Some(id) = self.channels.get(&user_id) {
+            return Ok(*id);
+        }
+
+        #[derive(Serialize)]
+        struct CreateDm {
+            recipient_id: DiscordId,
+        }
+
+        // Returns the existing channel, if any.
+        let channel: ApiChannel = self
+            .post_request(
+                "/users/@me/channels",
+                &CreateDm {
+                    recipient_id: user_id,
+                },
+            )
+            .await?;
+
+        self.channels.insert(user_id, channel.id);
+        Ok(channel.id)
+    }
+    async fn last_message(&mut self, channel_id: DiscordId) -> Result<Option<DiscordId>> {
+        if let Some(id) = self.last_message.get(&channel_id) {
+            return Ok(Some(*id));
+        }
+
+        let cursor = self
+            .db
+            .fetch_message_cursor(&cursor_key(channel_id))
+            .await?
+            .map(|cursor| DiscordId(cursor.last_id));
+
+        if let Some(id) = cursor {
+            self.last_message.insert(channel_id, id);
+        }
+
+        Ok(cursor)
+    }
+    async fn fetch_dm_messages(
+        &mut self,
+        user_id: DiscordId,
+        channel_id: DiscordId,
+    ) -> Result<Vec<ApiMessage>> {
+        let limit = MESSAGE_LIMIT.to_string();
+        let after = self
+            .last_message(channel_id)
+            .await?
+            .map(|id| id.as_u64().to_string());
+
+        let mut params = vec![("limit", limit.as_str())];
+        if let Some(after) = &after {
+            params.push(("after", after.as_str()));
+        }
+
+        let mut messages: Vec<ApiMessage> = self
+            .get_request(
+                &format!("/channels/{}/messages", channel_id.as_u64()),
+                &params,
+            )
+            .await?;
+
+        if let Some(last) = messages.iter().map(|message| message.id).max() {
+            self.db
+                .advance_message_cursor(&cursor_key(channel_id), last.as_u64())
+                .await?;
+            self.last_message.insert(channel_id, last);
+        }
+
+        // Ignore messages sent by the bot itself.
+        messages.retain(|message| message.author.id == user_id);
+        messages.sort_by_key(|message| message.id);
+
+        Ok(messages)
+    }
+    async fn request_messages(&mut self) -> Result<Vec<ExternalMessage>> {
+        let fields = self
+            .db
+            .fetch_unverified_fields(&RawFieldName::Discord)
+            .await?;
+
+        let mut messages = vec![];
+        for field in fields {
+            let handle = match field {
+                IdentityFieldValue::Discord(handle) => handle,
+                _ => continue,
+            };
+
+            // Failures are specific to the user, the remaining users are
+            // still processed.
+            let user_id = match self.lookup_user_id(&handle).await {
+                Ok(Some(user_id)) => user_id,
+                Ok(None) => {
+                    debug!("Discord user {} is not a member of the guild", handle);
+                    continue;
+                }
+                Err(err) => {
+                    warn!("Failed to look up Discord user {}: {:?}", handle, err);
+                    continue;
+                }
+            };
+
+            let channel_id = match self.open_dm_channel(user_id).await {
+                Ok(channel_id) => channel_id,
+                Err(err) => {
+                    warn!("Failed to open DM channel with {}: {:?}", handle, err);
+                    continue;
+                }
+            };
+
+            let dms = match self.fetch_dm_messages(user_id, channel_id).await {
+                Ok(dms) => dms,
+                Err(err) => {
+                    warn!("Failed to fetch DMs of {}: {:?}", handle, err);
+                    continue;
+                }
+            };
+
+            for message in dms {
+                messages.push(ExternalMessage {
+                    origin: ExternalMessageType::Discord(handle.clone()),
+                    id: message.id.as_u64().into(),
+                    timestamp: Timestamp::now(),
+                    values: vec![message.content.into()],
+                });
+            }
+        }
+
+        if !messages.is_empty() {
+            debug!("Fetched {} Discord message(-s)", messages.len());
+        }
+
+        Ok(messages)
+    }
+}
+
+#[async_trait]
+impl Adapter for DiscordClient {
+    type MessageType = ();
+
+    fn name(&self) -> &'static str {
+        "discord"
+    }
+    async fn fetch_messages(&mut self) -> Result<Vec<ExternalMessage>> {
+        self.request_messages().await
+    }
+    async fn send_message(&mut self, _to: &str, _content: Self::MessageType) -> Result<()> {
+        // Not required, users message the bot directly.
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use actix_test::TestServer;
+    use actix_web::{web, App, HttpResponse};
+    use std::sync::{Arc, Mutex};
+
+    // Channel Ids are derived from the user Ids.
+    const CHANNEL_OFFSET: u64 = 1_000_000;
+    const BOT_ID: u64 = 1;
+
+    #[derive(Default)]
+    struct MockState {
+        members: Vec<ApiGuildMember>,
+        messages: Vec<(DiscordId, ApiMessage)>,
+        next_id: u64,
+    }
+
+    impl MockState {
+        fn next_id(&mut self) -> DiscordId {
+            self.next_id += 1;
+            DiscordId(CHANNEL_OFFSET * 10 + self.next_id)
+        }
+    }
+
+    /// Stand-in for the Discord API, so the tests run offline.
+    #[derive(Clone, Default)]
+    pub struct DiscordMock {
+        state: Arc<Mutex<MockState>>,
+    }
+
+    #[derive(Deserialize)]
+    struct SearchQuery {
+        query: String,
+    }
+
+    #[derive(Deserialize)]
+    struct MessagesQuery {
+        after: Option<u64>,
+    }
+
+    #[derive(Deserialize)]
+    struct CreateDm {
+        recipient_id: DiscordId,
+    }
+
+    async fn search_members(
+        state: web::Data<Mutex<MockState>>,
+        query: web::Query<SearchQuery>,
+    ) -> HttpResponse {
+        let state = state.lock().unwrap();
+        let members: Vec<&ApiGuildMember> = state
+            .members
+            .iter()
+            .filter(|member| member.user.username.starts_with(&query.query))
+            .collect();
+
+        HttpResponse::Ok().json(members)
+    }
+
+    async fn create_dm(body: web::Json<CreateDm>) -> HttpResponse {
+        HttpResponse::Ok().json(ApiChannel {
+            id: DiscordId(body.recipient_id.as_u64() + CHANNEL_OFFSET),
+        })
+    }
+
+    async fn channel_messages(
+        state: web::Data<Mutex<MockState>>,
+        channel: web::Path<u64>,
+        query: web::Query<MessagesQuery>,
+    ) -> HttpResponse {
+        let state = state.lock().unwrap();
+        let channel = DiscordId(channel.into_inner());
+        let after = query.after.unwrap_or(0);
+
+        // Newest messages first, like the Discord API.
+        let messages: Vec<&ApiMessage> = state
+            .messages
+            .iter()
+            .rev()
+            .filter(|(id, message)| id == &channel && message.id.as_u64() > after)
+            .map(|(_, message)| message)
+            .collect();
+
+        HttpResponse::Ok().json(messages)
+    }
+
+    impl DiscordMock {
+        pub fn new() -> Self {
+            Self::default()
+        }
+        pub fn add_member(&self, username: &str) {
+            let mut state = self.state.lock().unwrap();
+            let id = state.next_id();
+
+            state.members.push(ApiGuildMember {
+                user: ApiUser {
+                    id,
+                    username: username.to_string(),
+                    discriminator: Some("0".to_string()),
+                },
+            });
+        }
+        /// Sends a DM to the bot. If `reply` is set, the message is sent by
+        /// the bot instead.
+        fn push_message(&self, username: &str, content: &str, reply: bool) {
+            let mut state = self.state.lock().unwrap();
+            let user = state
+                .members
+                .iter()
+                .find(|member| member.user.username == username)
+                .map(|member| member.user.clone())
+                .unwrap();
+
+            let id = state.next_id();
+            let channel = DiscordId(user.id.as_u64() + CHANNEL_OFFSET);
+            let author = if reply {
+                ApiUser {
+                    id: DiscordId(BOT_ID),
+                    username: "registrar".to_string(),
+                    discriminator: None,
+                }
+            } else {
+                user
+            };
+
+            state.messages.push((
+                channel,
+                ApiMessage {
+                    id,
+                    author,
+                    content: content.to_string(),
+                },
+            ));
+        }
+        pub fn send_dm(&self, username: &str, content: &str) {
+            self.push_message(username, content, false)
+        }
+        pub fn send_reply(&self, username: &str, content: &str) {
+            self.push_message(username, content, true)
+        }
+        pub fn start(&self) -> TestServer {
+            let state = Arc::clone(&self.state);
+
+            actix_test::start(move || {
+                App::new()
+                    .app_data(web::Data::from(Arc::clone(&state)))
+                    .route(
+                        "/guilds/{guild}/members/search",
+                        web::get().to(search_members),
+                    )
+                    .route("/users/@me/channels", web::post().to(create_dm))
+                    .route(
+                        "/channels/{channel}/messages",
+                        web::get().to(channel_messages),
+                    )
+            })
+        }
+    }
+
+    fn discord_client(db: Database, server: &TestServer) -> DiscordClient {
+        DiscordBuilder::new(db)
+            .token("token".to_string())
+            .guild_id("guild".to_string())
+            .api_base(Some(server.url("")))
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn user_handles() {
+        let user = |username: &str, disc: Option<&str>| ApiUser {
+            id: DiscordId(1),
+            username: username.to_string(),
+            discriminator: disc.map(|d| d.to_string()),
+        };
+
+        assert_eq!(user("Alice", Some("0")).handle(), "alice");
+        assert_eq!(user("Alice", None).handle(), "alice");
+        assert_eq!(user("Alice", Some("1234")).handle(), "alice#1234");
+        assert_eq!(normalize_handle(" @Alice#1234 "), "alice#1234");
+    }
+
+    #[actix::test]
+    async fn fetch_direct_messages() {
+        let mock = DiscordMock::new();
+        mock.add_member("alice");
+        mock.add_member("alice2");
+        let server = mock.start();
+
+        let db = crate::tests::new_db().await;
+        let mut client = discord_client(db.clone(), &server);
+
+        // Unknown user.
+        assert!(client.lookup_user_id("bob").await.unwrap().is_none());
+
+        let user_id = client.lookup_user_id("alice").await.unwrap().unwrap();
+        let channel_id = client.open_dm_channel(user_id).await.unwrap();
+
+        // No messages yet.
+        let messages = client.fetch_dm_messages(user_id, channel_id).await.unwrap();
+        assert!(messages.is_empty());
+
+        mock.send_dm("alice", "first");
+        mock.send_reply("alice", "reply");
+        mock.send_dm("alice", "second");
+        mock.send_dm("alice2", "other");
+
+        // Only messages of the user, oldest first.
+        let messages = client.fetch_dm_messages(user_id, channel_id).await.unwrap();
+        let content: Vec<&str> = messages.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(content, vec!["first", "second"]);
+
+        // Messages are only fetched once.
+        let messages = client.fetch_dm_messages(user_id, channel_id).await.unwrap();
+        assert!(messages.is_empty());
+
+        mock.send_dm("alice", "third");
+
+        let messages = client.fetch_dm_messages(user_id, channel_id).await.unwrap();
+        let content: Vec<&str> = messages.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(content, vec!["third"]);
+
+        // Messages are not fetched again after a restart.
+        let mut client = discord_client(db, &server);
+        let messages = client.fetch_dm_messages(user_id, channel_id).await.unwrap();
+        assert!(messages.is_empty());
+    }
+}
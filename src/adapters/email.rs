@@ -1,14 +1,232 @@
-use std::collections::HashSet;
-
-use crate::adapters::Adapter;
+use crate::adapters::{Adapter, AdapterMessage};
+use crate::api::{EmailLink, DEFAULT_LINK_TTL};
+use crate::database::EmailCursor;
 use crate::primitives::{
-    ExpectedMessage, ExternalMessage, ExternalMessageType, MessageId, Timestamp,
+    AuthenticationCheck, EmailAuthentication, ExpectedMessage, ExternalMessage,
+    ExternalMessageType, FailureReason, IdentityContext, IdentityFieldValue, Timestamp,
 };
-use crate::Result;
+use crate::{Database, EmailTemplateConfig, Result, VerificationLinkConfig};
+use imap::extensions::idle::SetReadTimeout;
+use imap::Session;
 use lettre::smtp::authentication::Credentials;
 use lettre::smtp::SmtpClient;
 use lettre::Transport;
-use lettre_email::EmailBuilder;
+use lettre_email::{Email, EmailBuilder};
+use mailparse::{MailHeader, MailHeaderMap};
+use native_tls::TlsStream;
+use std::convert::TryFrom;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const DEFAULT_IMAP_PORT: u16 = 993;
+// Re-issue the IDLE command (and check for new messages) at least every five
+// minutes, in case notifications got lost.
+const IDLE_TIMEOUT: u64 = 300;
+const IDLE_RECONNECT_DELAY: u64 = 10;
+const DEFAULT_SUBJECT: &str = "W3F Registrar Verification Service";
+const DEFAULT_UI_URL: &str = "https://registrar.web3.foundation/";
+const DEFAULT_TEXT_TEMPLATE: &str = include_str!("../../config/templates/second_challenge.txt");
+const DEFAULT_HTML_TEMPLATE: &str = include_str!("../../config/templates/second_challenge.html");
+
+// The `UIDVALIDITY` of the mailbox and the fetched messages, ordered by UID.
+type FetchedMessages = (u32, Vec<FetchedMessage>);
+
+struct FetchedMessage {
+    uid: u32,
+    // Not available if the message could not be parsed.
+    message: Option<ExternalMessage>,
+    // Only available if the authentication checks are enabled.
+    authentication: Option<EmailAuthentication>,
+    // Automatic replies, such as out-of-office notices, as indicated by the
+    // `Auto-Submitted` header (RFC 3834).
+    auto_submitted: bool,
+}
+
+/// The IMAP search query used to find new messages.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchStrategy {
+    /// Standard `SINCE` query, searches for the messages of the last day.
+    Since,
+    /// Standard `UNSEEN` query, searches for messages without the `\Seen`
+    /// flag. Fetched messages are flagged as seen by the server.
+    Unseen,
+    /// Gmail specific `X-GM-RAW` query, searches for the messages of the last
+    /// day.
+    Gmail,
+}
+
+impl Default for SearchStrategy {
+    fn default() -> Self {
+        SearchStrategy::Since
+    }
+}
+
+impl SearchStrategy {
+    fn query(&self) -> String {
+        match self {
+            // The date has no time and timezone, so include the previous day.
+            SearchStrategy::Since => format!(
+                "SINCE {}",
+                (chrono::Utc::now() - chrono::Duration::days(1)).format("%d-%b-%Y")
+            ),
+            SearchStrategy::Unseen => "UNSEEN".to_string(),
+            SearchStrategy::Gmail => "X-GM-RAW \"newer_than:1d\"".to_string(),
+        }
+    }
+}
+
+/// How messages are handled which fail the DKIM, SPF and DMARC checks. The
+/// results are recorded on the field either way.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthenticationPolicy {
+    /// The message is dropped and does not count as a verification attempt.
+    Reject,
+    /// The message is processed as usual.
+    Flag,
+    /// The message is processed, but the second challenge, which is sent to
+    /// the inbox of the user, must be entered manually. Verification links
+    /// are not included and refused for the field.
+    RequireSecondChallenge,
+}
+
+/// Evaluates the `Authentication-Results` header (RFC 8601) added by the
+/// receiving mail server, identified by `authserv_id`. Headers of other
+/// servers are ignored, since those can be forged by the sender. The message
+/// passes if DMARC passes or, if DMARC was not evaluated, if DKIM or SPF
+/// passes for a domain aligned with the `From` domain.
+fn authenticate(headers: &[MailHeader], authserv_id: &str, sender: &str) -> EmailAuthentication {
+    let from_domain = domain_of(sender);
+
+    let mut dkim = vec![];
+    let mut spf = AuthenticationCheck::none();
+    let mut dmarc = AuthenticationCheck::none();
+
+    // The receiving server prepends its header, so only the first one is
+    // trusted. Any further header with the same `authserv-id` was added by
+    // the sender.
+    let results = headers
+        .get_all_values("Authentication-Results")
+        .into_iter()
+        .map(|value| strip_comments(&value))
+        .find(|value| {
+            value
+                .split(';')
+                .next()
+                .and_then(|id| id.split_whitespace().next())
+                .map(|id| id.eq_ignore_ascii_case(authserv_id))
+                .unwrap_or(false)
+        });
+
+    for result in results.iter().flat_map(|value| value.split(';').skip(1)) {
+        let mut tokens = result.split_whitespace();
+        let (method, outcome) = match tokens.next().and_then(|t| t.split_once('=')) {
+            Some((method, outcome)) => (
+                // Strip the optional method version, e.g. `dkim/1`.
+                method.split('/').next().unwrap_or_default().to_lowercase(),
+                outcome.to_lowercase(),
+            ),
+            None => continue,
+        };
+
+        let property = |name: &str| {
+            result
+                .split_whitespace()
+                .skip(1)
+                .filter_map(|t| t.split_once('='))
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| domain_of(value.trim_matches('"')))
+        };
+
+        let domain = match method.as_str() {
+            "dkim" => property("header.d").or_else(|| property("header.i")),
+            "spf" => property("smtp.mailfrom").or_else(|| property("smtp.helo")),
+            "dmarc" => property("header.from"),
+            _ => continue,
+        };
+
+        let check = AuthenticationCheck {
+            result: outcome,
+            aligned: domain
+                .as_ref()
+                .map(|domain| is_aligned(domain, &from_domain))
+                .unwrap_or(false),
+            domain,
+        };
+
+        match method.as_str() {
+            "dkim" => dkim.push(check),
+            "spf" => spf = check,
+            _ => dmarc = check,
+        }
+    }
+
+    // A message can have multiple DKIM signatures, only one must be valid.
+    let dkim = dkim
+        .iter()
+        .find(|check| check.is_aligned_pass())
+        .or_else(|| dkim.iter().find(|check| check.result == "pass"))
+        .or_else(|| dkim.first())
+        .cloned()
+        .unwrap_or_else(AuthenticationCheck::none);
+
+    let passed = if dmarc.result != "none" {
+        dmarc.is_aligned_pass()
+    } else {
+        dkim.is_aligned_pass() || spf.is_aligned_pass()
+    };
+
+    EmailAuthentication {
+        dkim,
+        spf,
+        dmarc,
+        passed,
+        timestamp: Timestamp::now(),
+    }
+}
+
+/// Returns the (lowercase) domain of an email address or of a domain.
+fn domain_of(value: &str) -> String {
+    value
+        .rsplit('@')
+        .next()
+        .unwrap_or_default()
+        .trim_end_matches('.')
+        .to_lowercase()
+}
+
+/// Relaxed alignment, the domains must be equal or one must be a subdomain of
+/// the other.
+fn is_aligned(domain: &str, from_domain: &str) -> bool {
+    // Top-level domains are never aligned.
+    domain.contains('.')
+        && (domain == from_domain
+            || domain.ends_with(&format!(".{}", from_domain))
+            || from_domain.ends_with(&format!(".{}", domain)))
+}
+
+/// Removes comments, e.g. `(p=NONE sp=NONE)`, which can be nested.
+fn strip_comments(value: &str) -> String {
+    let mut depth = 0;
+    value
+        .chars()
+        .filter(|c| match c {
+            '(' => {
+                depth += 1;
+                false
+            }
+            ')' => {
+                depth = std::cmp::max(depth - 1, 0);
+                false
+            }
+            _ => depth == 0,
+        })
+        .collect()
+}
 
 trait ExtractSender<T> {
     type Error;
@@ -33,19 +251,179 @@ impl ExtractSender<String> for String {
     }
 }
 
+/// Messages sent to the email address of the identity.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum EmailMessage {
+    Challenge(ChallengeEmail),
+    // The reason why a challenge was rejected.
+    Failure(FailureReason),
+}
+
+/// The second challenge, sent to the email address of the identity.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ChallengeEmail {
+    pub context: IdentityContext,
+    pub challenge: ExpectedMessage,
+    // No verification link is included if set, see `IdentityField::untrusted`.
+    pub untrusted: bool,
+}
+
+/// Subject, plain text and HTML templates of the second challenge email.
+/// Supported placeholders are `{subject}`, `{challenge}`, `{chain}`,
+/// `{address}`, `{ui_link}` and `{verification_link}`. Lines containing
+/// `{verification_link}` are removed if verification links are disabled.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct EmailTemplate {
+    subject: String,
+    text: String,
+    html: String,
+    ui_url: String,
+}
+
+impl Default for EmailTemplate {
+    fn default() -> Self {
+        EmailTemplate {
+            subject: DEFAULT_SUBJECT.to_string(),
+            text: DEFAULT_TEXT_TEMPLATE.to_string(),
+            html: DEFAULT_HTML_TEMPLATE.to_string(),
+            ui_url: DEFAULT_UI_URL.to_string(),
+        }
+    }
+}
+
+impl EmailTemplate {
+    /// Loads the templates from the configured files. Unspecified values fall
+    /// back to the defaults.
+    pub fn load(config: EmailTemplateConfig) -> Result<Self> {
+        let read = |path: Option<String>, default: &str| match path {
+            Some(path) => std::fs::read_to_string(&path)
+                .map_err(|err| anyhow!("failed to read email template {}: {:?}", path, err)),
+            None => Ok(default.to_string()),
+        };
+
+        Ok(EmailTemplate {
+            subject: config
+                .subject
+                .unwrap_or_else(|| DEFAULT_SUBJECT.to_string()),
+            text: read(config.text, DEFAULT_TEXT_TEMPLATE)?,
+            html: read(config.html, DEFAULT_HTML_TEMPLATE)?,
+            ui_url: config.ui_url.unwrap_or_else(|| DEFAULT_UI_URL.to_string()),
+        })
+    }
+    /// Returns the subject, the plain text and the HTML body.
+    fn render(
+        &self,
+        email: &ChallengeEmail,
+        verification_link: Option<&str>,
+    ) -> (String, String, String) {
+        let context = &email.context;
+        let ui_link = format!(
+            "{}?network={}&address={}",
+            self.ui_url,
+            context.chain.as_str(),
+            context.address.as_str()
+        );
+
+        let values = [
+            ("subject", self.subject.as_str()),
+            ("challenge", email.challenge.value.as_str()),
+            ("chain", context.chain.as_str()),
+            ("address", context.address.as_str()),
+            ("ui_link", ui_link.as_str()),
+            ("verification_link", verification_link.unwrap_or_default()),
+        ];
+
+        let render = |template: &str, escape: bool| {
+            template
+                .lines()
+                .filter(|line| verification_link.is_some() || !line.contains("{verification_link}"))
+                .map(|line| replace_placeholders(line, &values, escape))
+                .collect::<Vec<String>>()
+                .join("\n")
+        };
+
+        (
+            self.subject.clone(),
+            render(&self.text, false),
+            render(&self.html, true),
+        )
+    }
+}
+
+/// Replaces the placeholders in a single pass, so values are never
+/// interpreted as placeholders themselves. Unknown placeholders are kept.
+fn replace_placeholders(line: &str, values: &[(&str, &str)], escape: bool) -> String {
+    let mut rendered = String::new();
+    let mut rest = line;
+
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let value = rest.find('}').and_then(|end| {
+            values
+                .iter()
+                .find(|(key, _)| *key == &rest[1..end])
+                .map(|(_, value)| (end, value))
+        });
+
+        match value {
+            Some((end, value)) => {
+                if escape {
+                    rendered.push_str(&escape_html(value));
+                } else {
+                    rendered.push_str(value);
+                }
+
+                rest = &rest[end + 1..];
+            }
+            None => {
+                rendered.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    rendered.push_str(rest);
+    rendered
+}
+
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
 pub struct EmailClientBuilder {
+    db: Database,
     server: Option<String>,
     imap_server: Option<String>,
+    imap_port: Option<u16>,
+    search_strategy: Option<SearchStrategy>,
+    authentication_policy: Option<AuthenticationPolicy>,
+    authserv_id: Option<String>,
+    verification_link: Option<VerificationLinkConfig>,
+    template: Option<EmailTemplateConfig>,
     inbox: Option<String>,
     user: Option<String>,
     password: Option<String>,
 }
 
 impl EmailClientBuilder {
-    pub fn new() -> Self {
+    pub fn new(db: Database) -> Self {
         EmailClientBuilder {
+            db,
             server: None,
             imap_server: None,
+            imap_port: None,
+            search_strategy: None,
+            authentication_policy: None,
+            authserv_id: None,
+            verification_link: None,
+            template: None,
             inbox: None,
             user: None,
             password: None,
@@ -59,6 +437,38 @@ impl EmailClientBuilder {
         self.imap_server = Some(imap_server);
         self
     }
+    /// Defaults to `993`.
+    pub fn imap_port(mut self, port: Option<u16>) -> Self {
+        self.imap_port = port;
+        self
+    }
+    /// Defaults to `SearchStrategy::Since`.
+    pub fn search_strategy(mut self, strategy: Option<SearchStrategy>) -> Self {
+        self.search_strategy = strategy;
+        self
+    }
+    /// The authentication checks are disabled if not specified.
+    pub fn authentication_policy(mut self, policy: Option<AuthenticationPolicy>) -> Self {
+        self.authentication_policy = policy;
+        self
+    }
+    /// The `authserv-id` of the receiving mail server, which adds the
+    /// `Authentication-Results` header. Required for the authentication checks.
+    pub fn authserv_id(mut self, authserv_id: Option<String>) -> Self {
+        self.authserv_id = authserv_id;
+        self
+    }
+    /// Includes a verification link in the second challenge email if
+    /// specified.
+    pub fn verification_link(mut self, config: Option<VerificationLinkConfig>) -> Self {
+        self.verification_link = config;
+        self
+    }
+    /// Uses the default templates if not specified.
+    pub fn template(mut self, config: Option<EmailTemplateConfig>) -> Self {
+        self.template = config;
+        self
+    }
     pub fn email_inbox(mut self, inbox: String) -> Self {
         self.inbox = Some(inbox);
         self
@@ -73,128 +483,504 @@ impl EmailClientBuilder {
     }
     #[allow(clippy::or_fun_call)]
     pub fn build(self) -> Result<EmailClient> {
+        let authserv_id = match self.authentication_policy {
+            Some(_) => Some(self.authserv_id.ok_or(anyhow!(
+                "authserv-id not specified for authentication checks"
+            ))?),
+            None => None,
+        };
+
         Ok(EmailClient {
+            db: self.db,
             smtp_server: self.server.ok_or(anyhow!("SMTP server not specified"))?,
             imap_server: self
                 .imap_server
                 .ok_or(anyhow!("IMAP server not specified"))?,
+            imap_port: self.imap_port.unwrap_or(DEFAULT_IMAP_PORT),
+            search_strategy: self.search_strategy.unwrap_or_default(),
+            authentication_policy: self.authentication_policy,
+            authserv_id,
+            verification_link: self.verification_link,
+            template: match self.template {
+                Some(config) => EmailTemplate::load(config)?,
+                None => EmailTemplate::default(),
+            },
             inbox: self.inbox.ok_or(anyhow!("inbox server not specified"))?,
             user: self.user.ok_or(anyhow!("user server not specified"))?,
             password: self
                 .password
                 .ok_or(anyhow!("password server not specified"))?,
-            cache: HashSet::new(),
+            idle_queue: None,
         })
     }
 }
 
 #[derive(Clone)]
 pub struct EmailClient {
+    db: Database,
     smtp_server: String,
     imap_server: String,
+    imap_port: u16,
+    search_strategy: SearchStrategy,
+    authentication_policy: Option<AuthenticationPolicy>,
+    // Only set if the authentication checks are enabled.
+    authserv_id: Option<String>,
+    verification_link: Option<VerificationLinkConfig>,
+    template: EmailTemplate,
     inbox: String,
     user: String,
     password: String,
-    // Keep track of messages.
-    cache: HashSet<MessageId>,
+    // Messages pushed by the IDLE session, if active.
+    idle_queue: Option<Arc<Mutex<Vec<FetchedMessages>>>>,
 }
 
 impl EmailClient {
-    fn request_messages(&mut self) -> Result<Vec<ExternalMessage>> {
+    /// Identifies the mailbox in the database, which keeps track of the
+    /// processed messages.
+    fn mailbox_id(&self) -> String {
+        format!("{}@{}/{}", self.user, self.imap_server, self.inbox)
+    }
+    fn connect(&self) -> Result<Session<TlsStream<TcpStream>>> {
         let tls = native_tls::TlsConnector::builder().build()?;
-        let client = imap::connect((self.imap_server.as_str(), 993), &self.imap_server, &tls)?;
+        let client = imap::connect(
+            (self.imap_server.as_str(), self.imap_port),
+            &self.imap_server,
+            &tls,
+        )?;
 
-        let mut imap = client
+        client
             .login(&self.user, &self.password)
-            .map_err(|(err, _)| err)?;
+            .map_err(|(err, _)| err.into())
+    }
+    async fn request_messages(&mut self) -> Result<Vec<ExternalMessage>> {
+        // Messages are pushed by the IDLE session, if active.
+        if let Some(queue) = &self.idle_queue {
+            let mut fetched = std::mem::take(&mut *queue.lock().unwrap());
 
-        imap.select(&self.inbox)?;
+            let mut messages = vec![];
+            let mut result = Ok(());
+            let mut processed = 0;
+            for (uid_validity, batch) in &mut fetched {
+                result = self
+                    .claim_messages(*uid_validity, batch, &mut messages)
+                    .await;
 
-        // Fetch the messages of the last day. The database keeps track of which messages
-        // have been processed.
-        //
-        // Gmail has a custom search syntax and does not support the IMAP
-        // standardized queries.
-        let recent_seq = imap.search("X-GM-RAW \"newer_than:1d\"")?;
+                if result.is_err() {
+                    break;
+                }
+
+                processed += 1;
+            }
+
+            if let Err(err) = result {
+                // The IDLE session does not fetch these messages again, so
+                // the unprocessed ones are queued for the next attempt.
+                queue
+                    .lock()
+                    .unwrap()
+                    .splice(0..0, fetched.into_iter().skip(processed));
+
+                if messages.is_empty() {
+                    return Err(err);
+                }
 
-        if recent_seq.is_empty() {
-            return Ok(vec![]);
+                error!(
+                    "Failed to claim messages, retrying on the next poll: {:?}",
+                    err
+                );
+            }
+
+            return Ok(messages);
         }
 
-        // Unwrap is fine since length of sequence is checked above.
-        let min = recent_seq.iter().min().unwrap();
-        let max = recent_seq.iter().max().unwrap();
+        let cursor = self.db.fetch_email_cursor(&self.mailbox_id()).await?;
 
-        let query = if min == max {
-            min.to_string()
-        } else {
-            format!("{}:{}", min, max)
-        };
+        let mut imap = self.connect()?;
+        let fetched = self.fetch_from_session(&mut imap, cursor.as_ref());
+        let _ = imap.logout();
+        let (uid_validity, mut fetched) = fetched?;
 
-        let messages = imap.fetch(query, "(RFC822 UID)")?;
-        let mut parsed_messages = vec![];
-        for message in &messages {
-            if let Some(body) = message.body() {
-                let mail = mailparse::parse_mail(body)?;
-
-                let sender = mail
-                    .headers
-                    .iter()
-                    .find(|header| header.get_key_ref() == "From")
-                    .ok_or_else(|| anyhow!("unrecognized data"))?
-                    .get_value()
-                    .extract_sender()?;
-
-                let id = message
-                    .uid
-                    .ok_or_else(|| anyhow!("missing UID for email message"))?
-                    .into();
-
-                // Skip message if it was already processed.
-                if self.cache.contains(&id) {
+        let mut messages = vec![];
+        self.claim_messages(uid_validity, &mut fetched, &mut messages)
+            .await?;
+
+        Ok(messages)
+    }
+    /// Adds the messages which were not processed yet, including by other
+    /// instances sharing the same mailbox, to `claimed`. Processed messages
+    /// are removed from `messages`, so only the remaining ones are left on
+    /// failure.
+    async fn claim_messages(
+        &self,
+        uid_validity: u32,
+        messages: &mut Vec<FetchedMessage>,
+        claimed: &mut Vec<ExternalMessage>,
+    ) -> Result<()> {
+        let mailbox = self.mailbox_id();
+        let cursor = self.db.fetch_email_cursor(&mailbox).await?;
+
+        // Previous UIDs are no longer valid if the `UIDVALIDITY` of the
+        // mailbox has changed.
+        if cursor.as_ref().map(|c| c.uid_validity) != Some(uid_validity) {
+            debug!("Resetting email cursor of {}", mailbox);
+            self.db
+                .reset_email_cursor(&mailbox, cursor.as_ref(), uid_validity)
+                .await?;
+        }
+
+        while let Some(uid) = messages.first().map(|fetched| fetched.uid) {
+            let is_new = self.db.claim_email_uid(&mailbox, uid_validity, uid).await?;
+
+            let fetched = messages.remove(0);
+            if !is_new {
+                continue;
+            }
+
+            let message = match fetched.message {
+                Some(message) => message,
+                None => continue,
+            };
+
+            // Never process (and reply to) automatic replies, which could
+            // otherwise result in mail loops.
+            if fetched.auto_submitted {
+                debug!("Skipping automatic reply from {:?}", message.origin);
+                continue;
+            }
+
+            if let Some(authentication) = fetched.authentication {
+                if !self
+                    .apply_authentication_policy(&message, authentication)
+                    .await?
+                {
                     continue;
                 }
+            }
 
-                debug!("Received message from {}", sender);
-
-                // Prepare parsed message
-                let mut parsed_message = ExternalMessage {
-                    origin: ExternalMessageType::Email(sender),
-                    id: message
-                        .uid
-                        .ok_or_else(|| anyhow!("missing UID for email message"))?
-                        .into(),
-                    timestamp: Timestamp::now(),
-                    values: vec![],
-                };
+            claimed.push(message);
+        }
 
-                // Add body content.
-                if let Ok(body) = mail.get_body() {
-                    parsed_message.values.push(body.into());
-                } else {
-                    warn!("No body found in message");
+        Ok(())
+    }
+    /// Records the authentication results on the corresponding fields and
+    /// returns whether the message should be processed.
+    pub async fn apply_authentication_policy(
+        &self,
+        message: &ExternalMessage,
+        authentication: EmailAuthentication,
+    ) -> Result<bool> {
+        let passed = authentication.passed;
+        self.db
+            .set_email_authentication(&message.origin, &authentication)
+            .await?;
+
+        if passed {
+            return Ok(true);
+        }
+
+        match self.authentication_policy {
+            Some(AuthenticationPolicy::Reject) => {
+                warn!(
+                    "Rejecting message from {:?}, failed authentication checks: {:?}",
+                    message.origin, authentication
+                );
+
+                Ok(false)
+            }
+            Some(AuthenticationPolicy::RequireSecondChallenge) => {
+                debug!(
+                    "Requiring second challenge for {:?}, failed authentication checks",
+                    message.origin
+                );
+
+                self.db.require_second_challenge(&message.origin).await?;
+                Ok(true)
+            }
+            Some(AuthenticationPolicy::Flag) | None => Ok(true),
+        }
+    }
+    /// Completes the message with the state of the recipient. Returns `false`
+    /// if the message must not be sent.
+    async fn prepare_message(&self, to: &str, content: &mut EmailMessage) -> Result<bool> {
+        let field = IdentityFieldValue::Email(to.to_string());
+
+        match content {
+            EmailMessage::Challenge(email) => {
+                email.untrusted = self
+                    .db
+                    .fetch_judgement_state(&email.context)
+                    .await?
+                    .map(|state| state.fields.iter().any(|f| f.value == field && f.untrusted))
+                    .unwrap_or(false);
+
+                Ok(true)
+            }
+            EmailMessage::Failure(_) => {
+                // The sender of the failed challenge might be forged, in which
+                // case the reply would go to an uninvolved third party
+                // (backscatter).
+                let passed = self.db.email_authentication_passed(&field).await?;
+                if !passed {
+                    debug!(
+                        "Not replying to {}, the message did not pass the authentication checks",
+                        to
+                    );
                 }
 
-                // An email message can contain multiple "subparts". Add each of
-                // those into the prepared message.
-                for subpart in mail.subparts {
-                    if let Ok(body) = subpart.get_body() {
-                        parsed_message.values.push(body.into());
-                    } else {
-                        debug!("No body found in subpart message");
-                    }
+                Ok(passed)
+            }
+        }
+    }
+    /// Keeps a session open which waits for new messages via IMAP IDLE, so
+    /// messages no longer have to be polled. Returns `false` if the server
+    /// does not support IDLE.
+    pub async fn start_idle(&mut self) -> Result<bool> {
+        let mut imap = self.connect()?;
+        if !imap.capabilities()?.has_str("IDLE") {
+            let _ = imap.logout();
+            return Ok(false);
+        }
+
+        let cursor = self.db.fetch_email_cursor(&self.mailbox_id()).await?;
+        self.spawn_idle(imap, cursor);
+
+        Ok(true)
+    }
+    fn spawn_idle<T>(&mut self, mut imap: Session<T>, mut cursor: Option<EmailCursor>)
+    where
+        T: 'static + Read + Write + SetReadTimeout + Send,
+    {
+        let queue = Arc::new(Mutex::new(vec![]));
+        self.idle_queue = Some(Arc::clone(&queue));
+
+        // The IMAP client is blocking, so the session runs in its own thread.
+        let client = self.clone();
+        thread::spawn(move || {
+            let mut result = client.run_idle(&mut imap, &mut cursor, &queue);
+
+            // Reconnect on failure, the IDLE capability was already checked
+            // on startup.
+            loop {
+                if let Err(err) = result {
+                    error!(
+                        "IDLE session failed, reconnecting in {}s: {:?}",
+                        IDLE_RECONNECT_DELAY, err
+                    );
                 }
 
-                self.cache.insert(parsed_message.id);
-                parsed_messages.push(parsed_message);
+                thread::sleep(Duration::from_secs(IDLE_RECONNECT_DELAY));
+                result = client
+                    .connect()
+                    .and_then(|mut imap| client.run_idle(&mut imap, &mut cursor, &queue));
+            }
+        });
+    }
+    /// Only returns on failure.
+    fn run_idle<T: Read + Write + SetReadTimeout>(
+        &self,
+        imap: &mut Session<T>,
+        cursor: &mut Option<EmailCursor>,
+        queue: &Mutex<Vec<FetchedMessages>>,
+    ) -> Result<()> {
+        loop {
+            let (uid_validity, messages) = self.fetch_from_session(imap, cursor.as_ref())?;
+
+            // Keep track of the last fetched message. The cursor in the
+            // database is updated once the messages are claimed.
+            let last_uid = messages.last().map(|fetched| fetched.uid).or_else(|| {
+                cursor
+                    .as_ref()
+                    .filter(|cursor| cursor.uid_validity == uid_validity)
+                    .map(|cursor| cursor.last_uid)
+            });
+
+            *cursor = Some(EmailCursor {
+                uid_validity,
+                last_uid: last_uid.unwrap_or(0),
+            });
+
+            if !messages.is_empty() {
+                debug!("Received {} message(-s) via IDLE session", messages.len());
+                queue.lock().unwrap().push((uid_validity, messages));
+            }
+
+            // Wait until the mailbox changes.
+            imap.idle()?
+                .wait_with_timeout(Duration::from_secs(IDLE_TIMEOUT))?;
+        }
+    }
+    /// Fetches the messages after the cursor or, if the cursor is not
+    /// available, the messages matching the search strategy. Returns the
+    /// `UIDVALIDITY` of the mailbox and the messages, ordered by UID.
+    fn fetch_from_session<T: Read + Write>(
+        &self,
+        imap: &mut Session<T>,
+        cursor: Option<&EmailCursor>,
+    ) -> Result<FetchedMessages> {
+        let uid_validity = imap
+            .select(&self.inbox)?
+            .uid_validity
+            .ok_or_else(|| anyhow!("missing UIDVALIDITY for mailbox {}", self.inbox))?;
+
+        // A reset cursor does not point to any message yet, in which case the
+        // search strategy limits the messages to the recent ones.
+        let last_uid = cursor
+            .filter(|cursor| cursor.uid_validity == uid_validity && cursor.last_uid > 0)
+            .map(|cursor| cursor.last_uid);
+
+        let query = match last_uid {
+            Some(last_uid) => format!("UID {}:*", last_uid + 1),
+            None => self.search_strategy.query(),
+        };
+
+        // The range `<UID>:*` always contains the latest message, even if its
+        // UID is lower than the specified one.
+        let mut uids: Vec<u32> = imap
+            .uid_search(query)?
+            .into_iter()
+            .filter(|uid| Some(*uid) > last_uid)
+            .collect();
+
+        if uids.is_empty() {
+            return Ok((uid_validity, vec![]));
+        }
+
+        uids.sort_unstable();
+        let query = uids
+            .iter()
+            .map(|uid| uid.to_string())
+            .collect::<Vec<String>>()
+            .join(",");
+
+        let messages = imap.uid_fetch(query, "RFC822")?;
+        let mut parsed_messages = vec![];
+        for message in &messages {
+            let uid = message
+                .uid
+                .ok_or_else(|| anyhow!("missing UID for email message"))?;
+
+            let parsed = message
+                .body()
+                .ok_or_else(|| anyhow!("no body found for message"))
+                .and_then(|body| self.parse_message(uid, body));
+
+            match parsed {
+                Ok(fetched) => parsed_messages.push(fetched),
+                Err(err) => {
+                    // The message is still returned, so the cursor moves past
+                    // it and it is not fetched again on every poll.
+                    warn!("Skipping unprocessable message with UID {}: {:?}", uid, err);
+                    parsed_messages.push(FetchedMessage {
+                        uid,
+                        message: None,
+                        authentication: None,
+                        auto_submitted: false,
+                    });
+                }
+            }
+        }
+
+        parsed_messages.sort_by_key(|fetched| fetched.uid);
+
+        Ok((uid_validity, parsed_messages))
+    }
+    fn parse_message(&self, uid: u32, body: &[u8]) -> Result<FetchedMessage> {
+        let mail = mailparse::parse_mail(body)?;
+
+        let sender = mail
+            .headers
+            .iter()
+            .find(|header| header.get_key_ref() == "From")
+            .ok_or_else(|| anyhow!("unrecognized data"))?
+            .get_value()
+            .extract_sender()?;
+
+        let authentication = self
+            .authserv_id
+            .as_ref()
+            .map(|authserv_id| authenticate(&mail.headers, authserv_id, &sender));
+
+        let auto_submitted = mail
+            .headers
+            .get_first_value("Auto-Submitted")
+            .map(|value| !value.trim().eq_ignore_ascii_case("no"))
+            .unwrap_or(false);
+
+        debug!("Received message from {}", sender);
+
+        // Prepare parsed message
+        let mut parsed_message = ExternalMessage {
+            origin: ExternalMessageType::Email(sender),
+            id: uid.into(),
+            timestamp: Timestamp::now(),
+            values: vec![],
+        };
+
+        // Add body content.
+        if let Ok(body) = mail.get_body() {
+            parsed_message.values.push(body.into());
+        } else {
+            warn!("No body found in message");
+        }
+
+        // An email message can contain multiple "subparts". Add each of
+        // those into the prepared message.
+        for subpart in mail.subparts {
+            if let Ok(body) = subpart.get_body() {
+                parsed_message.values.push(body.into());
             } else {
-                warn!("No body found for message");
+                debug!("No body found in subpart message");
             }
         }
 
-        Ok(parsed_messages)
+        Ok(FetchedMessage {
+            uid,
+            message: Some(parsed_message),
+            authentication,
+            auto_submitted,
+        })
     }
-    async fn send_message(&self, to: &str, message: &str) -> Result<()> {
+    /// Returns the subject, the plain text and the HTML body.
+    fn render_message(&self, to: &str, email: &ChallengeEmail) -> Result<(String, String, String)> {
+        let link = match &self.verification_link {
+            Some(config) if !email.untrusted => Some(
+                EmailLink::new(
+                    &config.secret,
+                    to,
+                    &email.challenge.value,
+                    config.ttl.unwrap_or(DEFAULT_LINK_TTL),
+                )
+                .to_url(&config.api_base)?,
+            ),
+            _ => None,
+        };
+
+        Ok(self.template.render(email, link.as_deref()))
+    }
+    fn build_email(&self, to: &str, message: &EmailMessage) -> Result<Email> {
+        let builder = EmailBuilder::new()
+            // Addresses can be specified by the tuple (email, alias)
+            .to(to)
+            .from(self.user.as_str());
+
+        // Prevents automatic replies to these emails (RFC 3834).
+        let builder = match message {
+            EmailMessage::Challenge(email) => {
+                let (subject, text, html) = self.render_message(to, email)?;
+                builder
+                    .header(("Auto-Submitted", "auto-generated"))
+                    .subject(subject)
+                    .alternative(html, text)
+            }
+            EmailMessage::Failure(reason) => builder
+                .header(("Auto-Submitted", "auto-replied"))
+                .subject(self.template.subject.as_str())
+                .text(reason.description()),
+        };
+
+        Ok(builder.build()?)
+    }
+    async fn send_message(&self, to: &str, message: &EmailMessage) -> Result<()> {
         // SMTP transport
         let mut smtp = SmtpClient::new_simple(&self.smtp_server)?
             .credentials(Credentials::new(
@@ -203,17 +989,7 @@ impl EmailClient {
             ))
             .transport();
 
-        let email = EmailBuilder::new()
-            // Addresses can be specified by the tuple (email, alias)
-            .to(to)
-            .from(self.user.as_str())
-            .subject("W3F Registrar Verification Service")
-            .text(format!(
-                "Insert the following challenge into the web interface: {}",
-                message
-            ))
-            .build()?;
-
+        let email = self.build_email(to, message)?;
         let _ = smtp.send(email.into())?;
 
         Ok(())
@@ -222,15 +998,807 @@ impl EmailClient {
 
 #[async_trait]
 impl Adapter for EmailClient {
-    type MessageType = ExpectedMessage;
+    type MessageType = EmailMessage;
 
     fn name(&self) -> &'static str {
         "email"
     }
     async fn fetch_messages(&mut self) -> Result<Vec<ExternalMessage>> {
-        self.request_messages()
+        self.request_messages().await
+    }
+    async fn send_message(&mut self, to: &str, mut content: Self::MessageType) -> Result<()> {
+        if !self.prepare_message(to, &mut content).await? {
+            return Ok(());
+        }
+
+        Self::send_message(self, to, &content).await
+    }
+    fn recipient(&self, field: &IdentityFieldValue) -> Option<String> {
+        match field {
+            IdentityFieldValue::Email(to) => Some(to.clone()),
+            _ => None,
+        }
+    }
+}
+
+// The second challenge and the reasons for failed challenges are sent via
+// email.
+impl TryFrom<AdapterMessage> for EmailMessage {
+    type Error = AdapterMessage;
+
+    fn try_from(msg: AdapterMessage) -> std::result::Result<Self, Self::Error> {
+        match msg {
+            AdapterMessage::SecondChallenge(context, challenge) => {
+                Ok(EmailMessage::Challenge(ChallengeEmail {
+                    context,
+                    challenge,
+                    // Determined when sending.
+                    untrusted: false,
+                }))
+            }
+            AdapterMessage::FieldVerificationFailed(_, reason) => Ok(EmailMessage::Failure(reason)),
+            msg => Err(msg),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::challenge::ChallengeFormat;
+    use crate::primitives::JudgementState;
+    use std::io::{BufRead, BufReader};
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    // UIDs are offset, so these do not match the sequence numbers.
+    const UID_OFFSET: u32 = 10;
+
+    struct MockState {
+        uid_validity: u32,
+        // Raw message and whether it was seen, the UID is derived from the
+        // position.
+        messages: Vec<(String, bool)>,
+        // Received search queries.
+        searches: Vec<String>,
+    }
+
+    /// Minimal, plaintext IMAP server, so the tests run offline.
+    #[derive(Clone)]
+    pub struct ImapMock {
+        state: Arc<Mutex<MockState>>,
+    }
+
+    fn parse_uid_set(set: &str) -> Vec<u32> {
+        set.split(',')
+            .flat_map(|range| {
+                let mut parts = range.split(':').map(|n| n.parse::<u32>().unwrap());
+                let start = parts.next().unwrap();
+                let end = parts.next().unwrap_or(start);
+                start..=end
+            })
+            .collect()
+    }
+
+    impl ImapMock {
+        pub fn new() -> Self {
+            ImapMock {
+                state: Arc::new(Mutex::new(MockState {
+                    uid_validity: 1,
+                    messages: vec![],
+                    searches: vec![],
+                })),
+            }
+        }
+        pub fn add_message(&self, from: &str, body: &str) {
+            self.add_message_with_headers(from, &[], body);
+        }
+        pub fn add_message_with_headers(&self, from: &str, headers: &[&str], body: &str) {
+            let mut raw = String::new();
+            for header in headers {
+                raw.push_str(&format!("{}\r\n", header));
+            }
+
+            raw.push_str(&format!(
+                "From: {}\r\nSubject: Challenge\r\n\r\n{}\r\n",
+                from, body
+            ));
+            self.add_raw_message(raw);
+        }
+        pub fn add_raw_message(&self, raw: String) {
+            self.state.lock().unwrap().messages.push((raw, false));
+        }
+        pub fn set_uid_validity(&self, uid_validity: u32) {
+            self.state.lock().unwrap().uid_validity = uid_validity;
+        }
+        pub fn searches(&self) -> Vec<String> {
+            self.state.lock().unwrap().searches.clone()
+        }
+        pub fn start(&self) -> SocketAddr {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+
+            let state = Arc::clone(&self.state);
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let state = Arc::clone(&state);
+                    thread::spawn(move || Self::handle(stream.unwrap(), state));
+                }
+            });
+
+            addr
+        }
+        fn search(state: &MockState, query: &str) -> Vec<u32> {
+            let uids = (1..=state.messages.len() as u32).map(|seq| seq + UID_OFFSET);
+
+            if query == "UNSEEN" {
+                uids.filter(|uid| !state.messages[(uid - UID_OFFSET - 1) as usize].1)
+                    .collect()
+            } else if let Some(range) = query.strip_prefix("UID ") {
+                let start = range.trim_end_matches(":*").parse::<u32>().unwrap();
+                let uids: Vec<u32> = uids.collect();
+                // Like real servers, `<UID>:*` always includes the latest
+                // message.
+                uids.iter()
+                    .copied()
+                    .filter(|uid| *uid >= start || Some(uid) == uids.last())
+                    .collect()
+            } else {
+                uids.collect()
+            }
+        }
+        /// Notifies the client about new messages until it sends `DONE`.
+        fn idle(
+            stream: &mut TcpStream,
+            reader: &mut BufReader<TcpStream>,
+            state: &Mutex<MockState>,
+            tag: &str,
+        ) {
+            let mut count = state.lock().unwrap().messages.len();
+            stream.write_all(b"+ idling\r\n").unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_millis(100)))
+                .unwrap();
+
+            let mut line = String::new();
+            loop {
+                let new_count = state.lock().unwrap().messages.len();
+                if new_count > count {
+                    count = new_count;
+                    stream
+                        .write_all(format!("* {} EXISTS\r\n", count).as_bytes())
+                        .unwrap();
+                }
+
+                match reader.read_line(&mut line) {
+                    Ok(_) if line.trim_end() == "DONE" => break,
+                    Ok(0) => return,
+                    _ => {}
+                }
+            }
+
+            stream.set_read_timeout(None).unwrap();
+            stream
+                .write_all(format!("{} OK IDLE terminated\r\n", tag).as_bytes())
+                .unwrap();
+        }
+        fn handle(mut stream: TcpStream, state: Arc<Mutex<MockState>>) {
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            stream.write_all(b"* OK IMAP mock ready\r\n").unwrap();
+
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 0 {
+                let mut parts = line.trim_end().splitn(2, ' ');
+                let tag = parts.next().unwrap_or_default().to_string();
+                let mut cmd = parts.next().unwrap_or_default().to_string();
+                line.clear();
+
+                // Only UID commands are supported for searching and fetching.
+                let is_uid = cmd.starts_with("UID ");
+                if is_uid {
+                    cmd = cmd[4..].to_string();
+                }
+
+                let (cmd, args) = cmd.split_once(' ').unwrap_or((cmd.as_str(), ""));
+                if cmd == "IDLE" {
+                    Self::idle(&mut stream, &mut reader, &state, &tag);
+                    continue;
+                }
+
+                let mut state = state.lock().unwrap();
+                let resp = match cmd {
+                    "LOGIN" => format!("{} OK LOGIN completed\r\n", tag),
+                    "SELECT" => format!(
+                        "* {} EXISTS\r\n* OK [UIDVALIDITY {}] UIDs valid\r\n{} OK [READ-WRITE] SELECT completed\r\n",
+                        state.messages.len(),
+                        state.uid_validity,
+                        tag
+                    ),
+                    "SEARCH" if is_uid => {
+                        let uids: Vec<String> = Self::search(&state, args)
+                            .iter()
+                            .map(|uid| uid.to_string())
+                            .collect();
+
+                        state.searches.push(args.to_string());
+                        format!(
+                            "* SEARCH {}\r\n{} OK SEARCH completed\r\n",
+                            uids.join(" "),
+                            tag
+                        )
+                    }
+                    "FETCH" if is_uid => {
+                        let set = args.split(' ').next().unwrap();
+                        let mut resp = String::new();
+                        for uid in parse_uid_set(set) {
+                            let seq = uid - UID_OFFSET;
+                            let (raw, seen) = &mut state.messages[seq as usize - 1];
+                            *seen = true;
+                            resp.push_str(&format!(
+                                "* {} FETCH (UID {} RFC822 {{{}}}\r\n{})\r\n",
+                                seq,
+                                uid,
+                                raw.len(),
+                                raw
+                            ));
+                        }
+
+                        resp.push_str(&format!("{} OK FETCH completed\r\n", tag));
+                        resp
+                    }
+                    "LOGOUT" => {
+                        let _ = stream.write_all(
+                            format!("* BYE\r\n{} OK LOGOUT completed\r\n", tag).as_bytes(),
+                        );
+                        return;
+                    }
+                    _ => format!("{} BAD unsupported command\r\n", tag),
+                };
+
+                stream.write_all(resp.as_bytes()).unwrap();
+            }
+        }
+    }
+
+    async fn email_client(strategy: SearchStrategy) -> EmailClient {
+        // The database is not accessed when fetching from the session.
+        let db = Database::new("mongodb://localhost:27017/", "registrar_test")
+            .await
+            .unwrap();
+
+        email_client_with_db(strategy, db)
+    }
+
+    fn email_client_with_db(strategy: SearchStrategy, db: Database) -> EmailClient {
+        EmailClientBuilder::new(db)
+            .smtp_server("localhost".to_string())
+            .imap_server("localhost".to_string())
+            .search_strategy(Some(strategy))
+            .email_inbox("INBOX".to_string())
+            .email_user("registrar@email.com".to_string())
+            .email_password("password".to_string())
+            .build()
+            .unwrap()
+    }
+
+    fn session(addr: SocketAddr) -> Session<TcpStream> {
+        let mut client = imap::Client::new(TcpStream::connect(addr).unwrap());
+        client.read_greeting().unwrap();
+        client
+            .login("registrar@email.com", "password")
+            .map_err(|(err, _)| err)
+            .unwrap()
+    }
+
+    fn uids(messages: &[FetchedMessage]) -> Vec<u32> {
+        messages.iter().map(|fetched| fetched.uid).collect()
+    }
+
+    fn idle_messages(client: &EmailClient) -> Vec<u32> {
+        let queue = client.idle_queue.as_ref().unwrap();
+        std::mem::take(&mut *queue.lock().unwrap())
+            .into_iter()
+            .flat_map(|(_, messages)| uids(&messages))
+            .collect()
+    }
+
+    #[test]
+    fn search_queries() {
+        assert_eq!(SearchStrategy::Unseen.query(), "UNSEEN");
+        assert_eq!(SearchStrategy::Gmail.query(), "X-GM-RAW \"newer_than:1d\"");
+
+        let query = SearchStrategy::Since.query();
+        let date = query.strip_prefix("SINCE ").unwrap();
+        assert!(chrono::NaiveDate::parse_from_str(date, "%d-%b-%Y").is_ok());
+    }
+
+    fn parse_headers(raw: &str) -> Vec<MailHeader<'_>> {
+        mailparse::parse_headers(raw.as_bytes()).unwrap().0
+    }
+
+    #[test]
+    fn authentication_results() {
+        // Reported by the receiving server.
+        let raw = "Authentication-Results: mx.registrar.com;\r\n\
+            \tdkim=pass header.i=@mail.email.com header.s=selector header.b=abcdef;\r\n\
+            \tspf=pass (mx.registrar.com: domain of alice@email.com designates 1.2.3.4 as permitted sender) smtp.mailfrom=alice@email.com;\r\n\
+            \tdmarc=pass (p=NONE sp=NONE dis=NONE) header.from=email.com\r\n\r\n";
+
+        let auth = authenticate(&parse_headers(raw), "mx.registrar.com", "alice@email.com");
+        assert!(auth.passed);
+        assert_eq!(
+            auth.dkim,
+            AuthenticationCheck {
+                result: "pass".to_string(),
+                domain: Some("mail.email.com".to_string()),
+                aligned: true,
+            }
+        );
+        assert!(auth.spf.is_aligned_pass());
+        assert!(auth.dmarc.is_aligned_pass());
+
+        // DMARC takes precedence.
+        let raw = "Authentication-Results: mx.registrar.com; spf=pass smtp.mailfrom=email.com; \
+            dmarc=fail header.from=email.com\r\n\r\n";
+
+        let auth = authenticate(&parse_headers(raw), "mx.registrar.com", "alice@email.com");
+        assert!(!auth.passed);
+        assert!(auth.spf.is_aligned_pass());
+
+        // Without DMARC, either DKIM or SPF must pass for an aligned domain.
+        let raw = "Authentication-Results: mx.registrar.com; dkim=pass header.d=other.com; \
+            spf=softfail smtp.mailfrom=alice@email.com\r\n\r\n";
+
+        let auth = authenticate(&parse_headers(raw), "mx.registrar.com", "alice@email.com");
+        assert!(!auth.passed);
+        assert_eq!(auth.dkim.result, "pass");
+        assert!(!auth.dkim.aligned);
+        assert_eq!(auth.dmarc, AuthenticationCheck::none());
+
+        let raw = "Authentication-Results: mx.registrar.com; dkim=pass header.d=other.com; \
+            dkim=pass header.d=email.com\r\n\r\n";
+
+        let auth = authenticate(&parse_headers(raw), "mx.registrar.com", "alice@email.com");
+        assert!(auth.passed);
+        assert_eq!(auth.dkim.domain, Some("email.com".to_string()));
+
+        // Top-level domains are never aligned.
+        assert!(!is_aligned("com", "email.com"));
+    }
+
+    #[test]
+    fn authentication_results_forged() {
+        // Headers of other servers are ignored.
+        let raw =
+            "Authentication-Results: mx.attacker.com; dmarc=pass header.from=email.com\r\n\r\n";
+
+        let auth = authenticate(&parse_headers(raw), "mx.registrar.com", "alice@email.com");
+        assert!(!auth.passed);
+        assert_eq!(auth.dkim, AuthenticationCheck::none());
+        assert_eq!(auth.spf, AuthenticationCheck::none());
+        assert_eq!(auth.dmarc, AuthenticationCheck::none());
+
+        // Only the header prepended by the receiving server is trusted.
+        let raw = "Authentication-Results: mx.registrar.com; dmarc=fail header.from=email.com\r\n\
+            Authentication-Results: mx.registrar.com; dmarc=pass header.from=email.com\r\n\r\n";
+
+        let auth = authenticate(&parse_headers(raw), "mx.registrar.com", "alice@email.com");
+        assert!(!auth.passed);
+        assert_eq!(auth.dmarc.result, "fail");
+    }
+
+    #[actix::test]
+    async fn fetch_messages_since() {
+        let mock = ImapMock::new();
+        mock.add_message("Alice <alice@email.com>", "first_challenge");
+        mock.add_message("bob@email.com", "second_challenge");
+        let addr = mock.start();
+
+        let client = email_client(SearchStrategy::Since).await;
+        let mut imap = session(addr);
+
+        let (uid_validity, messages) = client.fetch_from_session(&mut imap, None).unwrap();
+        assert_eq!(uid_validity, 1);
+        assert_eq!(uids(&messages), vec![11, 12]);
+
+        let alice = messages[0].message.as_ref().unwrap();
+        assert_eq!(
+            alice.origin,
+            ExternalMessageType::Email("alice@email.com".to_string())
+        );
+        assert_eq!(alice.id, 11u32.into());
+        assert!(ExpectedMessage {
+            value: "first_challenge".to_string(),
+            is_verified: false,
+            issued_at: None,
+            format: ChallengeFormat::Hex,
+        }
+        .verify_message(alice));
+
+        let bob = messages[1].message.as_ref().unwrap();
+        assert_eq!(
+            bob.origin,
+            ExternalMessageType::Email("bob@email.com".to_string())
+        );
+
+        assert!(mock.searches()[0].starts_with("SINCE "));
+    }
+
+    #[actix::test]
+    async fn fetch_messages_unseen() {
+        let mock = ImapMock::new();
+        mock.add_message("alice@email.com", "first_challenge");
+        let addr = mock.start();
+
+        let client = email_client(SearchStrategy::Unseen).await;
+        let mut imap = session(addr);
+
+        let (_, messages) = client.fetch_from_session(&mut imap, None).unwrap();
+        assert_eq!(uids(&messages), vec![11]);
+
+        // Only the new message is fetched.
+        mock.add_message("alice@email.com", "second_challenge");
+        let (_, messages) = client.fetch_from_session(&mut imap, None).unwrap();
+        assert_eq!(uids(&messages), vec![12]);
+
+        assert_eq!(mock.searches(), vec!["UNSEEN".to_string(); 2]);
+    }
+
+    #[actix::test]
+    async fn fetch_messages_after_cursor() {
+        let mock = ImapMock::new();
+        mock.add_message("alice@email.com", "first_challenge");
+        mock.add_message("alice@email.com", "second_challenge");
+        let addr = mock.start();
+
+        let client = email_client(SearchStrategy::Since).await;
+        let mut imap = session(addr);
+
+        // Resume after the last processed message.
+        let cursor = EmailCursor {
+            uid_validity: 1,
+            last_uid: 11,
+        };
+
+        let (_, messages) = client.fetch_from_session(&mut imap, Some(&cursor)).unwrap();
+        assert_eq!(uids(&messages), vec![12]);
+
+        // All messages were processed.
+        let cursor = EmailCursor {
+            uid_validity: 1,
+            last_uid: 12,
+        };
+
+        let (_, messages) = client.fetch_from_session(&mut imap, Some(&cursor)).unwrap();
+        assert!(messages.is_empty());
+
+        assert_eq!(
+            mock.searches(),
+            vec!["UID 12:*".to_string(), "UID 13:*".to_string()]
+        );
+
+        // The cursor is ignored if the UIDVALIDITY has changed.
+        mock.set_uid_validity(2);
+
+        let (uid_validity, messages) = client.fetch_from_session(&mut imap, Some(&cursor)).unwrap();
+        assert_eq!(uid_validity, 2);
+        assert_eq!(uids(&messages), vec![11, 12]);
+        assert!(mock.searches()[2].starts_with("SINCE "));
+
+        // Same for a reset cursor.
+        let cursor = EmailCursor {
+            uid_validity: 2,
+            last_uid: 0,
+        };
+
+        let (_, messages) = client.fetch_from_session(&mut imap, Some(&cursor)).unwrap();
+        assert_eq!(uids(&messages), vec![11, 12]);
+        assert!(mock.searches()[3].starts_with("SINCE "));
     }
-    async fn send_message(&mut self, to: &str, content: Self::MessageType) -> Result<()> {
-        Self::send_message(self, to, content.value.as_str()).await
+
+    #[actix::test]
+    async fn skip_unprocessable_messages() {
+        let mock = ImapMock::new();
+        mock.add_raw_message("Subject: Challenge\r\n\r\nfirst_challenge\r\n".to_string());
+        mock.add_message("alice@email.com", "second_challenge");
+        let addr = mock.start();
+
+        let client = email_client_with_db(SearchStrategy::Since, crate::tests::new_db().await);
+        let mut imap = session(addr);
+
+        // The message without a sender is still returned.
+        let (uid_validity, messages) = client.fetch_from_session(&mut imap, None).unwrap();
+        assert_eq!(uids(&messages), vec![11, 12]);
+        assert!(messages[0].message.is_none());
+
+        let mut fetched = messages;
+        let mut messages = vec![];
+        client
+            .claim_messages(uid_validity, &mut fetched, &mut messages)
+            .await
+            .unwrap();
+        assert!(fetched.is_empty());
+        assert_eq!(messages.len(), 1);
+        assert_eq!(
+            messages[0].origin,
+            ExternalMessageType::Email("alice@email.com".to_string())
+        );
+
+        // The cursor moved past both messages.
+        let cursor = client
+            .db
+            .fetch_email_cursor(&client.mailbox_id())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(cursor.last_uid, 12);
+    }
+
+    #[actix::test]
+    async fn fetch_messages_via_idle() {
+        let mock = ImapMock::new();
+        mock.add_message("alice@email.com", "first_challenge");
+        let addr = mock.start();
+
+        let mut client = email_client(SearchStrategy::Since).await;
+        client.spawn_idle(session(addr), None);
+
+        // Existing messages are fetched when the session is started.
+        thread::sleep(Duration::from_secs(1));
+        assert_eq!(idle_messages(&client), vec![11]);
+
+        // New messages are pushed as they arrive.
+        mock.add_message("bob@email.com", "second_challenge");
+        thread::sleep(Duration::from_secs(1));
+        assert_eq!(idle_messages(&client), vec![12]);
+
+        // Only messages after the last fetched one were requested.
+        assert_eq!(mock.searches().last().unwrap(), "UID 12:*");
+
+        // Nothing new.
+        thread::sleep(Duration::from_secs(1));
+        assert!(idle_messages(&client).is_empty());
+    }
+
+    #[actix::test]
+    async fn fetch_messages_with_authentication() {
+        let mock = ImapMock::new();
+        mock.add_message_with_headers(
+            "alice@email.com",
+            &["Authentication-Results: mx.registrar.com; dmarc=pass header.from=email.com"],
+            "first_challenge",
+        );
+        mock.add_message("bob@email.com", "second_challenge");
+        let addr = mock.start();
+
+        let mut client = email_client(SearchStrategy::Since).await;
+        let mut imap = session(addr);
+
+        // Disabled by default.
+        let (_, messages) = client.fetch_from_session(&mut imap, None).unwrap();
+        assert!(messages
+            .iter()
+            .all(|fetched| fetched.authentication.is_none()));
+
+        client.authentication_policy = Some(AuthenticationPolicy::Reject);
+        client.authserv_id = Some("mx.registrar.com".to_string());
+
+        let (_, messages) = client.fetch_from_session(&mut imap, None).unwrap();
+        assert!(messages[0].authentication.as_ref().unwrap().passed);
+        assert!(!messages[1].authentication.as_ref().unwrap().passed);
+    }
+
+    #[actix::test]
+    async fn fetch_automatic_replies() {
+        let mock = ImapMock::new();
+        mock.add_message_with_headers(
+            "alice@email.com",
+            &["Auto-Submitted: auto-replied"],
+            "Out of office",
+        );
+        mock.add_message_with_headers("bob@email.com", &["Auto-Submitted: no"], "challenge");
+        mock.add_message("eve@email.com", "challenge");
+        let addr = mock.start();
+
+        let client = email_client(SearchStrategy::Since).await;
+        let mut imap = session(addr);
+
+        let (_, messages) = client.fetch_from_session(&mut imap, None).unwrap();
+        assert_eq!(
+            messages
+                .iter()
+                .map(|fetched| fetched.auto_submitted)
+                .collect::<Vec<bool>>(),
+            vec![true, false, false]
+        );
+    }
+
+    #[actix::test]
+    async fn build_failure_email() {
+        let client = email_client(SearchStrategy::Since).await;
+
+        let msg = EmailMessage::try_from(AdapterMessage::FieldVerificationFailed(
+            IdentityFieldValue::Email("alice@email.com".to_string()),
+            FailureReason::StaleChallenge,
+        ))
+        .unwrap();
+
+        let email: lettre::SendableEmail =
+            client.build_email("alice@email.com", &msg).unwrap().into();
+        let raw = email.message_to_string().unwrap();
+
+        assert!(raw.contains("Auto-Submitted: auto-replied"));
+        assert!(raw.contains(FailureReason::StaleChallenge.description()));
+
+        // Not sent via email.
+        assert!(
+            EmailMessage::try_from(AdapterMessage::FieldVerified(IdentityFieldValue::Email(
+                "alice@email.com".to_string()
+            )))
+            .is_err()
+        );
+    }
+
+    #[actix::test]
+    async fn failure_email_requires_authentication() {
+        // The authentication results are stored in the database.
+        let client = email_client_with_db(SearchStrategy::Since, crate::tests::new_db().await);
+        client
+            .db
+            .add_judgement_request(&JudgementState::alice())
+            .await
+            .unwrap();
+
+        let message = ExternalMessage {
+            origin: ExternalMessageType::Email("alice@email.com".to_string()),
+            id: 0u32.into(),
+            timestamp: Timestamp::now(),
+            values: vec![],
+        };
+        let authentication = |passed| EmailAuthentication {
+            dkim: AuthenticationCheck::none(),
+            spf: AuthenticationCheck::none(),
+            dmarc: AuthenticationCheck::none(),
+            passed,
+            timestamp: Timestamp::now(),
+        };
+
+        let mut failure = EmailMessage::Failure(FailureReason::WrongChallenge);
+
+        // Not sent if the authentication checks are disabled.
+        assert!(!client
+            .prepare_message("alice@email.com", &mut failure)
+            .await
+            .unwrap());
+
+        client
+            .apply_authentication_policy(&message, authentication(false))
+            .await
+            .unwrap();
+        assert!(!client
+            .prepare_message("alice@email.com", &mut failure)
+            .await
+            .unwrap());
+
+        client
+            .apply_authentication_policy(&message, authentication(true))
+            .await
+            .unwrap();
+        assert!(client
+            .prepare_message("alice@email.com", &mut failure)
+            .await
+            .unwrap());
+    }
+
+    fn challenge_email() -> ChallengeEmail {
+        ChallengeEmail {
+            context: IdentityContext::alice(),
+            challenge: ExpectedMessage {
+                value: "a1b2c3".to_string(),
+                is_verified: false,
+                issued_at: None,
+                format: ChallengeFormat::Hex,
+            },
+            untrusted: false,
+        }
+    }
+
+    #[test]
+    fn render_template() {
+        let template = EmailTemplate {
+            subject: "Verification".to_string(),
+            text: "Challenge for {chain} {address}: {challenge}\n\
+                Link: {verification_link}\n\
+                UI: {ui_link} {unknown}"
+                .to_string(),
+            html: "<p>{challenge}</p>\n<a href=\"{ui_link}\">{subject}</a>\n\
+                <a href=\"{verification_link}\">verify</a>"
+                .to_string(),
+            ui_url: "https://registrar.example.com/".to_string(),
+        };
+
+        let address = IdentityContext::alice().address;
+        let (subject, text, html) = template.render(&challenge_email(), None);
+        assert_eq!(subject, "Verification");
+
+        // The line of the verification link is removed.
+        assert_eq!(
+            text,
+            format!(
+                "Challenge for polkadot {0}: a1b2c3\n\
+                UI: https://registrar.example.com/?network=polkadot&address={0} {{unknown}}",
+                address.as_str()
+            )
+        );
+        assert_eq!(
+            html,
+            format!(
+                "<p>a1b2c3</p>\n\
+                <a href=\"https://registrar.example.com/?network=polkadot&amp;address={}\">Verification</a>",
+                address.as_str()
+            )
+        );
+
+        let (_, text, html) = template.render(&challenge_email(), Some("https://link?a=1&b=2"));
+        assert!(text.contains("Link: https://link?a=1&b=2\n"));
+        assert!(html.ends_with("<a href=\"https://link?a=1&amp;b=2\">verify</a>"));
+
+        // Values are not interpreted as placeholders.
+        assert_eq!(
+            replace_placeholders("{a} {b}", &[("a", "{b}"), ("b", "<b>")], true),
+            "{b} &lt;b&gt;"
+        );
+    }
+
+    #[test]
+    fn default_templates() {
+        let (_, text, html) = EmailTemplate::default().render(&challenge_email(), None);
+
+        for body in &[&text, &html] {
+            assert!(body.contains("a1b2c3"));
+            assert!(body.contains(IdentityContext::alice().address.as_str()));
+            assert!(!body.contains("verification_link"));
+            assert!(!body.contains('{'));
+        }
+
+        let (_, text, html) =
+            EmailTemplate::default().render(&challenge_email(), Some("https://link"));
+        assert!(text.contains("https://link"));
+        assert!(html.contains("https://link"));
+    }
+
+    #[actix::test]
+    async fn render_message_with_link() {
+        let mut client = email_client(SearchStrategy::Since).await;
+        client.verification_link = Some(VerificationLinkConfig {
+            api_base: "https://registrar.example.com".to_string(),
+            secret: "secret".to_string(),
+            ttl: None,
+        });
+
+        let (_, text, _) = client
+            .render_message("alice@email.com", &challenge_email())
+            .unwrap();
+
+        let url = text
+            .split_whitespace()
+            .find(|word| word.starts_with("https://registrar.example.com/api/"))
+            .unwrap();
+        let query = url::Url::parse(url).unwrap().query().unwrap().to_string();
+        let link = actix_web::web::Query::<EmailLink>::from_query(&query)
+            .unwrap()
+            .into_inner();
+
+        assert!(link.is_valid("secret"));
+        assert_eq!(link.email, "alice@email.com");
+        assert_eq!(link.challenge, "a1b2c3");
+
+        // Not included if the challenge must be entered manually.
+        let mut email = challenge_email();
+        email.untrusted = true;
+
+        let (_, text, _) = client.render_message("alice@email.com", &email).unwrap();
+        assert!(!text.contains("https://registrar.example.com/api/"));
     }
 }
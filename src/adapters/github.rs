@@ -0,0 +1,464 @@
+use crate::adapters::admin::RawFieldName;
+use crate::adapters::{read_body_limited, Adapter};
+use crate::primitives::{ExternalMessage, ExternalMessageType, IdentityFieldValue, Timestamp};
+use crate::{Database, Result};
+use reqwest::header::{self, HeaderMap, HeaderValue};
+use reqwest::{Client, StatusCode};
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::time::Duration;
+
+const DEFAULT_API_BASE: &str = "https://api.github.com";
+const USER_AGENT: &str = "polkadot-registrar-challenger";
+const REQUEST_TIMEOUT: u64 = 10;
+// Only the most recently updated gists are checked.
+const GIST_LIMIT: usize = 10;
+// Gist files and READMEs bigger than this are ignored.
+const MAX_CONTENT_LENGTH: usize = 64 * 1024;
+const MAX_USERNAME_LENGTH: usize = 39;
+
+pub struct GitHubBuilder {
+    db: Database,
+    token: Option<String>,
+    api_base: Option<String>,
+}
+
+impl GitHubBuilder {
+    pub fn new(db: Database) -> Self {
+        GitHubBuilder {
+            db,
+            token: None,
+            api_base: None,
+        }
+    }
+    /// Optional, but unauthenticated requests are heavily rate limited.
+    pub fn token(mut self, token: Option<String>) -> Self {
+        self.token = token;
+        self
+    }
+    /// Defaults to `https://api.github.com`.
+    pub fn api_base(mut self, api_base: Option<String>) -> Self {
+        self.api_base = api_base;
+        self
+    }
+    pub fn build(self) -> Result<GitHubClient> {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::USER_AGENT, HeaderValue::from_static(USER_AGENT));
+        if let Some(token) = self.token {
+            headers.insert(
+                header::AUTHORIZATION,
+                HeaderValue::from_str(&format!("token {}", token))?,
+            );
+        }
+
+        Ok(GitHubClient {
+            client: Client::builder()
+                .default_headers(headers)
+                .timeout(Duration::from_secs(REQUEST_TIMEOUT))
+                .build()?,
+            db: self.db,
+            api_base: self
+                .api_base
+                .unwrap_or_else(|| DEFAULT_API_BASE.to_string())
+                .trim_end_matches('/')
+                .to_string(),
+            cache: HashMap::new(),
+        })
+    }
+}
+
+/// Looks up the challenge in the public gists and the profile README of all
+/// pending GitHub accounts.
+pub struct GitHubClient {
+    client: Client,
+    db: Database,
+    api_base: String,
+    // Keep track of the last fetched content of each account, so the same
+    // (invalid) content does not count as a failed attempt on every request.
+    cache: HashMap<String, Vec<String>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ApiGist {
+    files: HashMap<String, ApiGistFile>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ApiGistFile {
+    raw_url: String,
+    size: usize,
+}
+
+/// Converts the handle (or profile URL) into the plain, lowercase username.
+pub fn normalize_handle(handle: &str) -> String {
+    let handle = handle.trim();
+    let handle = handle
+        .strip_prefix("https://github.com/")
+        .or_else(|| handle.strip_prefix("http://github.com/"))
+        .or_else(|| handle.strip_prefix("github.com/"))
+        .unwrap_or(handle);
+
+    handle
+        .trim_start_matches('@')
+        .trim_end_matches('/')
+        .to_lowercase()
+}
+
+/// Whether the username is valid according to the rules of GitHub: up to 39
+/// ASCII alphanumeric characters or hyphens, without a leading or trailing hyphen.
+/// Usernames are part of API paths, so anything else must be rejected.
+fn is_valid_username(user: &str) -> bool {
+    (1..=MAX_USERNAME_LENGTH).contains(&user.len())
+        && user.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        && !user.starts_with('-')
+        && !user.ends_with('-')
+}
+
+impl GitHubClient {
+    async fn get_request<T: DeserializeOwned>(&self, url: &str) -> Result<T> {
+        self.client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .map_err(|err| err.into())
+    }
+    async fn get_raw(&self, url: &str) -> Result<Option<String>> {
+        let resp = self
+            .client
+            .get(url)
+            .header(header::ACCEPT, "application/vnd.github.raw")
+            .send()
+            .await?;
+
+        // Not every user has a profile README.
+        if resp.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        let content = read_body_limited(resp.error_for_status()?, MAX_CONTENT_LENGTH).await?;
+        if content.is_none() {
+            debug!("Ignoring content of {}, too large", url);
+        }
+
+        Ok(content)
+    }
+    async fn fetch_gists(&self, user: &str) -> Result<Vec<String>> {
+        let gists: Vec<ApiGist> = self
+            .get_request(&format!(
+                "{}/users/{}/gists?per_page={}",
+                self.api_base, user, GIST_LIMIT
+            ))
+            .await?;
+
+        let mut content = vec![];
+        for file in gists.iter().flat_map(|gist| gist.files.values()) {
+            if file.size > MAX_CONTENT_LENGTH {
+                continue;
+            }
+
+            if let Some(file) = self.get_raw(&file.raw_url).await? {
+                content.push(file);
+            }
+        }
+
+        Ok(content)
+    }
+    async fn fetch_profile_readme(&self, user: &str) -> Result<Option<String>> {
+        // The profile README is located in the repository of the same name.
+        self.get_raw(&format!("{}/repos/{}/{}/readme", self.api_base, user, user))
+            .await
+    }
+    async fn request_messages(&mut self) -> Result<Vec<ExternalMessage>> {
+        let fields = self
+            .db
+            .fetch_unverified_fields(&RawFieldName::GitHub)
+            .await?;
+
+        let mut messages = vec![];
+        for field in fields {
+            let user = match field {
+                IdentityFieldValue::GitHub(user) => user,
+                _ => continue,
+            };
+
+            if !is_valid_username(&user) {
+                warn!("Skipping invalid GitHub username: {}", user);
+                continue;
+            }
+
+            let mut content = vec![];
+
+            match self.fetch_gists(&user).await {
+                Ok(mut gists) => content.append(&mut gists),
+                Err(err) => debug!("Failed to fetch gists of {}: {:?}", user, err),
+            }
+
+            match self.fetch_profile_readme(&user).await {
+                Ok(Some(readme)) => content.push(readme),
+                Ok(None) => {}
+                Err(err) => debug!("Failed to fetch profile README of {}: {:?}", user, err),
+            }
+
+            // Skip if nothing was found or the content was already processed.
+            if content.is_empty() || self.cache.get(&user) == Some(&content) {
+                continue;
+            }
+
+            debug!("Fetched challenge content from GitHub account {}", user);
+
+            self.cache.insert(user.clone(), content.clone());
+            messages.push(ExternalMessage {
+                origin: ExternalMessageType::GitHub(user),
+                // A message UID is not relevant, the content is tracked by
+                // the cache.
+                id: 0u32.into(),
+                timestamp: Timestamp::now(),
+                values: content.into_iter().map(|c| c.into()).collect(),
+            });
+        }
+
+        Ok(messages)
+    }
+}
+
+#[async_trait]
+impl Adapter for GitHubClient {
+    type MessageType = ();
+
+    fn name(&self) -> &'static str {
+        "github"
+    }
+    async fn fetch_messages(&mut self) -> Result<Vec<ExternalMessage>> {
+        self.request_messages().await
+    }
+    async fn send_message(&mut self, _to: &str, _content: Self::MessageType) -> Result<()> {
+        // GitHub accounts cannot be contacted.
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use actix_test::TestServer;
+    use actix_web::{web, App, HttpRequest, HttpResponse};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct MockState {
+        // User -> gists -> files.
+        gists: HashMap<String, Vec<Vec<String>>>,
+        // User -> profile README.
+        readmes: HashMap<String, String>,
+        // Amount of received requests.
+        requests: usize,
+    }
+
+    /// Stand-in for the GitHub API, so the tests run offline.
+    #[derive(Clone, Default)]
+    pub struct GitHubMock {
+        state: Arc<Mutex<MockState>>,
+    }
+
+    async fn list_gists(
+        req: HttpRequest,
+        state: web::Data<Mutex<MockState>>,
+        user: web::Path<String>,
+    ) -> HttpResponse {
+        let mut state = state.lock().unwrap();
+        let user = user.into_inner();
+        state.requests += 1;
+
+        let gists: Vec<ApiGist> = state
+            .gists
+            .get(&user)
+            .map(|gists| {
+                gists
+                    .iter()
+                    .enumerate()
+                    .map(|(gist, files)| ApiGist {
+                        files: files
+                            .iter()
+                            .enumerate()
+                            .map(|(file, content)| {
+                                let raw_url = req
+                                    .url_for(
+                                        "raw_gist",
+                                        [user.as_str(), &gist.to_string(), &file.to_string()],
+                                    )
+                                    .unwrap()
+                                    .to_string();
+
+                                (
+                                    format!("challenge_{}.txt", file),
+                                    ApiGistFile {
+                                        raw_url,
+                                        size: content.len(),
+                                    },
+                                )
+                            })
+                            .collect(),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        HttpResponse::Ok().json(gists)
+    }
+
+    async fn raw_gist(
+        state: web::Data<Mutex<MockState>>,
+        path: web::Path<(String, usize, usize)>,
+    ) -> HttpResponse {
+        let mut state = state.lock().unwrap();
+        let (user, gist, file) = path.into_inner();
+        state.requests += 1;
+
+        match state
+            .gists
+            .get(&user)
+            .and_then(|gists| gists.get(gist))
+            .and_then(|files| files.get(file))
+        {
+            Some(content) => HttpResponse::Ok().body(content.clone()),
+            None => HttpResponse::NotFound().finish(),
+        }
+    }
+
+    async fn readme(
+        state: web::Data<Mutex<MockState>>,
+        path: web::Path<(String, String)>,
+    ) -> HttpResponse {
+        let mut state = state.lock().unwrap();
+        let (owner, repo) = path.into_inner();
+        state.requests += 1;
+
+        match state.readmes.get(&owner).filter(|_| owner == repo) {
+            Some(content) => HttpResponse::Ok().body(content.clone()),
+            None => HttpResponse::NotFound().finish(),
+        }
+    }
+
+    impl GitHubMock {
+        pub fn new() -> Self {
+            Self::default()
+        }
+        pub fn add_gist(&self, user: &str, content: &str) {
+            self.add_gist_files(user, &[content]);
+        }
+        pub fn add_gist_files(&self, user: &str, files: &[&str]) {
+            self.state
+                .lock()
+                .unwrap()
+                .gists
+                .entry(user.to_string())
+                .or_default()
+                .push(files.iter().map(|file| file.to_string()).collect());
+        }
+        pub fn set_readme(&self, user: &str, content: &str) {
+            self.state
+                .lock()
+                .unwrap()
+                .readmes
+                .insert(user.to_string(), content.to_string());
+        }
+        pub fn requests(&self) -> usize {
+            self.state.lock().unwrap().requests
+        }
+        pub fn start(&self) -> TestServer {
+            let state = Arc::clone(&self.state);
+
+            actix_test::start(move || {
+                App::new()
+                    .app_data(web::Data::from(Arc::clone(&state)))
+                    .route("/users/{user}/gists", web::get().to(list_gists))
+                    .service(
+                        web::resource("/raw/{user}/{gist}/{file}")
+                            .name("raw_gist")
+                            .route(web::get().to(raw_gist)),
+                    )
+                    .route("/repos/{owner}/{repo}/readme", web::get().to(readme))
+            })
+        }
+    }
+
+    async fn github_client(server: &TestServer) -> GitHubClient {
+        // The database is not accessed by the API calls.
+        let db = Database::new("mongodb://localhost:27017/", "registrar_test")
+            .await
+            .unwrap();
+
+        GitHubBuilder::new(db)
+            .api_base(Some(server.url("")))
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn handle_normalization() {
+        assert_eq!(normalize_handle("alice"), "alice");
+        assert_eq!(normalize_handle(" @Alice "), "alice");
+        assert_eq!(normalize_handle("https://github.com/Alice/"), "alice");
+        assert_eq!(normalize_handle("github.com/alice"), "alice");
+    }
+
+    #[test]
+    fn username_validation() {
+        assert!(is_valid_username("alice"));
+        assert!(is_valid_username("alice-bob"));
+        assert!(is_valid_username("a1"));
+        assert!(is_valid_username(&"a".repeat(39)));
+
+        assert!(!is_valid_username(""));
+        assert!(!is_valid_username(&"a".repeat(40)));
+        assert!(!is_valid_username("-alice"));
+        assert!(!is_valid_username("alice-"));
+        assert!(!is_valid_username("../alice"));
+        assert!(!is_valid_username("alice/repos"));
+        assert!(!is_valid_username("alice?per_page=100"));
+        assert!(!is_valid_username("alice#readme"));
+        assert!(!is_valid_username("alice bob"));
+        assert!(!is_valid_username("älice"));
+    }
+
+    #[actix::test]
+    async fn fetch_gists_and_readme() {
+        let mock = GitHubMock::new();
+        let server = mock.start();
+        let client = github_client(&server).await;
+
+        // Nothing published.
+        assert!(client.fetch_gists("alice").await.unwrap().is_empty());
+        assert!(client
+            .fetch_profile_readme("alice")
+            .await
+            .unwrap()
+            .is_none());
+
+        mock.add_gist("alice", "first");
+        mock.add_gist_files("alice", &["second", "third"]);
+        mock.add_gist("bob", "other");
+        mock.set_readme("alice", "# Alice");
+
+        let mut gists = client.fetch_gists("alice").await.unwrap();
+        gists.sort();
+        assert_eq!(
+            gists,
+            vec![
+                "first".to_string(),
+                "second".to_string(),
+                "third".to_string()
+            ]
+        );
+
+        let readme = client.fetch_profile_readme("alice").await.unwrap();
+        assert_eq!(readme, Some("# Alice".to_string()));
+
+        // Too large content is ignored.
+        mock.set_readme("bob", &"a".repeat(MAX_CONTENT_LENGTH + 1));
+        assert!(client.fetch_profile_readme("bob").await.unwrap().is_none());
+    }
+}
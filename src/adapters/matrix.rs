@@ -1,13 +1,17 @@
 use crate::adapters::admin::{process_admin, Command, Response};
-use crate::adapters::Adapter;
-use crate::primitives::{ExternalMessage, ExternalMessageType, Timestamp};
-use crate::{Database, Result};
+use crate::adapters::{Adapter, AdapterMessage};
+use crate::primitives::{ExternalMessage, ExternalMessageType, IdentityFieldValue, Timestamp};
+use crate::{Database, NetworkConfig, Result};
+use matrix_sdk::api::r0::room::create_room::{Request as CreateRoomRequest, RoomPreset};
 use matrix_sdk::events::room::member::MemberEventContent;
 use matrix_sdk::events::room::message::MessageEventContent;
 use matrix_sdk::events::{AnyMessageEventContent, StrippedStateEvent, SyncMessageEvent};
+use matrix_sdk::identifiers::{RoomId, UserId};
 use matrix_sdk::room::Room;
 use matrix_sdk::{Client, ClientConfig, EventHandler, SyncSettings};
 use ruma::events::room::message::{MessageType, TextMessageEventContent};
+use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::str::FromStr;
 use std::sync::Arc;
 use tokio::sync::Mutex;
@@ -19,8 +23,12 @@ const REJOIN_MAX_ATTEMPTS: usize = 5;
 
 #[derive(Clone)]
 pub struct MatrixClient {
+    client: Client,
     // TODO: This should just be a channel.
     messages: Arc<Mutex<Vec<ExternalMessage>>>,
+    // Direct room of each user, either found or created by the bot. Created
+    // rooms are only known to the client after the next sync.
+    direct_rooms: Arc<Mutex<HashMap<UserId, RoomId>>>,
 }
 
 impl MatrixClient {
@@ -30,6 +38,7 @@ impl MatrixClient {
         password: &str,
         db_path: &str,
         db: Database,
+        networks: Vec<NetworkConfig>,
         admins: Vec<MatrixHandle>,
     ) -> Result<MatrixClient> {
         info!("Setting up Matrix client");
@@ -56,6 +65,7 @@ impl MatrixClient {
                 client.clone(),
                 Arc::clone(&messages),
                 db,
+                networks,
                 admins,
             )))
             .await;
@@ -69,11 +79,77 @@ impl MatrixClient {
                 .ok_or_else(|| anyhow!("Failed to acquire sync token"))?,
         );
 
+        let sync_client = client.clone();
         actix::spawn(async move {
-            client.clone().sync(settings).await;
+            sync_client.sync(settings).await;
         });
 
-        Ok(MatrixClient { messages })
+        Ok(MatrixClient {
+            client,
+            messages,
+            direct_rooms: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+    /// Looks for a joined room shared only with the given user, such as the
+    /// room the user invited the bot to.
+    async fn find_direct_room(&self, user: &UserId) -> Result<Option<RoomId>> {
+        let own_id = self.client.user_id().await;
+
+        for room in self.client.joined_rooms() {
+            // The room stays marked as direct after the user has left.
+            if room.direct_target().as_ref() == Some(user)
+                && room.get_member_no_sync(user).await?.is_some()
+            {
+                return Ok(Some(room.room_id().clone()));
+            }
+
+            let members: Vec<UserId> = room
+                .joined_members_no_sync()
+                .await?
+                .iter()
+                .map(|member| member.user_id().clone())
+                .collect();
+
+            if members.len() == 2
+                && members.contains(user)
+                && own_id.as_ref().map(|id| members.contains(id)) == Some(true)
+            {
+                return Ok(Some(room.room_id().clone()));
+            }
+        }
+
+        Ok(None)
+    }
+    /// Returns a room shared only with the given user, creating a new direct
+    /// room if none exists yet.
+    async fn direct_room(&self, user: &UserId) -> Result<RoomId> {
+        let mut direct_rooms = self.direct_rooms.lock().await;
+        if let Some(room_id) = direct_rooms.get(user) {
+            // Reuse the room while it's not synced yet, or as long as the user
+            // is still a member.
+            match self.client.get_joined_room(room_id) {
+                Some(room) if room.get_member_no_sync(user).await?.is_none() => {}
+                _ => return Ok(room_id.clone()),
+            }
+        }
+
+        // Only scan the joined rooms if no room is known yet.
+        if let Some(room_id) = self.find_direct_room(user).await? {
+            direct_rooms.insert(user.clone(), room_id.clone());
+            return Ok(room_id);
+        }
+
+        debug!("Creating direct room with {}", user);
+        let invite = [user.clone()];
+        let mut request = CreateRoomRequest::new();
+        request.invite = &invite;
+        request.is_direct = true;
+        request.preset = Some(RoomPreset::TrustedPrivateChat);
+
+        let room_id = self.client.create_room(request).await?.room_id;
+        direct_rooms.insert(user.clone(), room_id.clone());
+
+        Ok(room_id)
     }
 }
 
@@ -84,6 +160,8 @@ struct Listener {
     client: Client,
     messages: Arc<Mutex<Vec<ExternalMessage>>>,
     db: Database,
+    // Used by the admin commands to detect the network of addresses.
+    networks: Vec<NetworkConfig>,
     admins: Vec<MatrixHandle>,
 }
 
@@ -92,12 +170,14 @@ impl Listener {
         client: Client,
         messages: Arc<Mutex<Vec<ExternalMessage>>>,
         db: Database,
+        networks: Vec<NetworkConfig>,
         admins: Vec<MatrixHandle>,
     ) -> Self {
         Self {
             client,
             messages,
             db,
+            networks,
             admins,
         }
     }
@@ -152,12 +232,17 @@ impl EventHandler for Listener {
                 return;
             };
 
+            // Ignore messages sent by the bot itself.
+            if self.client.user_id().await.as_ref() == Some(&event.sender) {
+                return;
+            }
+
             // Check for admin message
             let sender = event.sender.to_string();
             if self.admins.contains(&MatrixHandle(sender)) {
                 let resp = match Command::from_str(msg_body) {
                     // If a valid admin command was found, execute it.
-                    Ok(cmd) => Some(process_admin(&self.db, cmd).await),
+                    Ok(cmd) => Some(process_admin(&self.db, &self.networks, cmd).await),
                     Err(err @ Response::InvalidSyntax(_)) => Some(err),
                     // Ignore, allow noise (catches `UnknownCommand`).
                     Err(_) => None,
@@ -199,9 +284,26 @@ impl EventHandler for Listener {
     }
 }
 
+impl From<AdapterMessage> for MessageEventContent {
+    fn from(msg: AdapterMessage) -> Self {
+        let text = match msg {
+            AdapterMessage::SecondChallenge(_, challenge) => format!(
+                "Insert the following challenge into the web interface: {}",
+                challenge.value
+            ),
+            AdapterMessage::FieldVerified(_) => {
+                "Your Matrix account has been verified.".to_string()
+            }
+            AdapterMessage::FieldVerificationFailed(_, reason) => reason.description().to_string(),
+        };
+
+        MessageEventContent::text_plain(text)
+    }
+}
+
 #[async_trait]
 impl Adapter for MatrixClient {
-    type MessageType = ();
+    type MessageType = MessageEventContent;
 
     fn name(&self) -> &'static str {
         "Matrix"
@@ -211,7 +313,20 @@ impl Adapter for MatrixClient {
         // Return messages and wipe inner field.
         Ok(std::mem::take(&mut *lock))
     }
-    async fn send_message(&mut self, _to: &str, _content: Self::MessageType) -> Result<()> {
-        unimplemented!()
+    async fn send_message(&mut self, to: &str, content: Self::MessageType) -> Result<()> {
+        let user = UserId::try_from(to)?;
+        let room_id = self.direct_room(&user).await?;
+
+        self.client
+            .room_send(&room_id, AnyMessageEventContent::RoomMessage(content), None)
+            .await?;
+
+        Ok(())
+    }
+    fn recipient(&self, field: &IdentityFieldValue) -> Option<String> {
+        match field {
+            IdentityFieldValue::Matrix(to) => Some(to.clone()),
+            _ => None,
+        }
     }
 }
@@ -1,27 +1,38 @@
 use crate::database::Database;
 use crate::primitives::{
-    ExpectedMessage, ExternalMessage, IdentityFieldValue, NotificationMessage, Timestamp,
+    ExpectedMessage, ExternalMessage, FailureReason, IdentityContext, IdentityFieldValue,
+    NotificationMessage, Timestamp,
 };
-use crate::{AdapterConfig, Result};
+use crate::{AdapterConfig, NetworkConfig, Result};
+use std::convert::TryFrom;
 use tokio::time::{interval, Duration};
 use tracing::Instrument;
 
 pub mod admin;
+pub mod discord;
 pub mod email;
+pub mod github;
 pub mod matrix;
 pub mod twitter;
+pub mod web;
 
-pub async fn run_adapters(config: AdapterConfig, db: Database) -> Result<()> {
+pub async fn run_adapters(
+    config: AdapterConfig,
+    networks: Vec<NetworkConfig>,
+    db: Database,
+) -> Result<()> {
     let listener = AdapterListener::new(db.clone()).await;
     // Convenience flat for logging
     let mut started = false;
 
     // Deconstruct struct to get around borrowing violations.
     let AdapterConfig {
-        watcher: _,
         matrix: matrix_config,
         twitter: twitter_config,
         email: email_config,
+        web: web_config,
+        discord: discord_config,
+        github: github_config,
         display_name: _,
     } = config;
 
@@ -42,7 +53,8 @@ pub async fn run_adapters(config: AdapterConfig, db: Database) -> Result<()> {
                 &config.username,
                 &config.password,
                 &config.db_path,
-                db,
+                db.clone(),
+                networks,
                 config.admins.unwrap_or_default(),
             )
             .await?;
@@ -66,17 +78,19 @@ pub async fn run_adapters(config: AdapterConfig, db: Database) -> Result<()> {
 
         async {
             info!("Configuring client");
-            let twitter_client = twitter::TwitterBuilder::new()
+            let twitter_client = twitter::TwitterBuilder::new(db.clone())
                 .consumer_key(config.api_key)
                 .consumer_secret(config.api_secret)
                 .token(config.token)
                 .token_secret(config.token_secret)
+                .api_base(config.api_base)
+                .request_interval(config.request_interval)
+                .messages(config.messages)
                 .build()?;
 
             info!("Starting message adapter");
-            listener
-                .start_message_adapter(twitter_client, config.request_interval)
-                .await;
+            // The client decides when to poll, based on the rate limits.
+            listener.start_message_adapter(twitter_client, 1).await;
 
             Result::Ok(())
         }
@@ -94,23 +108,127 @@ pub async fn run_adapters(config: AdapterConfig, db: Database) -> Result<()> {
         info!(
             smtp_server = config.smtp_server.as_str(),
             imap_server = config.imap_server.as_str(),
+            imap_port = ?config.imap_port,
+            search_strategy = ?config.search_strategy,
+            authentication_policy = ?config.authentication_policy,
+            authserv_id = ?config.authserv_id,
+            idle = ?config.idle,
+            verification_link = config.verification_link.is_some(),
             inbox = config.inbox.as_str(),
             user = config.user.as_str(),
         );
 
         async {
             info!("Configuring client");
-            let email_client = email::EmailClientBuilder::new()
+            let mut email_client = email::EmailClientBuilder::new(db.clone())
                 .smtp_server(config.smtp_server)
                 .imap_server(config.imap_server)
+                .imap_port(config.imap_port)
+                .search_strategy(config.search_strategy)
+                .authentication_policy(config.authentication_policy)
+                .authserv_id(config.authserv_id)
+                .verification_link(config.verification_link)
+                .template(config.template)
                 .email_inbox(config.inbox)
                 .email_user(config.user)
                 .email_password(config.password)
                 .build()?;
 
+            // New messages are pushed by the IDLE session, if supported.
+            let mut interval = config.request_interval;
+            if config.idle.unwrap_or(false) {
+                match email_client.start_idle().await {
+                    Ok(true) => {
+                        info!("Started IDLE session");
+                        interval = 1;
+                    }
+                    Ok(false) => {
+                        warn!("IMAP server does not support IDLE, falling back to polling")
+                    }
+                    Err(err) => error!(
+                        "Failed to start IDLE session, falling back to polling: {:?}",
+                        err
+                    ),
+                }
+            }
+
+            info!("Starting message adapter");
+            listener.start_message_adapter(email_client, interval).await;
+
+            Result::Ok(())
+        }
+        .instrument(span)
+        .await?;
+
+        started = true;
+    }
+
+    // Web client configuration and execution.
+    if let Some(config) = web_config.filter(|config| config.enabled) {
+        let span = info_span!("web_adapter");
+        info!(challenge_path = ?config.challenge_path, dns_resolver = ?config.dns_resolver);
+
+        async {
+            info!("Configuring client");
+            let web_client = web::WebClientBuilder::new(db.clone())
+                .challenge_path(config.challenge_path)
+                .dns_resolver(config.dns_resolver)
+                .build()?;
+
             info!("Starting message adapter");
             listener
-                .start_message_adapter(email_client, config.request_interval)
+                .start_message_adapter(web_client, config.request_interval)
+                .await;
+
+            Result::Ok(())
+        }
+        .instrument(span)
+        .await?;
+
+        started = true;
+    }
+
+    // Discord client configuration and execution.
+    if let Some(config) = discord_config.filter(|config| config.enabled) {
+        let span = info_span!("discord_adapter");
+        info!(guild_id = config.guild_id.as_str(), api_base = ?config.api_base);
+
+        async {
+            info!("Configuring client");
+            let discord_client = discord::DiscordBuilder::new(db.clone())
+                .token(config.token)
+                .guild_id(config.guild_id)
+                .api_base(config.api_base)
+                .build()?;
+
+            info!("Starting message adapter");
+            listener
+                .start_message_adapter(discord_client, config.request_interval)
+                .await;
+
+            Result::Ok(())
+        }
+        .instrument(span)
+        .await?;
+
+        started = true;
+    }
+
+    // GitHub client configuration and execution.
+    if let Some(config) = github_config.filter(|config| config.enabled) {
+        let span = info_span!("github_adapter");
+        info!(api_base = ?config.api_base);
+
+        async {
+            info!("Configuring client");
+            let github_client = github::GitHubBuilder::new(db.clone())
+                .token(config.token)
+                .api_base(config.api_base)
+                .build()?;
+
+            info!("Starting message adapter");
+            listener
+                .start_message_adapter(github_client, config.request_interval)
                 .await;
 
             Result::Ok(())
@@ -135,11 +253,51 @@ pub trait Adapter {
     fn name(&self) -> &'static str;
     async fn fetch_messages(&mut self) -> Result<Vec<ExternalMessage>>;
     async fn send_message(&mut self, to: &str, content: Self::MessageType) -> Result<()>;
+    /// The address to which messages regarding the field are sent, if the
+    /// adapter can contact the owner of that field.
+    fn recipient(&self, _field: &IdentityFieldValue) -> Option<String> {
+        None
+    }
+}
+
+/// Messages which the adapters can send to the users directly.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum AdapterMessage {
+    SecondChallenge(IdentityContext, ExpectedMessage),
+    FieldVerified(IdentityFieldValue),
+    FieldVerificationFailed(IdentityFieldValue, FailureReason),
 }
 
 // Filler for adapters that do not send messages.
-impl From<ExpectedMessage> for () {
-    fn from(_: ExpectedMessage) -> Self {}
+impl TryFrom<AdapterMessage> for () {
+    type Error = AdapterMessage;
+
+    fn try_from(msg: AdapterMessage) -> std::result::Result<Self, Self::Error> {
+        Err(msg)
+    }
+}
+
+/// Reads the body of the response, unless it exceeds `limit` bytes. Returns
+/// `None` in that case, without reading the remainder of the body.
+async fn read_body_limited(mut resp: reqwest::Response, limit: usize) -> Result<Option<String>> {
+    if resp
+        .content_length()
+        .map(|len| len > limit as u64)
+        .unwrap_or(false)
+    {
+        return Ok(None);
+    }
+
+    let mut body = vec![];
+    while let Some(chunk) = resp.chunk().await? {
+        if body.len() + chunk.len() > limit {
+            return Ok(None);
+        }
+
+        body.extend_from_slice(&chunk);
+    }
+
+    Ok(Some(String::from_utf8_lossy(&body).into_owned()))
 }
 
 pub struct AdapterListener {
@@ -153,7 +311,7 @@ impl AdapterListener {
     pub async fn start_message_adapter<T>(&self, mut adapter: T, timeout: u64)
     where
         T: 'static + Adapter + Send,
-        <T as Adapter>::MessageType: From<ExpectedMessage>,
+        <T as Adapter>::MessageType: TryFrom<AdapterMessage>,
     {
         let mut interval = interval(Duration::from_secs(timeout));
 
@@ -184,28 +342,64 @@ impl AdapterListener {
                     }
                 }
 
-                // Check if a second challenge must be sent to the user directly.
+                // Check if any notifications must be sent to the user directly.
                 match db.fetch_events(event_counter).await {
                     Ok((events, new_counter)) => {
                         for event in &events {
-                            if let NotificationMessage::AwaitingSecondChallenge { context, field } =
-                                event
-                            {
-                                if let IdentityFieldValue::Email(to) = field {
-                                    if adapter.name() == "email" {
-                                        debug!("Sending second challenge to {}", to);
-                                        if let Ok(challenge) = db
-                                            .fetch_second_challenge(context, field)
-                                            .await
-                                            .map_err(|err| error!("Failed to fetch second challenge from database: {:?}", err)) {
-                                                let _ = adapter
-                                                    .send_message(to.as_str(), challenge.into())
-                                                    .await
-                                                    .map_err(|err| error!("Failed to send second challenge to {} ({} adapter): {:?}", to, adapter.name(), err));
-                                                }
+                            let (context, field) = match event {
+                                NotificationMessage::AwaitingSecondChallenge { context, field }
+                                | NotificationMessage::FieldVerified { context, field }
+                                | NotificationMessage::FieldVerificationFailed {
+                                    context,
+                                    field,
+                                    ..
+                                } => (context, field),
+                                _ => continue,
+                            };
+
+                            // Skip fields which can't be contacted by this adapter.
+                            let to = match adapter.recipient(field) {
+                                Some(to) => to,
+                                None => continue,
+                            };
+
+                            let message = match event {
+                                NotificationMessage::AwaitingSecondChallenge { .. } => {
+                                    match db.fetch_second_challenge(context, field).await {
+                                        Ok(challenge) => AdapterMessage::SecondChallenge(
+                                            context.clone(),
+                                            challenge,
+                                        ),
+                                        Err(err) => {
+                                            error!("Failed to fetch second challenge from database: {:?}", err);
+                                            continue;
+                                        }
                                     }
                                 }
-                            }
+                                NotificationMessage::FieldVerified { .. } => {
+                                    AdapterMessage::FieldVerified(field.clone())
+                                }
+                                NotificationMessage::FieldVerificationFailed { reason, .. } => {
+                                    AdapterMessage::FieldVerificationFailed(field.clone(), *reason)
+                                }
+                                _ => continue,
+                            };
+
+                            // Skip messages which are not supported by this adapter.
+                            let content = match T::MessageType::try_from(message) {
+                                Ok(content) => content,
+                                Err(_) => continue,
+                            };
+
+                            debug!("Sending message to {} ({} adapter)", to, adapter.name());
+                            let _ = adapter.send_message(&to, content).await.map_err(|err| {
+                                error!(
+                                    "Failed to send message to {} ({} adapter): {:?}",
+                                    to,
+                                    adapter.name(),
+                                    err
+                                )
+                            });
                         }
 
                         event_counter = new_counter;
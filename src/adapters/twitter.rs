@@ -1,18 +1,42 @@
-use crate::adapters::Adapter;
-use crate::primitives::{ExternalMessage, ExternalMessageType, MessageId, Timestamp};
-use crate::Result;
+use crate::adapters::{Adapter, AdapterMessage};
+use crate::database::Database;
+use crate::primitives::{
+    ExternalMessage, ExternalMessageType, FailureReason, IdentityFieldValue, Timestamp,
+};
+use crate::{Result, TwitterMessagesConfig};
 use hmac::{Hmac, Mac};
 use rand::{thread_rng, Rng};
-use reqwest::header::{self, HeaderValue};
-use reqwest::{Client, Request};
+use reqwest::header::{self, HeaderMap, HeaderValue};
+use reqwest::{Client, Request, Response, StatusCode};
 use serde::de::DeserializeOwned;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use sha1::Sha1;
-use std::collections::{HashMap, HashSet};
-use std::convert::{TryFrom, TryInto};
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryFrom;
 use std::time::{SystemTime, UNIX_EPOCH};
 use std::{cmp::Ordering, hash::Hash};
 
+const DEFAULT_API_BASE: &str = "https://api.twitter.com";
+// Maximum amount of DM events per page, as specified by Twitter.
+const PAGE_SIZE: usize = 100;
+// Maximum amount of pages fetched per poll.
+const MAX_PAGES: usize = 5;
+// Upper bound of the backoff after failed requests, in seconds.
+const MAX_BACKOFF: u64 = 900;
+// Maximum amount of direct messages queued while rate limited.
+const MAX_OUTBOX: usize = 1000;
+
+const DEFAULT_SECOND_CHALLENGE: &str =
+    "Insert the following challenge into the web interface: {challenge}";
+const DEFAULT_VERIFIED: &str = "Your Twitter account has been verified.";
+
+// Endpoints, used to keep track of the individual rate limits.
+const DM_EVENTS: &str = "/2/dm_events";
+const USERS: &str = "/2/users";
+const USERS_BY: &str = "/2/users/by";
+const USERS_ME: &str = "/2/users/me";
+const DM_CONVERSATIONS: &str = "/2/dm_conversations/with/:participant_id/messages";
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct ReceivedMessageContext {
     sender: TwitterId,
@@ -20,7 +44,7 @@ pub struct ReceivedMessageContext {
     message: String,
 }
 
-#[derive(Debug, Clone, Eq, Hash, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Eq, Hash, PartialEq)]
 pub struct TwitterId(u64);
 
 impl TwitterId {
@@ -55,20 +79,145 @@ impl TryFrom<String> for TwitterId {
     }
 }
 
+// Ids are encoded as strings by the API.
+impl<'de> Deserialize<'de> for TwitterId {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map(TwitterId)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+impl Serialize for TwitterId {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0.to_string())
+    }
+}
+
+/// The state of the rate limit of an endpoint, as reported by the
+/// `x-rate-limit-*` response headers.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+struct RateLimit {
+    remaining: u64,
+    // UNIX timestamp in seconds.
+    reset: u64,
+}
+
+impl RateLimit {
+    fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let value = |name: &str| -> Option<u64> { headers.get(name)?.to_str().ok()?.parse().ok() };
+
+        Some(RateLimit {
+            remaining: value("x-rate-limit-remaining")?,
+            reset: value("x-rate-limit-reset")?,
+        })
+    }
+    fn is_exhausted(&self, now: u64) -> bool {
+        self.remaining == 0 && self.reset > now
+    }
+    /// Spreads the remaining requests evenly until the limit resets.
+    fn pacing(&self, now: u64) -> u64 {
+        if self.reset > now {
+            (self.reset - now) / (self.remaining + 1)
+        } else {
+            0
+        }
+    }
+}
+
+/// The wording of the direct messages sent to the users. Failures default to
+/// the description of the failure reason.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TwitterMessages {
+    second_challenge: String,
+    verified: String,
+    wrong_challenge: String,
+    unrelated_account: String,
+    stale_challenge: String,
+    already_verified: String,
+}
+
+impl Default for TwitterMessages {
+    fn default() -> Self {
+        TwitterMessages {
+            second_challenge: DEFAULT_SECOND_CHALLENGE.to_string(),
+            verified: DEFAULT_VERIFIED.to_string(),
+            wrong_challenge: FailureReason::WrongChallenge.description().to_string(),
+            unrelated_account: FailureReason::UnrelatedAccount.description().to_string(),
+            stale_challenge: FailureReason::StaleChallenge.description().to_string(),
+            already_verified: FailureReason::AlreadyVerified.description().to_string(),
+        }
+    }
+}
+
+impl From<TwitterMessagesConfig> for TwitterMessages {
+    fn from(config: TwitterMessagesConfig) -> Self {
+        let default = Self::default();
+
+        TwitterMessages {
+            second_challenge: config.second_challenge.unwrap_or(default.second_challenge),
+            verified: config.verified.unwrap_or(default.verified),
+            wrong_challenge: config.wrong_challenge.unwrap_or(default.wrong_challenge),
+            unrelated_account: config
+                .unrelated_account
+                .unwrap_or(default.unrelated_account),
+            stale_challenge: config.stale_challenge.unwrap_or(default.stale_challenge),
+            already_verified: config.already_verified.unwrap_or(default.already_verified),
+        }
+    }
+}
+
+impl TwitterMessages {
+    fn render(&self, msg: &AdapterMessage) -> String {
+        match msg {
+            AdapterMessage::SecondChallenge(_, challenge) => self
+                .second_challenge
+                .replace("{challenge}", &challenge.value),
+            AdapterMessage::FieldVerified(_) => self.verified.clone(),
+            AdapterMessage::FieldVerificationFailed(_, FailureReason::WrongChallenge) => {
+                self.wrong_challenge.clone()
+            }
+            AdapterMessage::FieldVerificationFailed(_, FailureReason::UnrelatedAccount) => {
+                self.unrelated_account.clone()
+            }
+            AdapterMessage::FieldVerificationFailed(_, FailureReason::StaleChallenge) => {
+                self.stale_challenge.clone()
+            }
+            AdapterMessage::FieldVerificationFailed(_, FailureReason::AlreadyVerified) => {
+                self.already_verified.clone()
+            }
+        }
+    }
+}
+
 pub struct TwitterBuilder {
+    db: Database,
     consumer_key: Option<String>,
     consumer_secret: Option<String>,
     token: Option<String>,
     token_secret: Option<String>,
+    api_base: Option<String>,
+    request_interval: Option<u64>,
+    messages: Option<TwitterMessagesConfig>,
 }
 
 impl TwitterBuilder {
-    pub fn new() -> Self {
+    pub fn new(db: Database) -> Self {
         TwitterBuilder {
+            db,
             consumer_key: None,
             consumer_secret: None,
             token: None,
             token_secret: None,
+            api_base: None,
+            request_interval: None,
+            messages: None,
         }
     }
     pub fn consumer_key(mut self, key: String) -> Self {
@@ -87,9 +236,26 @@ impl TwitterBuilder {
         self.token_secret = Some(secret);
         self
     }
+    /// Defaults to `https://api.twitter.com`.
+    pub fn api_base(mut self, api_base: Option<String>) -> Self {
+        self.api_base = api_base;
+        self
+    }
+    /// The minimum interval between polls, in seconds. The actual interval
+    /// depends on the rate limits and on failed requests.
+    pub fn request_interval(mut self, interval: u64) -> Self {
+        self.request_interval = Some(interval);
+        self
+    }
+    /// Uses the default wording if not specified.
+    pub fn messages(mut self, config: Option<TwitterMessagesConfig>) -> Self {
+        self.messages = config;
+        self
+    }
     pub fn build(self) -> Result<TwitterClient> {
         Ok(TwitterClient {
             client: Client::new(),
+            db: self.db,
             consumer_key: self
                 .consumer_key
                 .ok_or_else(|| anyhow!("consumer key name not specified"))?,
@@ -100,12 +266,30 @@ impl TwitterBuilder {
             token_secret: self
                 .token_secret
                 .ok_or_else(|| anyhow!("token secret not specified"))?,
+            api_base: self
+                .api_base
+                .unwrap_or_else(|| DEFAULT_API_BASE.to_string())
+                .trim_end_matches('/')
+                .to_string(),
+            request_interval: self
+                .request_interval
+                .ok_or_else(|| anyhow!("request interval not specified"))?,
+            next_poll: 0,
+            failures: 0,
+            rate_limits: HashMap::new(),
+            own_id: None,
             twitter_ids: HashMap::new(),
-            cache: HashSet::new(),
+            last_message: None,
+            messages: self.messages.map(Into::into).unwrap_or_default(),
+            outbox: VecDeque::new(),
         })
     }
 }
 
+fn cursor_key(own_id: &TwitterId) -> String {
+    format!("twitter:{}", own_id.as_u64())
+}
+
 fn gen_nonce() -> String {
     let random: [u8; 16] = thread_rng().gen();
     hex::encode(random)
@@ -122,32 +306,97 @@ fn gen_timestamp() -> u64 {
 #[derive(Clone)]
 pub struct TwitterClient {
     client: Client,
+    db: Database,
     consumer_key: String,
     consumer_secret: String,
     token: String,
     token_secret: String,
+    api_base: String,
+    request_interval: u64,
+    // UNIX timestamp (in seconds) of the next poll.
+    next_poll: u64,
+    // Consecutive failed polls, used for the backoff.
+    failures: u32,
+    rate_limits: HashMap<&'static str, RateLimit>,
+    // The Id of the bot account, whose own messages are skipped.
+    own_id: Option<TwitterId>,
+    // Twitter Id -> screen name, kept across polls.
     twitter_ids: HashMap<TwitterId, String>,
-    // Keep track of messages.
-    cache: HashSet<MessageId>,
+    // The Id of the last processed DM event, persisted in the database so
+    // that messages are not processed again after a restart.
+    last_message: Option<u64>,
+    messages: TwitterMessages,
+    // Direct messages (recipient and text) which are not sent yet due to the
+    // rate limits.
+    outbox: VecDeque<(String, String)>,
 }
 
 impl TwitterClient {
     async fn request_messages(&mut self) -> Result<Vec<ExternalMessage>> {
+        // The adapter is ticked frequently, the client decides when to poll.
+        if Timestamp::now().raw() < self.next_poll {
+            return Ok(vec![]);
+        }
+
+        let res = self.poll_messages().await;
+        if res.is_ok() {
+            self.failures = 0;
+        } else {
+            self.failures = self.failures.saturating_add(1);
+        }
+
+        self.schedule_poll();
+        res
+    }
+    /// Schedules the next poll based on the request interval, the backoff
+    /// after failures and the rate limit of the DM endpoint.
+    fn schedule_poll(&mut self) {
+        let now = Timestamp::now().raw();
+
+        // Exponential backoff after failures.
+        let mut delay = if self.failures == 0 {
+            self.request_interval
+        } else {
+            self.request_interval
+                .saturating_mul(2u64.saturating_pow(self.failures))
+                .min(std::cmp::max(MAX_BACKOFF, self.request_interval))
+        };
+
+        if let Some(limit) = self.rate_limits.get(DM_EVENTS) {
+            delay = std::cmp::max(delay, limit.pacing(now));
+        }
+
+        debug!("Next Twitter poll in {} seconds", delay);
+        self.next_poll = now + delay;
+    }
+    async fn poll_messages(&mut self) -> Result<Vec<ExternalMessage>> {
         debug!("Requesting Twitter messages");
+
+        if self.own_id.is_none() {
+            let me = self
+                .get_request::<ApiUserMe>(USERS_ME, USERS_ME, &[])
+                .await?;
+
+            self.own_id = Some(me.data.id);
+        }
+
         // Request message on parse those into a simpler type.
-        let mut messages = self
-            .get_request::<ApiMessageRequest>(
-                "https://api.twitter.com/1.1/direct_messages/events/list.json",
-                None,
-            )
-            .await?
-            .parse()?;
+        let last_message = self.last_message().await?;
+        let mut messages = self.fetch_dm_events(last_message).await?;
+
+        // DM event Ids are snowflakes, so newer events have higher Ids.
+        let newest = messages.iter().map(|message| message.id).max();
 
-        // Skip message if it was already processed.
-        messages.retain(|message| !self.cache.contains(&message.id.into()));
+        // Skip message if it was already processed or if it was sent by the
+        // bot itself.
+        messages.retain(|message| {
+            last_message.map(|last| message.id > last).unwrap_or(true)
+                && Some(&message.sender) != self.own_id.as_ref()
+        });
 
         if messages.is_empty() {
             debug!("No new Twitter messages found");
+            self.advance_last_message(newest).await?;
             return Ok(vec![]);
         } else {
             debug!("Fetched {} message(-s)", messages.len());
@@ -169,39 +418,110 @@ impl TwitterClient {
         to_lookup.dedup();
 
         // Lookup Twitter Ids and insert those into the cache.
-        debug!("Looking up Twitter Ids");
         if !to_lookup.is_empty() {
+            debug!("Looking up Twitter Ids");
             let lookup_results = self.lookup_twitter_id(Some(&to_lookup), None).await?;
             self.twitter_ids.extend(lookup_results);
         }
 
-        // Parse all messages into `TwitterMessage`.
+        // Parse all messages into `TwitterMessage`, oldest first.
         let mut parsed_messages = vec![];
-        for message in messages {
-            let sender = self
-                .twitter_ids
-                .get(&message.sender)
-                .ok_or_else(|| anyhow!("Failed to find Twitter handle based on Id"))?
-                .clone();
-
-            let id = message.id.into();
+        for message in messages.into_iter().rev() {
+            let sender = match self.twitter_ids.get(&message.sender) {
+                Some(sender) => sender.clone(),
+                None => {
+                    // E.g. suspended accounts.
+                    warn!("Failed to find Twitter handle of Id {:?}", message.sender);
+                    continue;
+                }
+            };
 
             parsed_messages.push(ExternalMessage {
                 origin: ExternalMessageType::Twitter(sender),
-                id,
+                id: message.id.into(),
                 timestamp: Timestamp::now(),
                 values: vec![message.message.into()],
             });
-
-            self.cache.insert(id);
         }
 
+        self.advance_last_message(newest).await?;
         Ok(parsed_messages)
     }
+    /// The Id of the last processed DM event, loaded from the database on the
+    /// first poll.
+    async fn last_message(&mut self) -> Result<Option<u64>> {
+        if self.last_message.is_none() {
+            if let Some(own_id) = &self.own_id {
+                self.last_message = self
+                    .db
+                    .fetch_message_cursor(&cursor_key(own_id))
+                    .await?
+                    .map(|cursor| cursor.last_id);
+            }
+        }
+
+        Ok(self.last_message)
+    }
+    async fn advance_last_message(&mut self, newest: Option<u64>) -> Result<()> {
+        let last_id = match newest {
+            Some(id) if self.last_message.map(|last| id > last).unwrap_or(true) => id,
+            _ => return Ok(()),
+        };
+
+        if let Some(own_id) = &self.own_id {
+            self.db
+                .advance_message_cursor(&cursor_key(own_id), last_id)
+                .await?;
+        }
+
+        self.last_message = Some(last_id);
+        Ok(())
+    }
+    /// Fetches the most recent DM events, newest first. Pages are fetched
+    /// until an already processed message is found.
+    async fn fetch_dm_events(
+        &mut self,
+        last_message: Option<u64>,
+    ) -> Result<Vec<ReceivedMessageContext>> {
+        let page_size = PAGE_SIZE.to_string();
+        let mut messages = vec![];
+        let mut pagination_token: Option<String> = None;
+
+        for _ in 0..MAX_PAGES {
+            let mut params = vec![
+                ("dm_event.fields", "id,text,sender_id,created_at"),
+                ("event_types", "MessageCreate"),
+                ("max_results", page_size.as_str()),
+            ];
+
+            if let Some(token) = pagination_token.as_deref() {
+                params.push(("pagination_token", token));
+            }
+
+            let page = self
+                .get_request::<ApiDmEvents>(DM_EVENTS, DM_EVENTS, &params)
+                .await?;
+
+            let mut parsed = page.parse();
+            let known = parsed
+                .iter()
+                .any(|message| last_message.map(|last| message.id <= last).unwrap_or(false));
+
+            messages.append(&mut parsed);
+
+            match page.meta.next_token {
+                Some(token) if !known => pagination_token = Some(token),
+                _ => break,
+            }
+        }
+
+        Ok(messages)
+    }
     /// Creates a signature as documented here:
     /// https://developer.twitter.com/en/docs/authentication/oauth-1-0a/creating-a-signature
     fn authenticate_request(
         &self,
+        method: &str,
         url: &str,
         request: &mut Request,
         params: Option<&[(&str, &str)]>,
@@ -236,7 +556,7 @@ impl TwitterClient {
         // Remove the trailing `&`.
         params.pop();
 
-        let base = format!("GET&{}&{}", encode(url), encode(&params));
+        let base = format!("{}&{}&{}", method, encode(url), encode(&params));
 
         // Sign the base string.
         let sign_key = format!(
@@ -274,158 +594,886 @@ impl TwitterClient {
 
         Ok(())
     }
-    async fn get_request<T: DeserializeOwned>(
-        &self,
-        url: &str,
-        params: Option<&[(&str, &str)]>,
-    ) -> Result<T> {
-        let mut full_url = String::from(url);
+    /// Returns an error if the rate limit of the endpoint is exhausted.
+    fn check_rate_limit(&self, endpoint: &str) -> Result<()> {
+        match self.rate_limits.get(endpoint) {
+            Some(limit) if limit.is_exhausted(Timestamp::now().raw()) => Err(anyhow!(
+                "Twitter rate limit of {} exhausted until {}",
+                endpoint,
+                limit.reset
+            )),
+            _ => Ok(()),
+        }
+    }
+    /// Keeps track of the rate limit of the endpoint.
+    async fn process_response(&mut self, endpoint: &'static str, resp: Response) -> Result<String> {
+        if let Some(limit) = RateLimit::from_headers(resp.headers()) {
+            self.rate_limits.insert(endpoint, limit);
+        }
 
-        if let Some(params) = params {
-            full_url.push('?');
-            for (key, val) in params {
-                full_url.push_str(&format!("{}={}&", key, val));
-            }
+        let status = resp.status();
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            // Do not send any further requests until the limit resets, even if
+            // the headers are missing.
+            let fallback = Timestamp::with_offset(self.request_interval).raw();
+            let limit = self.rate_limits.entry(endpoint).or_insert(RateLimit {
+                remaining: 0,
+                reset: fallback,
+            });
+            limit.remaining = 0;
 
-            // Remove trailing `&` or `?` in case "params" is empty.
-            full_url.pop();
+            return Err(anyhow!(
+                "Twitter rate limit of {} exceeded, resets at {}",
+                endpoint,
+                limit.reset
+            ));
         }
 
-        let mut request = self.client.get(&full_url).build()?;
-        self.authenticate_request(url, &mut request, params)?;
-        let resp = self.client.execute(request).await?;
         let txt = resp.text().await?;
-
         debug!("Twitter response: {:?}", txt);
 
+        if !status.is_success() {
+            return Err(anyhow!("Twitter responded with {}: {:?}", status, txt));
+        }
+
+        Ok(txt)
+    }
+    async fn get_request<T: DeserializeOwned>(
+        &mut self,
+        endpoint: &'static str,
+        path: &str,
+        params: &[(&str, &str)],
+    ) -> Result<T> {
+        self.check_rate_limit(endpoint)?;
+
+        let url = format!("{}{}", self.api_base, path);
+        let mut request = self.client.get(&url).query(params).build()?;
+        self.authenticate_request("GET", &url, &mut request, Some(params))?;
+        let resp = self.client.execute(request).await?;
+        let txt = self.process_response(endpoint, resp).await?;
+
         serde_json::from_str::<T>(&txt).map_err(|err| err.into())
     }
+    async fn post_request<T: Serialize>(
+        &mut self,
+        endpoint: &'static str,
+        path: &str,
+        body: &T,
+    ) -> Result<()> {
+        self.check_rate_limit(endpoint)?;
+
+        // The JSON body is not part of the OAuth signature.
+        let url = format!("{}{}", self.api_base, path);
+        let mut request = self.client.post(&url).json(body).build()?;
+        self.authenticate_request("POST", &url, &mut request, None)?;
+        let resp = self.client.execute(request).await?;
+        let _ = self.process_response(endpoint, resp).await?;
+
+        Ok(())
+    }
+    /// Whether sending direct messages is currently prevented by the rate
+    /// limits.
+    fn is_send_limited(&self) -> bool {
+        self.check_rate_limit(DM_CONVERSATIONS).is_err() || self.check_rate_limit(USERS_BY).is_err()
+    }
+    /// Sends the queued direct messages, oldest first, until the rate limits
+    /// are exhausted. Messages that failed for other reasons are dropped.
+    async fn send_queued(&mut self) -> Result<()> {
+        while let Some((to, text)) = self.outbox.front().cloned() {
+            if self.is_send_limited() {
+                debug!(
+                    "Rate limited, {} Twitter message(-s) queued",
+                    self.outbox.len()
+                );
+                break;
+            }
+
+            match self.send_direct_message(&to, &text).await {
+                Ok(()) => {
+                    self.outbox.pop_front();
+                }
+                // Retry once the rate limit resets.
+                Err(_) if self.is_send_limited() => {}
+                Err(err) => {
+                    self.outbox.pop_front();
+                    return Err(anyhow!(
+                        "Failed to send direct message to {}: {:?}",
+                        to,
+                        err
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+    async fn send_direct_message(&mut self, to: &str, text: &str) -> Result<()> {
+        let handle = to.to_lowercase();
+
+        // Lookup the Twitter Id of the recipient, if not cached yet.
+        let cached = self
+            .twitter_ids
+            .iter()
+            .find(|(_, cached)| **cached == handle)
+            .map(|(id, _)| id.clone());
+
+        let recipient = match cached {
+            Some(id) => id,
+            None => {
+                let lookup_results = self.lookup_twitter_id(None, Some(&[&handle])).await?;
+
+                let id = lookup_results
+                    .keys()
+                    .next()
+                    .cloned()
+                    .ok_or_else(|| anyhow!("Failed to find Twitter Id of {}", to))?;
+
+                self.twitter_ids.extend(lookup_results);
+                id
+            }
+        };
+
+        debug!("Sending direct message to {}", to);
+        self.post_request(
+            DM_CONVERSATIONS,
+            &format!("/2/dm_conversations/with/{}/messages", recipient.as_u64()),
+            &serde_json::json!({
+                "text": text,
+            }),
+        )
+        .await
+    }
     async fn lookup_twitter_id(
-        &self,
+        &mut self,
         twitter_ids: Option<&[&TwitterId]>,
         accounts: Option<&[&String]>,
     ) -> Result<HashMap<TwitterId, String>> {
-        let mut params = vec![];
+        let mut user_objects = vec![];
 
         // Lookups for UserIds
-        let mut lookup = String::new();
         if let Some(twitter_ids) = twitter_ids {
-            for twitter_id in twitter_ids {
-                lookup.push_str(&twitter_id.as_u64().to_string());
-                lookup.push(',');
+            for chunk in twitter_ids.chunks(PAGE_SIZE) {
+                let lookup = chunk
+                    .iter()
+                    .map(|id| id.as_u64().to_string())
+                    .collect::<Vec<String>>()
+                    .join(",");
+
+                let mut users = self
+                    .get_request::<ApiUsers>(USERS, USERS, &[("ids", lookup.as_str())])
+                    .await?;
+
+                user_objects.append(&mut users.data);
             }
-
-            // Remove trailing `,`.
-            lookup.pop();
-
-            params.push(("user_id", lookup.as_str()))
         }
 
         // Lookups for Accounts
-        let mut lookup = String::new();
         if let Some(accounts) = accounts {
-            for account in accounts {
-                lookup.push_str(&account.as_str().replace('@', ""));
-                lookup.push(',');
+            for chunk in accounts.chunks(PAGE_SIZE) {
+                let lookup = chunk
+                    .iter()
+                    .map(|account| account.replace('@', ""))
+                    .collect::<Vec<String>>()
+                    .join(",");
+
+                let mut users = self
+                    .get_request::<ApiUsers>(USERS_BY, USERS_BY, &[("usernames", lookup.as_str())])
+                    .await?;
+
+                user_objects.append(&mut users.data);
             }
-
-            // Remove trailing `,`.
-            lookup.pop();
-
-            params.push(("screen_name", lookup.as_str()))
-        }
-
-        #[derive(Deserialize)]
-        // Only `screen_name` required.
-        struct UserObject {
-            id: TwitterId,
-            screen_name: String,
-        }
-
-        debug!("Params: {:?}", params);
-
-        let user_objects = self
-            .get_request::<Vec<UserObject>>(
-                "https://api.twitter.com/1.1/users/lookup.json",
-                Some(&params),
-            )
-            .await?;
-
-        if user_objects.is_empty() {
-            return Err(anyhow!("unrecognized data"));
         }
 
         Ok(user_objects
             .into_iter()
-            .map(|obj| (obj.id, format!("@{}", obj.screen_name.to_lowercase())))
+            .map(|obj| (obj.id, format!("@{}", obj.username.to_lowercase())))
             .collect())
     }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
-struct ApiMessageRequest {
-    events: Vec<ApiEvent>,
+struct ApiUser {
+    id: TwitterId,
+    username: String,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
-struct ApiEvent {
-    #[serde(rename = "type")]
-    t_type: String,
-    id: String,
-    created_timestamp: Option<String>,
-    message_create: ApiMessageCreate,
+struct ApiUserMe {
+    data: ApiUser,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
-struct ApiMessageCreate {
-    target: ApiTarget,
-    sender_id: Option<String>,
-    message_data: ApiMessageData,
+struct ApiUsers {
+    // Not present if none of the users were found.
+    #[serde(default)]
+    data: Vec<ApiUser>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
-struct ApiTarget {
-    recipient_id: String,
+struct ApiDmEvents {
+    #[serde(default)]
+    data: Vec<ApiDmEvent>,
+    #[serde(default)]
+    meta: ApiMeta,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-struct ApiMessageData {
-    text: String,
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct ApiDmEvent {
+    id: String,
+    // Not present for attachments without any text.
+    text: Option<String>,
+    sender_id: Option<TwitterId>,
+    created_at: Option<String>,
 }
 
-impl ApiMessageRequest {
-    fn parse(self) -> Result<Vec<ReceivedMessageContext>> {
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct ApiMeta {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_token: Option<String>,
+}
+
+impl ApiDmEvents {
+    /// Skips unrecognized events, so those do not block the following ones.
+    fn parse(&self) -> Vec<ReceivedMessageContext> {
         let mut messages = vec![];
 
-        for event in self.events {
-            let message = ReceivedMessageContext {
-                sender: event
-                    .message_create
-                    .sender_id
-                    .ok_or_else(|| anyhow!("unrecognized data"))?
-                    .try_into()?,
-                message: event.message_create.message_data.text,
-                id: event.id.parse().map_err(|_| anyhow!("unrecognized data"))?,
+        for event in &self.data {
+            let text = match &event.text {
+                Some(text) => text.clone(),
+                None => continue,
+            };
+
+            let (sender, id) = match (event.sender_id.clone(), event.id.parse::<u64>()) {
+                (Some(sender), Ok(id)) => (sender, id),
+                _ => {
+                    warn!(
+                        "Skipping unrecognized Twitter DM event with Id {}",
+                        event.id
+                    );
+                    continue;
+                }
             };
 
-            messages.push(message);
+            messages.push(ReceivedMessageContext {
+                sender,
+                message: text,
+                id,
+            });
         }
 
-        Ok(messages)
+        messages
     }
 }
 
 #[async_trait]
 impl Adapter for TwitterClient {
-    type MessageType = ();
+    type MessageType = AdapterMessage;
 
     fn name(&self) -> &'static str {
         "Twitter"
     }
     async fn fetch_messages(&mut self) -> Result<Vec<ExternalMessage>> {
+        // Send the messages that were queued due to the rate limits.
+        if let Err(err) = self.send_queued().await {
+            error!("{:?}", err);
+        }
+
         self.request_messages().await
     }
-    async fn send_message(&mut self, _to: &str, _content: Self::MessageType) -> Result<()> {
-        unimplemented!()
+    async fn send_message(&mut self, to: &str, content: Self::MessageType) -> Result<()> {
+        if self.outbox.len() >= MAX_OUTBOX {
+            return Err(anyhow!(
+                "Too many queued Twitter messages, dropping message"
+            ));
+        }
+
+        self.outbox
+            .push_back((to.to_string(), self.messages.render(&content)));
+
+        self.send_queued().await
+    }
+    fn recipient(&self, field: &IdentityFieldValue) -> Option<String> {
+        match field {
+            IdentityFieldValue::Twitter(to) => Some(to.clone()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::challenge::ChallengeFormat;
+    use crate::primitives::{ExpectedMessage, IdentityContext, MessagePart};
+    use actix_test::TestServer;
+    use actix_web::{web, App, HttpResponse, HttpResponseBuilder};
+    use std::sync::{Arc, Mutex};
+
+    const BOT_ID: u64 = 1;
+
+    #[derive(Default)]
+    struct MockState {
+        users: Vec<ApiUser>,
+        // Oldest first.
+        events: Vec<ApiDmEvent>,
+        sent: Vec<(TwitterId, String)>,
+        // Received requests, by path.
+        requests: Vec<String>,
+        // Reported via the `x-rate-limit-*` headers.
+        rate_limit: Option<RateLimit>,
+        // Responds with `429 Too Many Requests` if set.
+        exceeded: bool,
+        next_id: u64,
+    }
+
+    /// Stand-in for the Twitter API, so the tests run offline.
+    #[derive(Clone, Default)]
+    pub struct TwitterMock {
+        state: Arc<Mutex<MockState>>,
+    }
+
+    #[derive(Deserialize)]
+    struct DmEventsQuery {
+        max_results: usize,
+        pagination_token: Option<String>,
+    }
+
+    #[derive(Deserialize)]
+    struct UsersQuery {
+        ids: String,
+    }
+
+    #[derive(Deserialize)]
+    struct UsersByQuery {
+        usernames: String,
+    }
+
+    #[derive(Deserialize)]
+    struct DmBody {
+        text: String,
+    }
+
+    /// Tracks the request and prepares the response, including the rate limit
+    /// headers.
+    fn respond(state: &mut MockState, path: &str) -> HttpResponseBuilder {
+        state.requests.push(path.to_string());
+
+        let mut builder = if state.exceeded {
+            HttpResponse::TooManyRequests()
+        } else {
+            HttpResponse::Ok()
+        };
+
+        if let Some(limit) = state.rate_limit {
+            builder
+                .insert_header(("x-rate-limit-remaining", limit.remaining.to_string()))
+                .insert_header(("x-rate-limit-reset", limit.reset.to_string()));
+        }
+
+        builder
+    }
+
+    async fn users_me(state: web::Data<Mutex<MockState>>) -> HttpResponse {
+        let mut state = state.lock().unwrap();
+        let mut builder = respond(&mut state, USERS_ME);
+        if state.exceeded {
+            return builder.finish();
+        }
+
+        builder.json(ApiUserMe {
+            data: ApiUser {
+                id: TwitterId(BOT_ID),
+                username: "registrar".to_string(),
+            },
+        })
+    }
+
+    async fn dm_events(
+        state: web::Data<Mutex<MockState>>,
+        query: web::Query<DmEventsQuery>,
+    ) -> HttpResponse {
+        let mut state = state.lock().unwrap();
+        let mut builder = respond(&mut state, DM_EVENTS);
+        if state.exceeded {
+            return builder.finish();
+        }
+
+        // Newest first, the pagination token is the offset.
+        let offset: usize = query
+            .pagination_token
+            .as_ref()
+            .map(|token| token.parse().unwrap())
+            .unwrap_or(0);
+
+        let events: Vec<ApiDmEvent> = state
+            .events
+            .iter()
+            .rev()
+            .skip(offset)
+            .take(query.max_results)
+            .cloned()
+            .collect();
+
+        let next = offset + query.max_results;
+        builder.json(ApiDmEvents {
+            data: events,
+            meta: ApiMeta {
+                next_token: if next < state.events.len() {
+                    Some(next.to_string())
+                } else {
+                    None
+                },
+            },
+        })
+    }
+
+    async fn users(
+        state: web::Data<Mutex<MockState>>,
+        query: web::Query<UsersQuery>,
+    ) -> HttpResponse {
+        let mut state = state.lock().unwrap();
+        let mut builder = respond(&mut state, USERS);
+        if state.exceeded {
+            return builder.finish();
+        }
+
+        let ids: Vec<&str> = query.ids.split(',').collect();
+        let data: Vec<&ApiUser> = state
+            .users
+            .iter()
+            .filter(|user| ids.contains(&user.id.as_u64().to_string().as_str()))
+            .collect();
+
+        builder.json(serde_json::json!({ "data": data }))
+    }
+
+    async fn users_by(
+        state: web::Data<Mutex<MockState>>,
+        query: web::Query<UsersByQuery>,
+    ) -> HttpResponse {
+        let mut state = state.lock().unwrap();
+        let mut builder = respond(&mut state, USERS_BY);
+        if state.exceeded {
+            return builder.finish();
+        }
+
+        let usernames: Vec<String> = query
+            .usernames
+            .split(',')
+            .map(|name| name.to_lowercase())
+            .collect();
+        let data: Vec<&ApiUser> = state
+            .users
+            .iter()
+            .filter(|user| usernames.contains(&user.username.to_lowercase()))
+            .collect();
+
+        builder.json(serde_json::json!({ "data": data }))
+    }
+
+    async fn send_dm(
+        state: web::Data<Mutex<MockState>>,
+        participant: web::Path<u64>,
+        body: web::Json<DmBody>,
+    ) -> HttpResponse {
+        let mut state = state.lock().unwrap();
+        let mut builder = respond(&mut state, DM_CONVERSATIONS);
+        if state.exceeded {
+            return builder.finish();
+        }
+
+        state
+            .sent
+            .push((TwitterId(participant.into_inner()), body.into_inner().text));
+
+        builder.status(StatusCode::CREATED).json(serde_json::json!({
+            "data": {
+                "dm_conversation_id": "1-2",
+                "dm_event_id": "3",
+            }
+        }))
+    }
+
+    impl TwitterMock {
+        pub fn new() -> Self {
+            Self::default()
+        }
+        pub fn add_user(&self, id: u64, username: &str) {
+            self.state.lock().unwrap().users.push(ApiUser {
+                id: TwitterId(id),
+                username: username.to_string(),
+            });
+        }
+        /// Sends a DM to the bot.
+        pub fn send_dm(&self, sender: u64, text: &str) {
+            let mut state = self.state.lock().unwrap();
+            state.next_id += 1;
+
+            let id = (1000 + state.next_id).to_string();
+            state.events.push(ApiDmEvent {
+                id,
+                text: Some(text.to_string()),
+                sender_id: Some(TwitterId(sender)),
+                created_at: None,
+            });
+        }
+        pub fn sent(&self) -> Vec<(TwitterId, String)> {
+            self.state.lock().unwrap().sent.clone()
+        }
+        pub fn requests(&self, path: &str) -> usize {
+            self.state
+                .lock()
+                .unwrap()
+                .requests
+                .iter()
+                .filter(|req| *req == path)
+                .count()
+        }
+        pub fn set_rate_limit(&self, limit: Option<(u64, u64)>) {
+            self.state.lock().unwrap().rate_limit =
+                limit.map(|(remaining, reset)| RateLimit { remaining, reset });
+        }
+        pub fn set_exceeded(&self, exceeded: bool) {
+            self.state.lock().unwrap().exceeded = exceeded;
+        }
+        pub fn start(&self) -> TestServer {
+            let state = Arc::clone(&self.state);
+
+            actix_test::start(move || {
+                App::new()
+                    .app_data(web::Data::from(Arc::clone(&state)))
+                    .route(USERS_ME, web::get().to(users_me))
+                    .route(USERS_BY, web::get().to(users_by))
+                    .route(USERS, web::get().to(users))
+                    .route(DM_EVENTS, web::get().to(dm_events))
+                    .route(
+                        "/2/dm_conversations/with/{participant}/messages",
+                        web::post().to(send_dm),
+                    )
+            })
+        }
+    }
+
+    pub fn twitter_client(server: &TestServer, db: Database) -> TwitterClient {
+        TwitterBuilder::new(db)
+            .consumer_key("key".to_string())
+            .consumer_secret("secret".to_string())
+            .token("token".to_string())
+            .token_secret("secret".to_string())
+            .api_base(Some(server.url("")))
+            .request_interval(10)
+            .build()
+            .unwrap()
+    }
+
+    fn texts(messages: &[ExternalMessage]) -> Vec<(ExternalMessageType, Vec<MessagePart>)> {
+        messages
+            .iter()
+            .map(|message| (message.origin.clone(), message.values.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn rate_limit_pacing() {
+        let limit = RateLimit {
+            remaining: 9,
+            reset: 1100,
+        };
+
+        // Ten requests left in 100 seconds.
+        assert_eq!(limit.pacing(1000), 10);
+        assert!(!limit.is_exhausted(1000));
+
+        let limit = RateLimit {
+            remaining: 0,
+            reset: 1100,
+        };
+
+        // Wait until the limit resets.
+        assert_eq!(limit.pacing(1000), 100);
+        assert!(limit.is_exhausted(1000));
+        assert!(!limit.is_exhausted(1100));
+        assert_eq!(limit.pacing(1200), 0);
+    }
+
+    #[test]
+    fn parse_dm_events() {
+        let event = |id: &str, sender: Option<u64>| ApiDmEvent {
+            id: id.to_string(),
+            text: Some("challenge".to_string()),
+            sender_id: sender.map(TwitterId),
+            created_at: None,
+        };
+
+        let page = ApiDmEvents {
+            data: vec![
+                event("1", None),
+                event("invalid", Some(10)),
+                event("3", Some(10)),
+            ],
+            meta: ApiMeta::default(),
+        };
+
+        // Unrecognized events are skipped.
+        let messages = page.parse();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].id, 3);
+        assert_eq!(messages[0].sender, TwitterId(10));
+    }
+
+    #[actix::test]
+    async fn fetch_messages_paginated() {
+        let mock = TwitterMock::new();
+        mock.add_user(10, "Alice");
+        mock.add_user(11, "bob");
+
+        for i in 0..150 {
+            mock.send_dm(10 + i % 2, &format!("message_{}", i));
+        }
+
+        // Sent by the bot itself.
+        mock.send_dm(BOT_ID, "reply");
+
+        let server = mock.start();
+        let db = crate::tests::new_db().await;
+        let mut client = twitter_client(&server, db.clone());
+
+        // Oldest first.
+        let messages = client.poll_messages().await.unwrap();
+        assert_eq!(messages.len(), 150);
+        assert_eq!(
+            texts(&messages[..2]),
+            vec![
+                (
+                    ExternalMessageType::Twitter("@alice".to_string()),
+                    vec!["message_0".to_string().into()]
+                ),
+                (
+                    ExternalMessageType::Twitter("@bob".to_string()),
+                    vec!["message_1".to_string().into()]
+                ),
+            ]
+        );
+        assert_eq!(mock.requests(DM_EVENTS), 2);
+
+        // Only the first page is fetched if it contains known messages.
+        let messages = client.poll_messages().await.unwrap();
+        assert!(messages.is_empty());
+        assert_eq!(mock.requests(DM_EVENTS), 3);
+
+        mock.send_dm(11, "new_message");
+        let messages = client.poll_messages().await.unwrap();
+        assert_eq!(
+            texts(&messages),
+            vec![(
+                ExternalMessageType::Twitter("@bob".to_string()),
+                vec!["new_message".to_string().into()]
+            )]
+        );
+
+        // Twitter Ids are cached across polls.
+        assert_eq!(mock.requests(USERS), 1);
+        assert_eq!(mock.requests(USERS_ME), 1);
+
+        // Processed messages are not fetched again after a restart.
+        let mut client = twitter_client(&server, db);
+        let messages = client.poll_messages().await.unwrap();
+        assert!(messages.is_empty());
+        assert_eq!(mock.requests(DM_EVENTS), 5);
+    }
+
+    #[actix::test]
+    async fn fetch_messages_rate_limited() {
+        let mock = TwitterMock::new();
+        mock.add_user(10, "alice");
+        mock.send_dm(10, "message");
+
+        let reset = Timestamp::with_offset(100).raw();
+        mock.set_rate_limit(Some((0, reset)));
+
+        let server = mock.start();
+        let mut client = twitter_client(&server, crate::tests::new_db().await);
+
+        let messages = client.request_messages().await.unwrap();
+        assert_eq!(messages.len(), 1);
+
+        // No further polls until the limit resets.
+        assert!(client.next_poll >= reset);
+        assert!(client.request_messages().await.unwrap().is_empty());
+        assert_eq!(mock.requests(DM_EVENTS), 1);
+
+        // The requests are paced according to the remaining requests.
+        let reset = Timestamp::with_offset(1000).raw();
+        mock.set_rate_limit(Some((4, reset)));
+        client.next_poll = 0;
+        client.rate_limits.clear();
+
+        client.request_messages().await.unwrap();
+        assert!(client.next_poll >= Timestamp::with_offset(199).raw());
+        assert!(client.next_poll <= Timestamp::with_offset(201).raw());
+
+        // Exceeded, without any headers.
+        mock.set_rate_limit(None);
+        mock.set_exceeded(true);
+        client.next_poll = 0;
+        client.rate_limits.clear();
+
+        assert!(client.request_messages().await.is_err());
+        assert_eq!(client.failures, 1);
+        assert!(client.check_rate_limit(DM_EVENTS).is_err());
+
+        // Backoff after failures.
+        assert!(client.next_poll >= Timestamp::with_offset(20).raw());
+        client.next_poll = 0;
+        assert!(client.request_messages().await.is_err());
+        assert_eq!(client.failures, 2);
+        assert!(client.next_poll >= Timestamp::with_offset(40).raw());
+
+        // The exhausted limit prevents any requests.
+        assert_eq!(mock.requests(DM_EVENTS), 3);
+    }
+
+    #[test]
+    fn render_messages() {
+        let challenge = ExpectedMessage {
+            value: "1234".to_string(),
+            is_verified: false,
+            issued_at: None,
+            format: ChallengeFormat::Hex,
+        };
+        let field = IdentityFieldValue::Twitter("@alice".to_string());
+
+        let messages = TwitterMessages::default();
+        assert_eq!(
+            messages.render(&AdapterMessage::SecondChallenge(
+                IdentityContext::alice(),
+                challenge.clone()
+            )),
+            "Insert the following challenge into the web interface: 1234"
+        );
+        assert_eq!(
+            messages.render(&AdapterMessage::FieldVerificationFailed(
+                field.clone(),
+                FailureReason::StaleChallenge
+            )),
+            FailureReason::StaleChallenge.description()
+        );
+
+        // Unspecified messages fall back to the defaults.
+        let messages = TwitterMessages::from(TwitterMessagesConfig {
+            second_challenge: Some("Challenge: {challenge}".to_string()),
+            verified: None,
+            wrong_challenge: Some("Wrong challenge".to_string()),
+            unrelated_account: None,
+            stale_challenge: None,
+            already_verified: None,
+        });
+        assert_eq!(
+            messages.render(&AdapterMessage::SecondChallenge(
+                IdentityContext::alice(),
+                challenge
+            )),
+            "Challenge: 1234"
+        );
+        assert_eq!(
+            messages.render(&AdapterMessage::FieldVerified(field.clone())),
+            DEFAULT_VERIFIED
+        );
+        assert_eq!(
+            messages.render(&AdapterMessage::FieldVerificationFailed(
+                field,
+                FailureReason::WrongChallenge
+            )),
+            "Wrong challenge"
+        );
+    }
+
+    #[actix::test]
+    async fn send_messages_rate_limited() {
+        let mock = TwitterMock::new();
+        mock.add_user(10, "alice");
+        let server = mock.start();
+
+        let mut client = twitter_client(&server, crate::tests::new_db().await);
+        let field = IdentityFieldValue::Twitter("@alice".to_string());
+
+        // The last request before the limit resets.
+        mock.set_rate_limit(Some((0, Timestamp::with_offset(100).raw())));
+
+        client
+            .send_message("@alice", AdapterMessage::FieldVerified(field.clone()))
+            .await
+            .unwrap();
+
+        // Queued until the limit resets.
+        client
+            .send_message(
+                "@alice",
+                AdapterMessage::FieldVerificationFailed(
+                    field.clone(),
+                    FailureReason::WrongChallenge,
+                ),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            mock.sent(),
+            vec![(TwitterId(10), DEFAULT_VERIFIED.to_string())]
+        );
+        assert_eq!(client.outbox.len(), 1);
+
+        // Exceeded, the message remains queued.
+        mock.set_rate_limit(None);
+        mock.set_exceeded(true);
+        client.rate_limits.clear();
+
+        client.fetch_messages().await.unwrap_err();
+        assert_eq!(client.outbox.len(), 1);
+        assert_eq!(mock.requests(DM_CONVERSATIONS), 2);
+
+        // Sent on the next tick after the limit resets.
+        mock.set_exceeded(false);
+        client.rate_limits.clear();
+        client.next_poll = 0;
+
+        client.fetch_messages().await.unwrap();
+        assert!(client.outbox.is_empty());
+        assert_eq!(
+            mock.sent(),
+            vec![
+                (TwitterId(10), DEFAULT_VERIFIED.to_string()),
+                (
+                    TwitterId(10),
+                    FailureReason::WrongChallenge.description().to_string()
+                ),
+            ]
+        );
+    }
+
+    #[actix::test]
+    async fn send_direct_messages() {
+        let mock = TwitterMock::new();
+        mock.add_user(10, "Alice");
+        let server = mock.start();
+
+        let mut client = twitter_client(&server, crate::tests::new_db().await);
+
+        // Unknown user.
+        assert!(client.send_direct_message("@bob", "hello").await.is_err());
+
+        client.send_direct_message("@Alice", "hello").await.unwrap();
+        client.send_direct_message("@alice", "again").await.unwrap();
+
+        assert_eq!(
+            mock.sent(),
+            vec![
+                (TwitterId(10), "hello".to_string()),
+                (TwitterId(10), "again".to_string()),
+            ]
+        );
+
+        // The Twitter Id is cached.
+        assert_eq!(mock.requests(USERS_BY), 2);
     }
 }
@@ -0,0 +1,450 @@
+use crate::adapters::admin::RawFieldName;
+use crate::adapters::{read_body_limited, Adapter};
+use crate::primitives::{ExternalMessage, ExternalMessageType, IdentityFieldValue, Timestamp};
+use crate::{Database, Result};
+use reqwest::redirect::Policy;
+use reqwest::Client;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use trust_dns_resolver::config::{NameServerConfigGroup, ResolverConfig, ResolverOpts};
+use trust_dns_resolver::TokioAsyncResolver;
+use url::{Host, Url};
+
+const DEFAULT_CHALLENGE_PATH: &str = "/.well-known/polkadot-identity.txt";
+const DNS_RECORD_PREFIX: &str = "_polkadot-identity";
+const REQUEST_TIMEOUT: u64 = 10;
+// Challenge files are expected to be tiny. Anything bigger is ignored.
+const MAX_CONTENT_LENGTH: usize = 4096;
+
+pub struct WebClientBuilder {
+    db: Database,
+    challenge_path: Option<String>,
+    dns_resolver: Option<String>,
+    scheme: String,
+    allow_private_addresses: bool,
+}
+
+impl WebClientBuilder {
+    pub fn new(db: Database) -> Self {
+        WebClientBuilder {
+            db,
+            challenge_path: None,
+            dns_resolver: None,
+            scheme: "https".to_string(),
+            allow_private_addresses: false,
+        }
+    }
+    pub fn challenge_path(mut self, path: Option<String>) -> Self {
+        self.challenge_path = path;
+        self
+    }
+    /// The address of the DNS resolver, e.g. `1.1.1.1:53`. The system
+    /// configuration is used if not specified.
+    pub fn dns_resolver(mut self, resolver: Option<String>) -> Self {
+        self.dns_resolver = resolver;
+        self
+    }
+    #[cfg(test)]
+    pub fn scheme(mut self, scheme: &str) -> Self {
+        self.scheme = scheme.to_string();
+        self
+    }
+    #[cfg(test)]
+    pub fn allow_private_addresses(mut self) -> Self {
+        self.allow_private_addresses = true;
+        self
+    }
+    pub fn build(self) -> Result<WebClient> {
+        let mut path = self
+            .challenge_path
+            .unwrap_or_else(|| DEFAULT_CHALLENGE_PATH.to_string());
+
+        if !path.starts_with('/') {
+            path.insert(0, '/');
+        }
+
+        let resolver = if let Some(addr) = self.dns_resolver {
+            let addr: SocketAddr = addr
+                .parse()
+                .map_err(|_| anyhow!("invalid DNS resolver address: {}", addr))?;
+
+            TokioAsyncResolver::tokio(
+                ResolverConfig::from_parts(
+                    None,
+                    vec![],
+                    NameServerConfigGroup::from_ips_clear(&[addr.ip()], addr.port(), true),
+                ),
+                ResolverOpts::default(),
+            )?
+        } else {
+            TokioAsyncResolver::tokio_from_system_conf()?
+        };
+
+        Ok(WebClient {
+            db: self.db,
+            resolver,
+            challenge_path: path,
+            scheme: self.scheme,
+            allow_private_addresses: self.allow_private_addresses,
+            cache: HashMap::new(),
+        })
+    }
+}
+
+/// Looks up the challenge on the domains of all pending `Web` fields, either as
+/// a challenge file or as a `_polkadot-identity.<DOMAIN>` TXT record.
+pub struct WebClient {
+    resolver: TokioAsyncResolver,
+    db: Database,
+    challenge_path: String,
+    scheme: String,
+    allow_private_addresses: bool,
+    // Keep track of the last fetched content of each domain, so the same
+    // (invalid) content does not count as a failed attempt on every request.
+    cache: HashMap<String, Vec<String>>,
+}
+
+/// Strips the scheme and any path of the specified web address, leaving only
+/// the domain (and port, if any).
+fn extract_domain(web: &str) -> Option<&str> {
+    let web = web.trim();
+    let web = web
+        .strip_prefix("https://")
+        .or_else(|| web.strip_prefix("http://"))
+        .unwrap_or(web);
+
+    web.split('/').next().filter(|domain| !domain.is_empty())
+}
+
+/// Parses the host of the domain, which may include a port. IPv6 addresses
+/// must be enclosed in brackets, e.g. `[::1]:8080`.
+fn parse_host(domain: &str) -> Result<Host> {
+    Url::parse(&format!("http://{}", domain))?
+        .host()
+        .map(|host| host.to_owned())
+        .ok_or_else(|| anyhow!("no host specified in {}", domain))
+}
+
+/// Whether the address is reachable from the public internet. The challenge
+/// files must not be fetched from the local or internal network.
+fn is_public_address(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let octets = ip.octets();
+
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                // Shared address space (100.64.0.0/10).
+                || (octets[0] == 100 && (octets[1] & 0b1100_0000) == 64))
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4() {
+                // IPv4-mapped and -compatible addresses.
+                if ip.octets()[..3] != [0, 0, 0] {
+                    return is_public_address(IpAddr::V4(ip));
+                }
+            }
+
+            let first = ip.segments()[0];
+
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                // Unique local addresses (fc00::/7).
+                || (first & 0xfe00) == 0xfc00
+                // Link-local addresses (fe80::/10).
+                || (first & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
+impl WebClient {
+    /// Resolves the host of the domain and makes sure that it points to a
+    /// public address.
+    async fn resolve_public_address(&self, domain: &str) -> Result<(String, IpAddr)> {
+        let host = parse_host(domain)?;
+
+        let ip = match &host {
+            Host::Ipv4(ip) => IpAddr::V4(*ip),
+            Host::Ipv6(ip) => IpAddr::V6(*ip),
+            Host::Domain(name) => self
+                .resolver
+                .lookup_ip(name.as_str())
+                .await?
+                .iter()
+                .next()
+                .ok_or_else(|| anyhow!("no address found for {}", host))?,
+        };
+
+        if !self.allow_private_addresses && !is_public_address(ip) {
+            return Err(anyhow!("{} resolves to non-public address {}", host, ip));
+        }
+
+        Ok((host.to_string(), ip))
+    }
+    async fn fetch_challenge_file(&self, domain: &str) -> Result<String> {
+        let url = format!("{}://{}{}", self.scheme, domain, self.challenge_path);
+        let (host, ip) = self.resolve_public_address(domain).await?;
+
+        // Connect to the verified address only, so the host cannot resolve to
+        // a different address in the meantime. Redirects are not followed,
+        // since those could point to any address.
+        let client = Client::builder()
+            .timeout(Duration::from_secs(REQUEST_TIMEOUT))
+            .redirect(Policy::none())
+            .resolve(&host, SocketAddr::new(ip, 0))
+            .build()?;
+
+        let resp = client.get(&url).send().await?.error_for_status()?;
+        read_body_limited(resp, MAX_CONTENT_LENGTH)
+            .await?
+            .ok_or_else(|| anyhow!("challenge file at {} is too large", url))
+    }
+    async fn lookup_txt_records(&self, domain: &str) -> Result<Vec<String>> {
+        // Ports are irrelevant for DNS and IP addresses have no records.
+        let host = match parse_host(domain)? {
+            Host::Domain(name) => name,
+            _ => return Err(anyhow!("{} is not a domain name", domain)),
+        };
+        // Fully qualified, no search domains are appended.
+        let name = format!("{}.{}.", DNS_RECORD_PREFIX, host);
+
+        let lookup = self.resolver.txt_lookup(name.as_str()).await?;
+
+        Ok(lookup
+            .iter()
+            .map(|txt| {
+                // A single record can consist of multiple strings.
+                txt.txt_data()
+                    .iter()
+                    .map(|data| String::from_utf8_lossy(data))
+                    .collect::<String>()
+            })
+            .collect())
+    }
+    async fn request_messages(&mut self) -> Result<Vec<ExternalMessage>> {
+        let fields = self.db.fetch_unverified_fields(&RawFieldName::Web).await?;
+
+        let mut messages = vec![];
+        for field in fields {
+            let web = match field {
+                IdentityFieldValue::Web(web) => web,
+                _ => continue,
+            };
+
+            let domain = if let Some(domain) = extract_domain(&web) {
+                domain
+            } else {
+                debug!("Skipping invalid web address: {}", web);
+                continue;
+            };
+
+            let mut content = vec![];
+
+            match self.fetch_challenge_file(domain).await {
+                Ok(file) => content.push(file),
+                // Most likely the user did not upload the file.
+                Err(err) => debug!("Failed to fetch challenge file from {}: {:?}", domain, err),
+            }
+
+            match self.lookup_txt_records(domain).await {
+                Ok(mut records) => content.append(&mut records),
+                // Most likely the user did not create the record.
+                Err(err) => debug!("Failed to lookup TXT records of {}: {:?}", domain, err),
+            }
+
+            // Skip if nothing was found or the content was already processed.
+            if content.is_empty() || self.cache.get(&web) == Some(&content) {
+                continue;
+            }
+
+            debug!("Fetched challenge content from {}", domain);
+
+            self.cache.insert(web.clone(), content.clone());
+            messages.push(ExternalMessage {
+                origin: ExternalMessageType::Web(web),
+                // A message UID is not relevant, the content is tracked by
+                // the cache.
+                id: 0u32.into(),
+                timestamp: Timestamp::now(),
+                values: content.into_iter().map(|c| c.into()).collect(),
+            });
+        }
+
+        Ok(messages)
+    }
+}
+
+#[async_trait]
+impl Adapter for WebClient {
+    type MessageType = ();
+
+    fn name(&self) -> &'static str {
+        "web"
+    }
+    async fn fetch_messages(&mut self) -> Result<Vec<ExternalMessage>> {
+        self.request_messages().await
+    }
+    async fn send_message(&mut self, _to: &str, _content: Self::MessageType) -> Result<()> {
+        // Websites cannot be contacted.
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::net::UdpSocket;
+    use std::sync::{Arc, Mutex};
+    use trust_dns_resolver::proto::op::{Message, MessageType, ResponseCode};
+    use trust_dns_resolver::proto::rr::rdata::TXT;
+    use trust_dns_resolver::proto::rr::{RData, Record};
+
+    /// Local DNS server which answers queries with the configured TXT records
+    /// (keyed by the fully qualified name) and `NXDOMAIN` otherwise.
+    pub fn start_dns_stub(records: Arc<Mutex<HashMap<String, Vec<String>>>>) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+
+        std::thread::spawn(move || {
+            let mut buf = [0; 512];
+            loop {
+                let (len, from) = socket.recv_from(&mut buf).unwrap();
+                let req = Message::from_vec(&buf[..len]).unwrap();
+
+                let mut resp = Message::new();
+                resp.set_id(req.id())
+                    .set_message_type(MessageType::Response)
+                    .set_op_code(req.op_code())
+                    .set_recursion_desired(req.recursion_desired())
+                    .set_recursion_available(true)
+                    .set_response_code(ResponseCode::NXDomain);
+
+                for query in req.queries() {
+                    resp.add_query(query.clone());
+
+                    if let Some(txts) = records.lock().unwrap().get(&query.name().to_string()) {
+                        resp.set_response_code(ResponseCode::NoError);
+                        for txt in txts {
+                            resp.add_answer(Record::from_rdata(
+                                query.name().clone(),
+                                0,
+                                RData::TXT(TXT::new(vec![txt.clone()])),
+                            ));
+                        }
+                    }
+                }
+
+                socket.send_to(&resp.to_vec().unwrap(), from).unwrap();
+            }
+        });
+
+        addr
+    }
+
+    async fn web_client(resolver: SocketAddr) -> WebClient {
+        // The database is not accessed by the lookups.
+        let db = Database::new("mongodb://localhost:27017/", "registrar_test")
+            .await
+            .unwrap();
+
+        WebClientBuilder::new(db)
+            .dns_resolver(Some(resolver.to_string()))
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn domain_extraction() {
+        assert_eq!(extract_domain("alice.com"), Some("alice.com"));
+        assert_eq!(extract_domain(" alice.com/ "), Some("alice.com"));
+        assert_eq!(extract_domain("https://alice.com"), Some("alice.com"));
+        assert_eq!(extract_domain("http://alice.com/about"), Some("alice.com"));
+        assert_eq!(extract_domain("localhost:8080"), Some("localhost:8080"));
+        assert_eq!(extract_domain("https://"), None);
+        assert_eq!(extract_domain(""), None);
+    }
+
+    #[test]
+    fn host_parsing() {
+        let host = |domain: &str| -> Host { parse_host(domain).unwrap() };
+
+        assert_eq!(host("alice.com"), Host::Domain("alice.com".to_string()));
+        assert_eq!(
+            host("alice.com:8080"),
+            Host::Domain("alice.com".to_string())
+        );
+        assert!(matches!(host("1.1.1.1:443"), Host::Ipv4(ip) if ip.octets() == [1, 1, 1, 1]));
+        assert!(matches!(host("[::1]"), Host::Ipv6(ip) if ip.is_loopback()));
+        assert!(matches!(host("[::1]:8080"), Host::Ipv6(ip) if ip.is_loopback()));
+
+        assert!(parse_host("::1").is_err());
+        assert!(parse_host("alice.com:port").is_err());
+    }
+
+    #[test]
+    fn public_addresses() {
+        let public = |ip: &str| is_public_address(ip.parse().unwrap());
+
+        assert!(public("1.1.1.1"));
+        assert!(public("2606:4700:4700::1111"));
+        assert!(public("::ffff:1.1.1.1"));
+
+        assert!(!public("127.0.0.1"));
+        assert!(!public("10.0.0.1"));
+        assert!(!public("192.168.1.1"));
+        assert!(!public("169.254.169.254"));
+        assert!(!public("100.64.0.1"));
+        assert!(!public("0.0.0.0"));
+        assert!(!public("::1"));
+        assert!(!public("::"));
+        assert!(!public("fd00::1"));
+        assert!(!public("fe80::1"));
+        assert!(!public("::ffff:127.0.0.1"));
+    }
+
+    #[actix::test]
+    async fn reject_private_addresses() {
+        let client = web_client(start_dns_stub(Default::default())).await;
+
+        assert!(client
+            .resolve_public_address("127.0.0.1:8080")
+            .await
+            .is_err());
+        assert!(client.resolve_public_address("[::1]").await.is_err());
+        assert!(client.resolve_public_address("[::1]:8080").await.is_err());
+        assert!(client.fetch_challenge_file("localhost").await.is_err());
+
+        let (host, ip) = client.resolve_public_address("1.1.1.1:443").await.unwrap();
+        assert_eq!(host, "1.1.1.1");
+        assert_eq!(ip, "1.1.1.1".parse::<IpAddr>().unwrap());
+    }
+
+    #[actix::test]
+    async fn txt_record_lookup() {
+        let records = Arc::new(Mutex::new(HashMap::from([(
+            "_polkadot-identity.alice.com.".to_string(),
+            vec!["some_challenge".to_string()],
+        )])));
+
+        let client = web_client(start_dns_stub(records)).await;
+
+        let txts = client.lookup_txt_records("alice.com").await.unwrap();
+        assert_eq!(txts, vec!["some_challenge".to_string()]);
+
+        // Ports are ignored.
+        let txts = client.lookup_txt_records("alice.com:8080").await.unwrap();
+        assert_eq!(txts, vec!["some_challenge".to_string()]);
+
+        // IP addresses have no records.
+        assert!(client.lookup_txt_records("[::1]:8080").await.is_err());
+
+        // No record for the domain.
+        assert!(client.lookup_txt_records("bob.com").await.is_err());
+    }
+}
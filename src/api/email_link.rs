@@ -0,0 +1,245 @@
+use super::{JsonResult, VerifyChallenge};
+use crate::database::Database;
+use crate::primitives::{IdentityFieldValue, Timestamp};
+use crate::Result;
+use actix::prelude::*;
+use actix_web::{web, HttpResponse};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+pub const DEFAULT_LINK_TTL: u64 = 86400; // one day
+const LINK_PATH: &str = "/api/verify_email_link";
+
+pub struct EmailLinkVerifier {
+    db: Database,
+    // Links are rejected if not specified.
+    secret: Option<String>,
+}
+
+impl Default for EmailLinkVerifier {
+    fn default() -> Self {
+        panic!("EmailLinkVerifier is not initialized");
+    }
+}
+
+impl EmailLinkVerifier {
+    pub fn new(db: Database, secret: Option<String>) -> Self {
+        EmailLinkVerifier { db, secret }
+    }
+}
+
+impl SystemService for EmailLinkVerifier {}
+impl Supervised for EmailLinkVerifier {}
+
+impl Actor for EmailLinkVerifier {
+    type Context = Context<Self>;
+}
+
+impl Handler<EmailLink> for EmailLinkVerifier {
+    type Result = ResponseActFuture<Self, JsonResult<bool>>;
+
+    fn handle(&mut self, msg: EmailLink, _ctx: &mut Self::Context) -> Self::Result {
+        let db = self.db.clone();
+        let secret = self.secret.clone();
+
+        Box::pin(
+            async move {
+                debug!("Received email verification link for: {}", msg.email);
+                process_link(&db, secret.as_deref(), msg)
+                    .await
+                    .map_err(|err| error!("Failed to verify email link: {:?}", err))
+                    .unwrap_or_else(|_| JsonResult::Err("Backend error, contact admin".to_string()))
+            }
+            .into_actor(self),
+        )
+    }
+}
+
+/// A signed, expiring link which completes the second email challenge. Each
+/// link can only be used once.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, Message)]
+#[rtype(result = "JsonResult<bool>")]
+pub struct EmailLink {
+    pub email: String,
+    pub challenge: String,
+    // UNIX timestamp in seconds.
+    pub expires: u64,
+    // Hex encoded HMAC-SHA256 of the other values.
+    pub signature: String,
+}
+
+impl EmailLink {
+    pub fn new(secret: &str, email: &str, challenge: &str, ttl: u64) -> Self {
+        let expires = Timestamp::with_offset(ttl).raw();
+
+        EmailLink {
+            email: email.to_string(),
+            challenge: challenge.to_string(),
+            expires,
+            signature: hex::encode(
+                Self::mac(secret, email, challenge, expires)
+                    .finalize()
+                    .into_bytes(),
+            ),
+        }
+    }
+    fn mac(secret: &str, email: &str, challenge: &str, expires: u64) -> Hmac<Sha256> {
+        let mut mac: Hmac<Sha256> = Hmac::new_from_slice(secret.as_bytes()).unwrap();
+        // Neither the email address nor the challenge contain line breaks.
+        mac.update(format!("email_link\n{}\n{}\n{}", email, challenge, expires).as_bytes());
+        mac
+    }
+    /// Checks the signature (in constant time) and whether the link has
+    /// expired.
+    pub fn is_valid(&self, secret: &str) -> bool {
+        let signature = match hex::decode(&self.signature) {
+            Ok(signature) => signature,
+            Err(_) => return false,
+        };
+
+        Self::mac(secret, &self.email, &self.challenge, self.expires)
+            .verify_slice(&signature)
+            .is_ok()
+            && self.expires > Timestamp::now().raw()
+    }
+    /// The URL of the link, pointing to the session notifier at `api_base`.
+    pub fn to_url(&self, api_base: &str) -> Result<String> {
+        let expires = self.expires.to_string();
+
+        Ok(url::Url::parse_with_params(
+            &format!("{}{}", api_base.trim_end_matches('/'), LINK_PATH),
+            &[
+                ("email", self.email.as_str()),
+                ("challenge", self.challenge.as_str()),
+                ("expires", expires.as_str()),
+                ("signature", self.signature.as_str()),
+            ],
+        )?
+        .to_string())
+    }
+}
+
+async fn process_link(
+    db: &Database,
+    secret: Option<&str>,
+    link: EmailLink,
+) -> Result<JsonResult<bool>> {
+    let secret = if let Some(secret) = secret {
+        secret
+    } else {
+        return Ok(JsonResult::Err(
+            "Verification links are not enabled".to_string(),
+        ));
+    };
+
+    if !link.is_valid(secret) {
+        return Ok(JsonResult::Err("Invalid or expired link".to_string()));
+    }
+
+    if !db.claim_email_link(&link.signature, link.expires).await? {
+        return Ok(JsonResult::Err("The link was already used".to_string()));
+    }
+
+    let verified = db
+        .verify_second_challenge_link(VerifyChallenge {
+            entry: IdentityFieldValue::Email(link.email),
+            challenge: link.challenge,
+        })
+        .await;
+
+    // The link can be used again if the challenge could not be completed,
+    // e.g. because the field is locked.
+    if !matches!(verified, Ok(true)) {
+        db.release_email_link(&link.signature).await?;
+    }
+
+    verified.map(JsonResult::Ok)
+}
+
+// Opening a link must not change any state, since mail scanners and link
+// previews fetch them as well. The verification is only completed once the
+// user submits the form, which posts the query parameters back to this path.
+const CONFIRMATION_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head><meta charset="utf-8"><title>Verify email address</title></head>
+<body>
+<form method="post">
+<p>Please confirm the verification of your email address.</p>
+<button type="submit">Verify</button>
+</form>
+</body>
+</html>
+"#;
+
+pub async fn confirm_email_link(_req: web::Query<EmailLink>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(CONFIRMATION_PAGE)
+}
+
+pub async fn verify_email_link(req: web::Query<EmailLink>) -> HttpResponse {
+    // The link is opened in the browser, so respond with plain text.
+    match EmailLinkVerifier::from_registry()
+        .send(req.into_inner())
+        .await
+        .unwrap()
+    {
+        JsonResult::Ok(true) => HttpResponse::Ok()
+            .body("Your email address has been verified. You can close this window."),
+        JsonResult::Ok(false) => HttpResponse::BadRequest()
+            .body("Your email address could not be verified, please check the web interface."),
+        JsonResult::Err(err) => HttpResponse::BadRequest().body(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn email_link_signature() {
+        let link = EmailLink::new("secret", "alice@email.com", "challenge", 60);
+        assert!(link.is_valid("secret"));
+        assert!(!link.is_valid("other_secret"));
+
+        // Any modification invalidates the signature.
+        let mut modified = link.clone();
+        modified.email = "bob@email.com".to_string();
+        assert!(!modified.is_valid("secret"));
+
+        let mut modified = link.clone();
+        modified.challenge = "other_challenge".to_string();
+        assert!(!modified.is_valid("secret"));
+
+        let mut modified = link.clone();
+        modified.expires += 60;
+        assert!(!modified.is_valid("secret"));
+
+        let mut modified = link;
+        modified.signature = "invalid".to_string();
+        assert!(!modified.is_valid("secret"));
+
+        // Expired.
+        let link = EmailLink::new("secret", "alice@email.com", "challenge", 0);
+        assert!(!link.is_valid("secret"));
+    }
+
+    #[test]
+    fn email_link_url() {
+        let link = EmailLink::new("secret", "alice+test@email.com", "challenge", 60);
+        let url = link.to_url("https://registrar.example.com/").unwrap();
+
+        assert_eq!(
+            url,
+            format!(
+                "https://registrar.example.com/api/verify_email_link?email=alice%2Btest%40email.com&challenge=challenge&expires={}&signature={}",
+                link.expires, link.signature
+            )
+        );
+
+        // The query parameters are parsed back into the same link.
+        let query = url::Url::parse(&url).unwrap().query().unwrap().to_string();
+        let parsed = web::Query::<EmailLink>::from_query(&query).unwrap();
+        assert_eq!(parsed.into_inner(), link);
+    }
+}
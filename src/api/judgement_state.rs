@@ -1,6 +1,9 @@
 use super::JsonResult;
 use crate::database::Database;
-use crate::primitives::{IdentityContext, JudgementStateBlanked, NotificationMessage};
+use crate::primitives::{
+    ChainAddress, ChainName, IdentityContext, JudgementStateBlanked, NotificationMessage,
+};
+use crate::NetworkConfig;
 use actix::prelude::*;
 use actix_broker::BrokerSubscribe;
 use actix_web_actors::ws;
@@ -170,8 +173,44 @@ impl Handler<NotifyAccountState> for LookupServer {
     }
 }
 
-#[derive(Default)]
-pub struct WsAccountStatusSession;
+// Subscription request of a session. The network is detected based on the
+// address if not specified.
+#[derive(Debug, Clone, Deserialize)]
+struct SubscriptionRequest {
+    address: ChainAddress,
+    chain: Option<ChainName>,
+}
+
+impl SubscriptionRequest {
+    fn into_context(self, networks: &[NetworkConfig]) -> crate::Result<IdentityContext> {
+        let chain = match self.chain {
+            Some(chain) => chain,
+            None => self.address.chain(networks)?,
+        };
+
+        let context = IdentityContext::new(self.address, chain);
+        context.validate(networks)?;
+
+        Ok(context)
+    }
+}
+
+fn send_error(ctx: &mut ws::WebsocketContext<WsAccountStatusSession>, msg: String) {
+    match serde_json::to_string(&JsonResult::<()>::Err(msg)) {
+        Ok(m) => ctx.text(m),
+        Err(err) => error!("Failed to serialize WS session message response: {:?}", err),
+    }
+}
+
+pub struct WsAccountStatusSession {
+    networks: Arc<Vec<NetworkConfig>>,
+}
+
+impl WsAccountStatusSession {
+    pub fn new(networks: Arc<Vec<NetworkConfig>>) -> Self {
+        WsAccountStatusSession { networks }
+    }
+}
 
 impl Actor for WsAccountStatusSession {
     type Context = ws::WebsocketContext<Self>;
@@ -194,26 +233,28 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsAccountStatusSe
                     return;
                 }
 
-                if let Ok(context) = serde_json::from_slice::<IdentityContext>(msg.as_bytes()) {
-                    // Subscribe the the specified identity context.
-                    LookupServer::from_registry()
-                        .send(SubscribeAccountState {
-                            subscriber: ctx.address().recipient(),
-                            id_context: context,
-                        })
-                        .into_actor(self)
-                        .then(|_, _, _| fut::ready(()))
-                        .wait(ctx);
-                } else {
-                    // Invalid message type, inform caller.
-                    match serde_json::to_string(&JsonResult::<()>::Err(
-                        "Invalid message type".to_string(),
-                    )) {
-                        Ok(m) => ctx.text(m),
-                        Err(err) => {
-                            error!("Failed to serialize WS session message response: {:?}", err)
-                        }
+                let context = match serde_json::from_slice::<SubscriptionRequest>(msg.as_bytes()) {
+                    Ok(request) => request.into_context(&self.networks),
+                    Err(_) => {
+                        // Invalid message type, inform caller.
+                        send_error(ctx, "Invalid message type".to_string());
+                        return;
+                    }
+                };
+
+                match context {
+                    Ok(context) => {
+                        // Subscribe the the specified identity context.
+                        LookupServer::from_registry()
+                            .send(SubscribeAccountState {
+                                subscriber: ctx.address().recipient(),
+                                id_context: context,
+                            })
+                            .into_actor(self)
+                            .then(|_, _, _| fut::ready(()))
+                            .wait(ctx);
                     }
+                    Err(err) => send_error(ctx, format!("Invalid address: {}", err)),
                 }
             }
             ws::Message::Ping(b) => {
@@ -1,20 +1,30 @@
 use self::judgement_state::WsAccountStatusSession;
 use crate::database::Database;
-use crate::{NotifierConfig, Result};
+use crate::{NetworkConfig, NotifierConfig, Result};
 use actix::prelude::*;
 use actix::registry::SystemRegistry;
 use actix_cors::Cors;
 use actix_web::{web, App, Error as ActixError, HttpRequest, HttpResponse, HttpServer};
 use actix_web_actors::ws;
 use display_name_check::{check_display_name, DisplayNameChecker};
+use email_link::{confirm_email_link, verify_email_link, EmailLinkVerifier};
+use pgp_verification::{verify_pgp, PgpVerifier};
+use regenerate_challenge::{regenerate_challenge, ChallengeRegenerator};
 use second_challenge::{verify_second_challenge, SecondChallengeVerifier};
+use signature_verification::{verify_signature, SignatureVerifier};
 
 mod display_name_check;
+mod email_link;
 mod judgement_state;
+pub mod pgp_verification;
+mod regenerate_challenge;
 mod second_challenge;
+pub mod signature_verification;
 
 // Reexport
+pub use self::email_link::{EmailLink, DEFAULT_LINK_TTL};
 pub use self::judgement_state::{LookupServer, NotifyAccountState, ResponseAccountState};
+pub use self::regenerate_challenge::RegenerateChallenge;
 pub use self::second_challenge::VerifyChallenge;
 
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, Message)]
@@ -31,26 +41,52 @@ async fn healthcheck() -> HttpResponse {
 
 pub async fn run_rest_api_server(
     config: NotifierConfig,
+    networks: Vec<NetworkConfig>,
     db: Database,
 ) -> Result<Addr<LookupServer>> {
     // Add configured actor to the registry.
     let actor = LookupServer::new(db.clone()).start();
     SystemRegistry::set(actor.clone());
     SystemRegistry::set(SecondChallengeVerifier::new(db.clone()).start());
+    SystemRegistry::set(PgpVerifier::new(db.clone()).start());
+    SystemRegistry::set(ChallengeRegenerator::new(db.clone()).start());
+    SystemRegistry::set(SignatureVerifier::new(db.clone()).start());
+    SystemRegistry::set(
+        EmailLinkVerifier::new(db.clone(), config.verification_link_secret).start(),
+    );
     SystemRegistry::set(DisplayNameChecker::new(db, config.display_name).start());
 
+    // Used to detect the network of subscribed addresses.
+    let networks = web::Data::new(networks);
+
     // Run the WS server.
     let server = HttpServer::new(move || {
         let cors = Cors::permissive();
 
         App::new()
             .wrap(cors)
+            .app_data(networks.clone())
             .route("/healthcheck", web::get().to(healthcheck))
             .service(web::resource("/api/account_status").to(account_status_server_route))
             .route(
                 "/api/verify_second_challenge",
                 web::post().to(verify_second_challenge),
             )
+            .service(
+                web::resource("/api/verify_pgp")
+                    .app_data(pgp_verification::json_config())
+                    .route(web::post().to(verify_pgp)),
+            )
+            .route("/api/verify_signature", web::post().to(verify_signature))
+            .route(
+                "/api/regenerate_challenge",
+                web::post().to(regenerate_challenge),
+            )
+            .service(
+                web::resource("/api/verify_email_link")
+                    .route(web::get().to(confirm_email_link))
+                    .route(web::post().to(verify_email_link)),
+            )
             .route(
                 "/api/check_display_name",
                 web::post().to(check_display_name),
@@ -68,8 +104,13 @@ pub async fn run_rest_api_server(
 async fn account_status_server_route(
     req: HttpRequest,
     stream: web::Payload,
+    networks: web::Data<Vec<NetworkConfig>>,
 ) -> std::result::Result<HttpResponse, ActixError> {
-    ws::start(WsAccountStatusSession::default(), &req, stream)
+    ws::start(
+        WsAccountStatusSession::new(networks.into_inner()),
+        &req,
+        stream,
+    )
 }
 
 #[cfg(test)]
@@ -79,6 +120,8 @@ pub mod tests {
     use crate::DisplayNameConfig;
     use actix_test::{start, TestServer};
 
+    pub const TEST_LINK_SECRET: &str = "test_secret";
+
     impl Default for DisplayNameConfig {
         fn default() -> Self {
             DisplayNameConfig {
@@ -97,16 +140,38 @@ pub mod tests {
             // Add configured actor to the registry.
             SystemRegistry::set(t_actor.clone());
             SystemRegistry::set(SecondChallengeVerifier::new(db.clone()).start());
+            SystemRegistry::set(PgpVerifier::new(db.clone()).start());
+            SystemRegistry::set(ChallengeRegenerator::new(db.clone()).start());
+            SystemRegistry::set(SignatureVerifier::new(db.clone()).start());
+            SystemRegistry::set(
+                EmailLinkVerifier::new(db.clone(), Some(TEST_LINK_SECRET.to_string())).start(),
+            );
             SystemRegistry::set(
                 DisplayNameChecker::new(db.clone(), DisplayNameConfig::default()).start(),
             );
 
             App::new()
+                .app_data(web::Data::new(crate::tests::networks()))
                 .service(web::resource("/api/account_status").to(account_status_server_route))
                 .route(
                     "/api/verify_second_challenge",
                     web::post().to(verify_second_challenge),
                 )
+                .service(
+                    web::resource("/api/verify_pgp")
+                        .app_data(pgp_verification::json_config())
+                        .route(web::post().to(verify_pgp)),
+                )
+                .route("/api/verify_signature", web::post().to(verify_signature))
+                .route(
+                    "/api/regenerate_challenge",
+                    web::post().to(regenerate_challenge),
+                )
+                .service(
+                    web::resource("/api/verify_email_link")
+                        .route(web::get().to(confirm_email_link))
+                        .route(web::post().to(verify_email_link)),
+                )
                 .route(
                     "/api/check_display_name",
                     web::post().to(check_display_name),
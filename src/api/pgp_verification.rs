@@ -0,0 +1,342 @@
+use super::JsonResult;
+use crate::database::Database;
+use crate::primitives::{
+    ExternalMessage, ExternalMessageType, IdentityContext, IdentityFieldValue, Timestamp,
+};
+use crate::Result;
+use actix::prelude::*;
+use actix_web::{web, HttpResponse};
+use std::path::Path;
+use std::process::Output;
+use std::sync::Arc;
+use tokio::process::Command;
+use tokio::sync::Semaphore;
+
+const GPG_BINARY: &str = "gpg";
+// Public keys and signed messages are small, anything bigger is rejected.
+const MAX_PAYLOAD_SIZE: usize = 64 * 1024;
+// Every verification runs GnuPG twice, so only a few may run at a time.
+const MAX_CONCURRENT_VERIFICATIONS: usize = 4;
+
+pub struct PgpVerifier {
+    db: Database,
+    permits: Arc<Semaphore>,
+}
+
+impl Default for PgpVerifier {
+    fn default() -> Self {
+        panic!("PgpVerifier is not initialized");
+    }
+}
+
+impl PgpVerifier {
+    pub fn new(db: Database) -> Self {
+        PgpVerifier {
+            db,
+            permits: Arc::new(Semaphore::new(MAX_CONCURRENT_VERIFICATIONS)),
+        }
+    }
+}
+
+impl SystemService for PgpVerifier {}
+impl Supervised for PgpVerifier {}
+
+impl Actor for PgpVerifier {
+    type Context = Context<Self>;
+}
+
+impl Handler<VerifyPgp> for PgpVerifier {
+    type Result = ResponseActFuture<Self, JsonResult<bool>>;
+
+    fn handle(&mut self, msg: VerifyPgp, _ctx: &mut Self::Context) -> Self::Result {
+        let db = self.db.clone();
+        let permits = Arc::clone(&self.permits);
+
+        Box::pin(
+            async move {
+                debug!("Received PGP signed message from: {:?}", msg.context);
+                process_signed_message(&db, &permits, msg)
+                    .await
+                    .map_err(|err| error!("Failed to verify PGP signed message: {:?}", err))
+                    .unwrap_or_else(|_| JsonResult::Err("Backend error, contact admin".to_string()))
+            }
+            .into_actor(self),
+        )
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, Message)]
+#[rtype(result = "JsonResult<bool>")]
+pub struct VerifyPgp {
+    pub context: IdentityContext,
+    // ASCII-armored public key.
+    pub public_key: String,
+    // ASCII-armored signed message, containing the challenge.
+    pub signed_message: String,
+}
+
+async fn process_signed_message(
+    db: &Database,
+    permits: &Semaphore,
+    msg: VerifyPgp,
+) -> Result<JsonResult<bool>> {
+    let signed = {
+        let _permit = permits.acquire().await?;
+        verify_signature(&msg.public_key, &msg.signed_message).await
+    };
+
+    let signed = match signed {
+        Ok(signed) => signed,
+        Err(err) => {
+            debug!("Invalid PGP signature: {:?}", err);
+            return Ok(JsonResult::Err(
+                "Invalid public key or signature".to_string(),
+            ));
+        }
+    };
+
+    let state = if let Some(state) = db.fetch_judgement_state(&msg.context).await? {
+        state
+    } else {
+        return Ok(JsonResult::Err(
+            "There is no judgement request from that account for this registrar".to_string(),
+        ));
+    };
+
+    let fingerprint = state.fields.iter().find_map(|field| match &field.value {
+        IdentityFieldValue::PGPFingerprint(fingerprint) => Some(fingerprint),
+        _ => None,
+    });
+
+    match fingerprint {
+        Some(fingerprint) if fingerprint == &signed.fingerprint => {}
+        Some(_) => {
+            return Ok(JsonResult::Err(
+                "The message was not signed by the key of the specified PGP fingerprint"
+                    .to_string(),
+            ))
+        }
+        None => {
+            return Ok(JsonResult::Err(
+                "The identity does not specify a PGP fingerprint".to_string(),
+            ))
+        }
+    }
+
+    let message = ExternalMessage {
+        origin: ExternalMessageType::PGPFingerprint(signed.fingerprint),
+        // A message UID is not relevant, the API is called directly.
+        id: 0u32.into(),
+        timestamp: Timestamp::now(),
+        values: vec![signed.content.into()],
+    };
+
+    db.verify_pgp_message(&msg.context, &message)
+        .await
+        .map(JsonResult::Ok)
+}
+
+/// The content of a message with a valid signature.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SignedMessage {
+    // Fingerprint of the primary key, as formatted by GnuPG.
+    pub fingerprint: String,
+    pub content: String,
+}
+
+async fn run_gpg(homedir: &Path, args: &[&str]) -> Result<Output> {
+    Command::new(GPG_BINARY)
+        .arg("--batch")
+        .arg("--no-tty")
+        // Only public keys are used, no agent is required.
+        .arg("--no-autostart")
+        .arg("--homedir")
+        .arg(homedir)
+        .args(args)
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(|err| anyhow!("failed to execute {}: {:?}", GPG_BINARY, err))
+}
+
+/// Verifies the signature of the message with the given public key by using a
+/// temporary GnuPG keyring. Returns the fingerprint of the signing key and the
+/// signed content.
+pub async fn verify_signature(public_key: &str, signed_message: &str) -> Result<SignedMessage> {
+    let homedir = tempfile::tempdir()?;
+    let path = homedir.path();
+
+    let key_file = path.join("key.asc");
+    let msg_file = path.join("message.asc");
+    let status_file = path.join("status");
+
+    tokio::fs::write(&key_file, public_key).await?;
+    tokio::fs::write(&msg_file, signed_message).await?;
+
+    let out = run_gpg(path, &["--import", &key_file.to_string_lossy()]).await?;
+    if !out.status.success() {
+        return Err(anyhow!(
+            "failed to import public key: {}",
+            String::from_utf8_lossy(&out.stderr)
+        ));
+    }
+
+    // Outputs the signed content on success.
+    let out = run_gpg(
+        path,
+        &[
+            "--status-file",
+            &status_file.to_string_lossy(),
+            "--decrypt",
+            &msg_file.to_string_lossy(),
+        ],
+    )
+    .await?;
+
+    if !out.status.success() {
+        return Err(anyhow!(
+            "failed to verify signature: {}",
+            String::from_utf8_lossy(&out.stderr)
+        ));
+    }
+
+    // The status line has the format:
+    // `[GNUPG:] VALIDSIG <SIG_FPR> <DATE> <TIMESTAMP> <EXPIRY> <VERSION> <RESERVED> <ALGO> <HASH> <CLASS> <PRIMARY_FPR>`
+    let status = tokio::fs::read_to_string(&status_file).await?;
+    let fingerprint = status
+        .lines()
+        .find_map(|line| line.strip_prefix("[GNUPG:] VALIDSIG "))
+        .and_then(|line| {
+            let parts: Vec<&str> = line.split(' ').collect();
+            // Prefer the fingerprint of the primary key, in case a subkey
+            // was used for signing.
+            parts
+                .get(9)
+                .or_else(|| parts.first())
+                .map(|s| s.to_string())
+        })
+        .ok_or_else(|| anyhow!("message does not contain a valid signature"))?;
+
+    Ok(SignedMessage {
+        fingerprint,
+        content: String::from_utf8(out.stdout)?,
+    })
+}
+
+/// Limits the size of requests to the PGP verification endpoint.
+pub fn json_config() -> web::JsonConfig {
+    web::JsonConfig::default().limit(MAX_PAYLOAD_SIZE)
+}
+
+pub async fn verify_pgp(req: web::Json<VerifyPgp>) -> HttpResponse {
+    HttpResponse::Ok().json(
+        PgpVerifier::from_registry()
+            .send(req.into_inner())
+            .await
+            .unwrap(),
+    )
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::process::Command;
+    use tempfile::TempDir;
+
+    /// A PGP key generated in a temporary GnuPG keyring.
+    pub struct PgpKey {
+        homedir: TempDir,
+        fingerprint: String,
+    }
+
+    impl PgpKey {
+        fn gpg(&self, args: &[&str]) -> Vec<u8> {
+            let out = Command::new(GPG_BINARY)
+                .args(["--batch", "--no-tty", "--passphrase", ""])
+                .args(["--pinentry-mode", "loopback", "--homedir"])
+                .arg(self.homedir.path())
+                .args(args)
+                .output()
+                .unwrap();
+
+            assert!(out.status.success());
+            out.stdout
+        }
+        pub fn generate() -> Self {
+            let mut key = PgpKey {
+                homedir: tempfile::tempdir().unwrap(),
+                fingerprint: String::new(),
+            };
+
+            key.gpg(&[
+                "--quick-gen-key",
+                "Alice <alice@email.com>",
+                "ed25519",
+                "sign",
+                "never",
+            ]);
+
+            let list = String::from_utf8(key.gpg(&["--with-colons", "--list-keys"])).unwrap();
+            key.fingerprint = list
+                .lines()
+                .find_map(|line| line.strip_prefix("fpr:::::::::"))
+                .map(|line| line.trim_end_matches(':').to_string())
+                .unwrap();
+
+            key
+        }
+        pub fn fingerprint(&self) -> String {
+            self.fingerprint.clone()
+        }
+        pub fn public_key(&self) -> String {
+            String::from_utf8(self.gpg(&["--armor", "--export"])).unwrap()
+        }
+        pub fn sign(&self, message: &str) -> String {
+            let file = self.homedir.path().join("message.txt");
+            std::fs::write(&file, message).unwrap();
+
+            String::from_utf8(self.gpg(&["--output", "-", "--clearsign", &file.to_string_lossy()]))
+                .unwrap()
+        }
+    }
+
+    impl Drop for PgpKey {
+        fn drop(&mut self) {
+            // Shut down the agent which was started for key generation.
+            let _ = Command::new("gpgconf")
+                .arg("--homedir")
+                .arg(self.homedir.path())
+                .args(["--kill", "gpg-agent"])
+                .output();
+        }
+    }
+
+    #[actix::test]
+    async fn signature_verification() {
+        let alice = PgpKey::generate();
+        let bob = PgpKey::generate();
+
+        let signed = alice.sign("some_challenge");
+
+        // Valid signature.
+        let res = verify_signature(&alice.public_key(), &signed)
+            .await
+            .unwrap();
+        assert_eq!(res.fingerprint, alice.fingerprint());
+        assert!(res.content.contains("some_challenge"));
+
+        // Signed by a different key.
+        assert!(verify_signature(&bob.public_key(), &signed).await.is_err());
+
+        // Modified content.
+        let modified = signed.replace("some_challenge", "other_challenge");
+        assert!(verify_signature(&alice.public_key(), &modified)
+            .await
+            .is_err());
+
+        // Unsigned content.
+        assert!(verify_signature(&alice.public_key(), "some_challenge")
+            .await
+            .is_err());
+    }
+}
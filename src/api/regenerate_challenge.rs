@@ -0,0 +1,101 @@
+use super::signature_verification::check_signature;
+use super::JsonResult;
+use crate::database::Database;
+use crate::primitives::{IdentityContext, IdentityFieldValue};
+use crate::Result;
+use actix::prelude::*;
+use actix_web::{web, HttpResponse};
+
+pub struct ChallengeRegenerator {
+    db: Database,
+}
+
+impl Default for ChallengeRegenerator {
+    fn default() -> Self {
+        panic!("ChallengeRegenerator is not initialized");
+    }
+}
+
+impl ChallengeRegenerator {
+    pub fn new(db: Database) -> Self {
+        ChallengeRegenerator { db }
+    }
+}
+
+impl SystemService for ChallengeRegenerator {}
+impl Supervised for ChallengeRegenerator {}
+
+impl Actor for ChallengeRegenerator {
+    type Context = Context<Self>;
+}
+
+impl Handler<RegenerateChallenge> for ChallengeRegenerator {
+    type Result = ResponseActFuture<Self, JsonResult<bool>>;
+
+    fn handle(&mut self, msg: RegenerateChallenge, _ctx: &mut Self::Context) -> Self::Result {
+        let db = self.db.clone();
+
+        Box::pin(
+            async move {
+                debug!("Received challenge regeneration request: {:?}", msg);
+                process_request(&db, msg)
+                    .await
+                    .map_err(|err| error!("Failed to regenerate challenge: {:?}", err))
+                    .unwrap_or_else(|_| JsonResult::Err("Backend error, contact admin".to_string()))
+            }
+            .into_actor(self),
+        )
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, Message)]
+#[rtype(result = "JsonResult<bool>")]
+pub struct RegenerateChallenge {
+    pub context: IdentityContext,
+    pub entry: IdentityFieldValue,
+    // Hex encoded signature of the signature challenge, proving that the
+    // request was sent by the owner of the address.
+    pub signature: String,
+}
+
+async fn process_request(db: &Database, msg: RegenerateChallenge) -> Result<JsonResult<bool>> {
+    let state = if let Some(state) = db.fetch_judgement_state(&msg.context).await? {
+        state
+    } else {
+        return Ok(JsonResult::Err(
+            "There is no judgement request from that account for this registrar".to_string(),
+        ));
+    };
+
+    // Requests created by older versions can not be authenticated.
+    let challenge = match state.signature_challenge {
+        Some(challenge) => challenge,
+        None => {
+            return Ok(JsonResult::Err(
+                "The judgement request does not support regenerating challenges".to_string(),
+            ))
+        }
+    };
+
+    let signature = match hex::decode(msg.signature.trim().trim_start_matches("0x")) {
+        Ok(signature) => signature,
+        Err(_) => return Ok(JsonResult::Err("Invalid signature encoding".to_string())),
+    };
+
+    if !check_signature(&msg.context.address, challenge.value.as_bytes(), &signature)? {
+        return Ok(JsonResult::Err(
+            "The challenge was not signed by the key of the address".to_string(),
+        ));
+    }
+
+    db.regenerate_challenge(msg).await.map(JsonResult::Ok)
+}
+
+pub async fn regenerate_challenge(req: web::Json<RegenerateChallenge>) -> HttpResponse {
+    HttpResponse::Ok().json(
+        ChallengeRegenerator::from_registry()
+            .send(req.into_inner())
+            .await
+            .unwrap(),
+    )
+}
@@ -0,0 +1,196 @@
+use super::JsonResult;
+use crate::database::Database;
+use crate::primitives::{ChainAddress, IdentityContext};
+use crate::Result;
+use actix::prelude::*;
+use actix_web::{web, HttpResponse};
+use ed25519_dalek::Verifier;
+
+// Signing context of sr25519 signatures, as used by Substrate based chains.
+const SIGNING_CONTEXT: &[u8] = b"substrate";
+
+pub struct SignatureVerifier {
+    db: Database,
+}
+
+impl Default for SignatureVerifier {
+    fn default() -> Self {
+        panic!("SignatureVerifier is not initialized");
+    }
+}
+
+impl SignatureVerifier {
+    pub fn new(db: Database) -> Self {
+        SignatureVerifier { db }
+    }
+}
+
+impl SystemService for SignatureVerifier {}
+impl Supervised for SignatureVerifier {}
+
+impl Actor for SignatureVerifier {
+    type Context = Context<Self>;
+}
+
+impl Handler<VerifySignature> for SignatureVerifier {
+    type Result = ResponseActFuture<Self, JsonResult<bool>>;
+
+    fn handle(&mut self, msg: VerifySignature, _ctx: &mut Self::Context) -> Self::Result {
+        let db = self.db.clone();
+
+        Box::pin(
+            async move {
+                debug!("Received signature from: {:?}", msg.context);
+                process_signature(&db, msg)
+                    .await
+                    .map_err(|err| error!("Failed to verify signature: {:?}", err))
+                    .unwrap_or_else(|_| JsonResult::Err("Backend error, contact admin".to_string()))
+            }
+            .into_actor(self),
+        )
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, Message)]
+#[rtype(result = "JsonResult<bool>")]
+pub struct VerifySignature {
+    pub context: IdentityContext,
+    // Hex encoded sr25519 or ed25519 signature of the signature challenge,
+    // optionally prefixed with `0x`.
+    pub signature: String,
+}
+
+async fn process_signature(db: &Database, msg: VerifySignature) -> Result<JsonResult<bool>> {
+    let state = if let Some(state) = db.fetch_judgement_state(&msg.context).await? {
+        state
+    } else {
+        return Ok(JsonResult::Err(
+            "There is no judgement request from that account for this registrar".to_string(),
+        ));
+    };
+
+    let challenge = match state.signature_challenge {
+        Some(challenge) => challenge,
+        None => {
+            return Ok(JsonResult::Err(
+                "The judgement request does not require a signature".to_string(),
+            ))
+        }
+    };
+
+    if challenge.is_verified {
+        return Ok(JsonResult::Ok(true));
+    }
+
+    let signature = match hex::decode(msg.signature.trim().trim_start_matches("0x")) {
+        Ok(signature) => signature,
+        Err(_) => return Ok(JsonResult::Err("Invalid signature encoding".to_string())),
+    };
+
+    if !check_signature(&msg.context.address, challenge.value.as_bytes(), &signature)? {
+        return Ok(JsonResult::Err(
+            "The challenge was not signed by the key of the address".to_string(),
+        ));
+    }
+
+    db.verify_signature_challenge(&msg.context)
+        .await
+        .map(JsonResult::Ok)
+}
+
+/// Checks whether the message was signed by the key of the address. Both
+/// sr25519 and ed25519 signatures are supported. Wallets usually wrap the
+/// message in `<Bytes>` tags before signing, which is accepted as well.
+pub fn check_signature(address: &ChainAddress, message: &[u8], signature: &[u8]) -> Result<bool> {
+    let public_key = address.decode()?.public_key;
+
+    let wrapped = [b"<Bytes>", message, b"</Bytes>"].concat();
+    let messages = [message, wrapped.as_slice()];
+
+    if let (Ok(public), Ok(signature)) = (
+        schnorrkel::PublicKey::from_bytes(&public_key),
+        schnorrkel::Signature::from_bytes(signature),
+    ) {
+        if messages.iter().any(|message| {
+            public
+                .verify_simple(SIGNING_CONTEXT, message, &signature)
+                .is_ok()
+        }) {
+            return Ok(true);
+        }
+    }
+
+    if let (Ok(public), Ok(signature)) = (
+        ed25519_dalek::PublicKey::from_bytes(&public_key),
+        ed25519_dalek::Signature::from_bytes(signature),
+    ) {
+        if messages
+            .iter()
+            .any(|message| public.verify(message, &signature).is_ok())
+        {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+pub async fn verify_signature(req: web::Json<VerifySignature>) -> HttpResponse {
+    HttpResponse::Ok().json(
+        SignatureVerifier::from_registry()
+            .send(req.into_inner())
+            .await
+            .unwrap(),
+    )
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use ed25519_dalek::Signer;
+
+    /// Encodes the public key as Polkadot address.
+    pub fn encode_address(public_key: &[u8; 32]) -> ChainAddress {
+        ChainAddress::from(crate::ss58::encode(0, public_key))
+    }
+
+    #[test]
+    fn check_sr25519_signature() {
+        let keypair = schnorrkel::Keypair::generate();
+        let address = encode_address(&keypair.public.to_bytes());
+
+        let signature = keypair.sign_simple(SIGNING_CONTEXT, b"challenge");
+        assert!(check_signature(&address, b"challenge", &signature.to_bytes()).unwrap());
+        assert!(!check_signature(&address, b"other", &signature.to_bytes()).unwrap());
+
+        // Wrapped by the wallet.
+        let signature = keypair.sign_simple(SIGNING_CONTEXT, b"<Bytes>challenge</Bytes>");
+        assert!(check_signature(&address, b"challenge", &signature.to_bytes()).unwrap());
+
+        // Signed by a different key.
+        let other = schnorrkel::Keypair::generate();
+        let signature = other.sign_simple(SIGNING_CONTEXT, b"challenge");
+        assert!(!check_signature(&address, b"challenge", &signature.to_bytes()).unwrap());
+    }
+
+    #[test]
+    fn check_ed25519_signature() {
+        let secret = ed25519_dalek::SecretKey::from_bytes(&[7; 32]).unwrap();
+        let public = ed25519_dalek::PublicKey::from(&secret);
+        let keypair = ed25519_dalek::Keypair { secret, public };
+        let address = encode_address(&keypair.public.to_bytes());
+
+        let signature = keypair.sign(b"challenge");
+        assert!(check_signature(&address, b"challenge", &signature.to_bytes()).unwrap());
+        assert!(!check_signature(&address, b"other", &signature.to_bytes()).unwrap());
+
+        // Invalid signatures are rejected.
+        assert!(!check_signature(&address, b"challenge", &[0; 10]).unwrap());
+    }
+
+    #[test]
+    fn check_invalid_address() {
+        let address = ChainAddress::from("Alice".to_string());
+        assert!(check_signature(&address, b"challenge", &[0; 64]).is_err());
+    }
+}
@@ -0,0 +1,302 @@
+use rand::{thread_rng, Rng};
+
+// Amount of words of a word phrase challenge.
+const WORD_COUNT: usize = 6;
+// Length of an alphanumeric challenge.
+const ALPHANUMERIC_LENGTH: usize = 10;
+// Length of a hex challenge, encoding 16 random bytes.
+const HEX_LENGTH: usize = 32;
+// Only the start of a text is searched for challenges, so that long texts,
+// such as encoded attachments, do not produce an excessive amount of
+// candidates.
+const MAX_SCANNED_CHARS: usize = 1024;
+/// Maximum amount of candidates extracted from a single text.
+pub const MAX_CANDIDATES: usize = 4096;
+// Excludes characters which are easily confused, such as `0`/`O` and `1`/`I`/`L`.
+const ALPHANUMERIC_CHARSET: &[u8] = b"23456789ABCDEFGHJKMNPQRSTUVWXYZ";
+
+/// The format of newly generated challenges.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChallengeFormat {
+    /// 32 hex characters, e.g. `8f0c6e3b...`.
+    Hex,
+    /// Six words separated by spaces, e.g. `maple otter ...`.
+    Words,
+    /// Ten uppercase characters without ambiguous ones, e.g. `K7QW4ZP2MX`.
+    Alphanumeric,
+}
+
+impl Default for ChallengeFormat {
+    fn default() -> Self {
+        ChallengeFormat::Hex
+    }
+}
+
+impl ChallengeFormat {
+    pub fn generate(&self) -> String {
+        let mut rng = thread_rng();
+
+        match self {
+            ChallengeFormat::Hex => {
+                let random: [u8; 16] = rng.gen();
+                hex::encode(random)
+            }
+            ChallengeFormat::Words => (0..WORD_COUNT)
+                .map(|_| WORDS[rng.gen_range(0..WORDS.len())])
+                .collect::<Vec<&str>>()
+                .join(" "),
+            ChallengeFormat::Alphanumeric => (0..ALPHANUMERIC_LENGTH)
+                .map(|_| ALPHANUMERIC_CHARSET[rng.gen_range(0..ALPHANUMERIC_CHARSET.len())] as char)
+                .collect(),
+        }
+    }
+    /// Whether the text contains the challenge. The human-friendly formats
+    /// ignore case, whitespace and hyphens.
+    pub fn matches(&self, challenge: &str, text: &str) -> bool {
+        match self {
+            ChallengeFormat::Hex => text.contains(challenge),
+            ChallengeFormat::Words | ChallengeFormat::Alphanumeric => {
+                normalize(text).contains(&normalize(challenge))
+            }
+        }
+    }
+}
+
+fn normalize(text: &str) -> String {
+    text.chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .flat_map(|c| c.to_lowercase())
+        .collect()
+}
+
+/// Extracts the substrings of the text which could be a challenge of any
+/// format, as stored in the database. Uses the same normalization as
+/// `ChallengeFormat::matches`, so every challenge matched by the text is
+/// included. Only the first `limit` words, respectively the first
+/// `MAX_SCANNED_CHARS` characters, are considered and at most `MAX_CANDIDATES`
+/// are returned.
+pub fn candidates(text: &str, limit: usize) -> Vec<String> {
+    let mut candidates = vec![];
+    let text: String = text.chars().take(MAX_SCANNED_CHARS).collect();
+    let text = text
+        .split_whitespace()
+        .take(limit)
+        .collect::<Vec<&str>>()
+        .join(" ");
+
+    // Hex challenges are matched exactly.
+    for token in text.split(|c: char| !c.is_ascii_alphanumeric()) {
+        for i in 0..(token.len() + 1).saturating_sub(HEX_LENGTH) {
+            candidates.push(token[i..i + HEX_LENGTH].to_string());
+        }
+    }
+
+    // The human-friendly formats ignore whitespace and hyphens, but any other
+    // character separates the challenges.
+    for segment in text.split(|c: char| !c.is_alphanumeric() && !c.is_whitespace() && c != '-') {
+        let normalized = normalize(segment);
+
+        let chars: Vec<char> = normalized.to_uppercase().chars().collect();
+        for window in chars.windows(ALPHANUMERIC_LENGTH) {
+            if window
+                .iter()
+                .all(|c| c.is_ascii() && ALPHANUMERIC_CHARSET.contains(&(*c as u8)))
+            {
+                candidates.push(window.iter().collect());
+            }
+        }
+
+        for (i, _) in normalized.char_indices() {
+            find_words(&normalized[i..], &mut vec![], &mut candidates);
+        }
+    }
+
+    candidates.sort();
+    candidates.dedup();
+    candidates.truncate(MAX_CANDIDATES);
+    candidates
+}
+
+/// Collects the word phrases at the start of the normalized text, which
+/// contains the words without separators.
+fn find_words(text: &str, words: &mut Vec<&'static str>, found: &mut Vec<String>) {
+    if words.len() == WORD_COUNT {
+        found.push(words.join(" "));
+        return;
+    }
+
+    for word in WORDS.iter().filter(|word| text.starts_with(*word)) {
+        words.push(*word);
+        find_words(&text[word.len()..], words, found);
+        words.pop();
+    }
+}
+
+#[rustfmt::skip]
+const WORDS: &[&str] = &[
+    "acorn", "album", "alley", "amber", "angle", "apple", "apron", "arena", "armor", "arrow",
+    "atlas", "autumn", "badge", "bagel", "baker", "bamboo", "banana", "banjo", "barn", "basil",
+    "basket", "beach", "beaver", "bench", "berry", "bike", "bird", "bison", "blade", "blanket",
+    "bloom", "board", "boat", "book", "boot", "bottle", "bowl", "brain", "branch", "bread",
+    "brick", "bridge", "brush", "bucket", "buffalo", "bunny", "butter", "cabin", "cactus", "cake",
+    "camel", "camera", "candle", "canoe", "canyon", "card", "carpet", "carrot", "castle", "cave",
+    "cedar", "cello", "chair", "chalk", "cheese", "cherry", "chess", "chicken", "cider", "circle",
+    "clock", "cloud", "clover", "coast", "cobra", "cocoa", "coffee", "comet", "coral", "cotton",
+    "cowboy", "crab", "crane", "crayon", "cricket", "crown", "cube", "cup", "curtain", "daisy",
+    "desert", "dolphin", "donkey", "door", "dragon", "drum", "eagle", "earth", "eel", "ember",
+    "engine", "falcon", "farm", "feather", "fence", "fern", "ferry", "fiddle", "fire", "flag",
+    "flute", "forest", "fossil", "fox", "frog", "fruit", "galaxy", "garden", "garlic", "ginger",
+    "giraffe", "glacier", "globe", "glove", "goat", "gold", "goose", "grape", "gravel", "guitar",
+    "hammer", "harbor", "harp", "hawk", "hazel", "heart", "helmet", "hill", "honey", "horse",
+    "hotel", "igloo", "island", "ivory", "jacket", "jaguar", "jelly", "jewel", "jungle", "kayak",
+    "kettle", "kitten", "kiwi", "koala", "ladder", "lake", "lamp", "lemon", "leopard", "lily",
+    "lion", "lizard", "lobster", "lotus", "magnet", "mango", "maple", "marble", "meadow", "melon",
+    "mirror", "monkey", "moon", "moose", "muffin", "music", "needle", "nest", "noodle", "ocean",
+    "olive", "onion", "orange", "orbit", "otter", "owl", "oyster", "paddle", "palace", "panda",
+    "paper", "parrot", "peach", "peanut", "pearl", "pencil", "pepper", "piano", "pigeon", "pillow",
+    "pilot", "planet", "plum", "pocket", "pony", "potato", "pumpkin", "puzzle", "rabbit", "radio",
+    "rainbow", "raven", "river", "robot", "rocket", "rose", "ruby", "saddle", "salmon", "sand",
+    "scarf", "shark", "sheep", "shell", "silver", "skate", "sled", "snail", "spider", "sponge",
+    "spoon", "squid", "star", "stone", "sugar", "summer", "sunset", "swan", "table", "tiger",
+    "tomato", "tractor", "train", "tulip", "turtle", "valley", "violin", "volcano", "wagon",
+    "walnut", "whale", "window", "winter", "wolf", "yacht", "zebra",
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generate_challenges() {
+        let challenge = ChallengeFormat::Hex.generate();
+        assert_eq!(challenge.len(), 32);
+        assert!(challenge.chars().all(|c| c.is_ascii_hexdigit()));
+
+        let challenge = ChallengeFormat::Words.generate();
+        let words: Vec<&str> = challenge.split(' ').collect();
+        assert_eq!(words.len(), WORD_COUNT);
+        assert!(words.iter().all(|word| WORDS.contains(word)));
+
+        let challenge = ChallengeFormat::Alphanumeric.generate();
+        assert_eq!(challenge.len(), ALPHANUMERIC_LENGTH);
+        assert!(challenge.bytes().all(|c| ALPHANUMERIC_CHARSET.contains(&c)));
+    }
+
+    #[test]
+    fn word_list() {
+        assert_eq!(WORDS.len(), 256);
+
+        let mut words = WORDS.to_vec();
+        words.sort_unstable();
+        words.dedup();
+        assert_eq!(words.len(), WORDS.len());
+    }
+
+    #[test]
+    fn tolerant_matching() {
+        let format = ChallengeFormat::Words;
+        let challenge = "maple otter piano river swan tulip";
+        assert!(format.matches(challenge, "Maple Otter piano river swan tulip"));
+        assert!(format.matches(
+            challenge,
+            "my challenge: maple  otter\npiano-river swan tulip!"
+        ));
+        assert!(!format.matches(challenge, "maple otter piano river swan"));
+
+        let format = ChallengeFormat::Alphanumeric;
+        let challenge = "K7QW4ZP2MX";
+        assert!(format.matches(challenge, "k7qw4zp2mx"));
+        assert!(format.matches(challenge, "K7QW4 ZP2MX"));
+        assert!(!format.matches(challenge, "K7QW4ZP2M"));
+
+        // Hex challenges are matched exactly.
+        let format = ChallengeFormat::Hex;
+        let challenge = "a1b2c3d4e5";
+        assert!(format.matches(challenge, "challenge a1b2c3d4e5"));
+        assert!(!format.matches(challenge, "A1B2C3D4E5"));
+    }
+
+    #[test]
+    fn challenge_candidates() {
+        let found = candidates("Hi, Maple otter piano river swan tulip K7QW4ZP2MX", 100);
+        assert!(found.contains(&"maple otter piano river swan tulip".to_string()));
+        assert!(found.contains(&"K7QW4ZP2MX".to_string()));
+        assert!(!found.contains(&"hi".to_string()));
+
+        let hex = ChallengeFormat::Hex.generate();
+        let found = candidates(&format!("challenge: {}", hex), 100);
+        assert!(found.contains(&hex));
+
+        // Typed with spaces and hyphens.
+        let found = candidates("Challenge: K7QW4 ZP2MX", 100);
+        assert!(found.contains(&"K7QW4ZP2MX".to_string()));
+
+        let found = candidates("k7qw4-zp2mx, mapleotter piano-river\nswan tulip!", 100);
+        assert!(found.contains(&"K7QW4ZP2MX".to_string()));
+        assert!(found.contains(&"maple otter piano river swan tulip".to_string()));
+
+        // Other characters separate the challenges, as for matching.
+        let found = candidates("K7QW4.ZP2MX", 100);
+        assert!(!found.contains(&"K7QW4ZP2MX".to_string()));
+        assert!(!ChallengeFormat::Alphanumeric.matches("K7QW4ZP2MX", "K7QW4.ZP2MX"));
+
+        // Only the first words are considered.
+        let found = candidates("one two K7QW4ZP2MX", 2);
+        assert!(found.is_empty());
+    }
+
+    #[test]
+    fn candidates_of_long_token() {
+        // E.g. a base64 encoded attachment.
+        let mut rng = thread_rng();
+        let charset = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let token: String = (0..1_000_000)
+            .map(|_| charset[rng.gen_range(0..charset.len())] as char)
+            .collect();
+
+        let hex = ChallengeFormat::Hex.generate();
+        let found = candidates(&format!("{} {}", hex, token), 100);
+        assert!(found.contains(&hex));
+        assert!(found.len() <= MAX_CANDIDATES);
+
+        // Challenges after the scanned part are ignored.
+        let found = candidates(&format!("{} {}", token, hex), 100);
+        assert!(!found.contains(&hex));
+        assert!(found.len() <= MAX_CANDIDATES);
+    }
+
+    #[test]
+    fn candidates_of_matching_texts() {
+        for format in [
+            ChallengeFormat::Hex,
+            ChallengeFormat::Words,
+            ChallengeFormat::Alphanumeric,
+        ] {
+            let challenge = format.generate();
+            let spaced: String = challenge
+                .chars()
+                .enumerate()
+                .flat_map(|(i, c)| if i % 3 == 2 { vec![c, ' '] } else { vec![c] })
+                .collect();
+
+            let texts = [
+                challenge.clone(),
+                format!("My challenge: {}.", challenge),
+                challenge.to_lowercase(),
+                challenge.to_uppercase(),
+                spaced.clone(),
+                spaced.replace(' ', "-"),
+            ];
+
+            for text in texts.iter().filter(|text| format.matches(&challenge, text)) {
+                assert!(
+                    candidates(text, 100).contains(&challenge),
+                    "{:?} not found in {:?}",
+                    challenge,
+                    text
+                );
+            }
+        }
+    }
+}
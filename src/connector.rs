@@ -1,8 +1,10 @@
+use crate::adapters::admin::RawFieldName;
+use crate::adapters::{discord, github};
 use crate::display_name::DisplayNameVerifier;
 use crate::primitives::{
-    ChainAddress, ChainName, IdentityContext, IdentityFieldValue, JudgementState, Timestamp,
+    ChainAddress, IdentityContext, IdentityFieldValue, JudgementState, Timestamp,
 };
-use crate::{Database, DisplayNameConfig, Result, WatcherConfig};
+use crate::{Database, DisplayNameConfig, NetworkConfig, Result};
 use actix::io::SinkWrite;
 use actix::io::WriteHandler;
 use actix::prelude::*;
@@ -13,7 +15,7 @@ use awc::{
     BoxedSocket, Client,
 };
 use futures::stream::{SplitSink, StreamExt};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::mpsc::{self, UnboundedSender};
@@ -21,6 +23,10 @@ use tokio::sync::RwLock;
 use tokio::time::sleep;
 use tracing::Instrument;
 
+// The latest version of the Watcher message format. Version 1 only supports
+// the `reasonable` and `erroneous` judgements.
+pub const WATCHER_VERSION: u32 = 2;
+
 // In seconds
 const HEARTBEAT_INTERVAL: u64 = 30;
 #[cfg(not(test))]
@@ -29,6 +35,8 @@ const PENDING_JUDGEMENTS_INTERVAL: u64 = 10;
 const DISPLAY_NAMES_INTERVAL: u64 = 10;
 #[cfg(not(test))]
 const JUDGEMENT_CANDIDATES_INTERVAL: u64 = 10;
+#[cfg(not(test))]
+const EXPIRED_CHALLENGES_INTERVAL: u64 = 60;
 const RECONNECTION_TIMEOUT: u64 = 10;
 
 #[cfg(test)]
@@ -37,31 +45,54 @@ const PENDING_JUDGEMENTS_INTERVAL: u64 = 1;
 const DISPLAY_NAMES_INTERVAL: u64 = 1;
 #[cfg(test)]
 const JUDGEMENT_CANDIDATES_INTERVAL: u64 = 1;
+#[cfg(test)]
+const EXPIRED_CHALLENGES_INTERVAL: u64 = 1;
 
 pub async fn run_connector(
     db: Database,
-    watchers: Vec<WatcherConfig>,
+    networks: Vec<NetworkConfig>,
     dn_config: DisplayNameConfig,
+    second_challenges: Vec<RawFieldName>,
 ) -> Result<()> {
-    if watchers.is_empty() {
-        warn!("No watcher is configured. Cannot process any requests or issue judgments");
+    if networks.is_empty() {
+        warn!("No network is configured. Cannot process any requests or issue judgments");
         return Ok(());
     }
 
-    for config in watchers {
+    for config in &networks {
+        if config.watcher_version() > WATCHER_VERSION {
+            return Err(anyhow!(
+                "version {} of the Watcher message format is not supported",
+                config.watcher_version()
+            ));
+        }
+
+        let judgement = config.default_judgement();
+        if judgement == Judgement::Erroneous
+            || judgement.min_watcher_version() > config.watcher_version()
+        {
+            return Err(anyhow!(
+                "the {} judgement cannot be used as default judgement of the {} network",
+                judgement.as_str(),
+                config.name.as_str()
+            ));
+        }
+    }
+
+    for config in networks {
         let span = info_span!("connector_initialization");
         span.in_scope(|| {
             debug!(
-                network = config.network.as_str(),
-                endpoint = config.endpoint.as_str()
+                network = config.name.as_str(),
+                endpoint = config.watcher.as_str()
             );
         });
 
         async {
             // Start Connector.
             let dn_verifier = DisplayNameVerifier::new(db.clone(), dn_config.clone());
-            let conn =
-                Connector::start(config.endpoint, config.network, db.clone(), dn_verifier).await?;
+            let conn = Connector::start(config, db.clone(), dn_verifier, second_challenges.clone())
+                .await?;
 
             info!("Connection initiated");
             info!("Sending pending judgements request to Watcher");
@@ -104,24 +135,67 @@ pub enum EventType {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct JudgementResponse {
+    // Not included in the first version of the message format.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<u32>,
     pub address: ChainAddress,
     pub judgement: Judgement,
 }
 
+impl JudgementResponse {
+    pub fn new(address: ChainAddress, judgement: Judgement, watcher_version: u32) -> Self {
+        JudgementResponse {
+            version: if watcher_version > 1 {
+                Some(watcher_version)
+            } else {
+                None
+            },
+            address,
+            judgement,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AckResponse {
     result: String,
     address: Option<ChainAddress>,
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
 pub enum Judgement {
     #[serde(rename = "reasonable")]
     Reasonable,
+    #[serde(rename = "knownGood", alias = "known_good")]
+    KnownGood,
+    #[serde(rename = "outOfDate", alias = "out_of_date")]
+    OutOfDate,
+    #[serde(rename = "lowQuality", alias = "low_quality")]
+    LowQuality,
     #[serde(rename = "erroneous")]
     Erroneous,
 }
 
+impl Judgement {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Judgement::Reasonable => "reasonable",
+            Judgement::KnownGood => "known_good",
+            Judgement::OutOfDate => "out_of_date",
+            Judgement::LowQuality => "low_quality",
+            Judgement::Erroneous => "erroneous",
+        }
+    }
+    /// The first version of the Watcher message format which supports the
+    /// judgement.
+    pub fn min_watcher_version(&self) -> u32 {
+        match self {
+            Judgement::Reasonable | Judgement::Erroneous => 1,
+            Judgement::KnownGood | Judgement::OutOfDate | Judgement::LowQuality => 2,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct JudgementRequest {
     pub address: ChainAddress,
@@ -173,7 +247,7 @@ pub enum WatcherMessage {
 #[derive(Debug, Clone, Message)]
 #[rtype(result = "crate::Result<()>")]
 pub enum ClientCommand {
-    ProvideJudgement(IdentityContext),
+    ProvideJudgement(IdentityContext, Judgement),
     RequestPendingJudgements,
     RequestDisplayNames,
     Ping,
@@ -185,8 +259,9 @@ struct Connector {
     sink: Option<SinkWrite<Message, SplitSink<Framed<BoxedSocket, Codec>, Message>>>,
     db: Database,
     dn_verifier: DisplayNameVerifier,
-    endpoint: String,
-    network: ChainName,
+    // Fields which require a second challenge, besides email.
+    second_challenges: Vec<RawFieldName>,
+    config: NetworkConfig,
     outgoing: UnboundedSender<ClientCommand>,
     inserted_states: Arc<RwLock<Vec<JudgementState>>>,
     // Tracks the last message received from the Watcher. If a certain treshold
@@ -196,20 +271,20 @@ struct Connector {
 
 impl Connector {
     async fn start(
-        endpoint: String,
-        network: ChainName,
+        config: NetworkConfig,
         db: Database,
         dn_verifier: DisplayNameVerifier,
+        second_challenges: Vec<RawFieldName>,
     ) -> Result<Addr<Connector>> {
         let (_, framed) = Client::new()
-            .ws(&endpoint)
+            .ws(&config.watcher)
             .max_frame_size(5_000_000)
             .connect()
             .await
             .map_err(|err| {
                 anyhow!(
                     "failed to initiate client connector to {}: {:?}",
-                    endpoint,
+                    config.watcher,
                     err
                 )
             })?;
@@ -225,8 +300,8 @@ impl Connector {
                 sink: Some(SinkWrite::new(sink, ctx)),
                 db,
                 dn_verifier,
-                endpoint,
-                network,
+                second_challenges,
+                config,
                 outgoing,
                 inserted_states: Default::default(),
                 last_watcher_msg: Timestamp::now(),
@@ -260,6 +335,26 @@ impl Connector {
             });
         });
     }
+    // Regenerate any challenges which exceeded the configured TTL.
+    fn start_expired_challenges_task(&self, ctx: &mut Context<Self>) {
+        info!("Starting expired challenge regeneration task");
+
+        let network = self.config.name.clone();
+
+        ctx.run_interval(
+            Duration::new(EXPIRED_CHALLENGES_INTERVAL, 0),
+            move |act, _ctx| {
+                let db = act.db.clone();
+                let network = network.clone();
+
+                actix::spawn(async move {
+                    if let Err(err) = db.process_expired_challenges(&network).await {
+                        error!("Error when regenerating expired challenges: {:?}", err);
+                    }
+                });
+            },
+        );
+    }
     // Request pending judgements every couple of seconds.
     fn start_pending_judgements_task(&self, ctx: &mut Context<Self>) {
         info!("Starting pending judgement requester background task");
@@ -286,21 +381,54 @@ impl Connector {
 
         let db = self.db.clone();
         let addr = ctx.address();
-        let network = self.network;
+        let network = self.config.name.clone();
+        let default_judgement = self.config.default_judgement();
+        let watcher_version = self.config.watcher_version();
 
         ctx.run_interval(
             Duration::new(JUDGEMENT_CANDIDATES_INTERVAL, 0),
             move |_act, _ctx| {
                 let db = db.clone();
                 let addr = addr.clone();
+                let network = network.clone();
 
                 actix::spawn(async move {
                     // Provide judgments for the specific network.
-                    match db.fetch_judgement_candidates(network).await {
+                    match db.fetch_judgement_candidates(&network).await {
                         Ok(completed) => {
                             for state in completed {
-                                info!("Notifying Watcher about judgement: {:?}", state.context);
-                                addr.do_send(ClientCommand::ProvideJudgement(state.context));
+                                let judgement = if state.is_erroneous {
+                                    Judgement::Erroneous
+                                } else {
+                                    match state.judgement_level {
+                                        // The Watcher might no longer support
+                                        // the level after a configuration
+                                        // change. The default judgement is
+                                        // checked on startup.
+                                        Some(level)
+                                            if level.min_watcher_version() > watcher_version =>
+                                        {
+                                            warn!(
+                                                "The Watcher does not support the {} judgement of {:?}, issuing the {} judgement instead",
+                                                level.as_str(),
+                                                state.context,
+                                                default_judgement.as_str()
+                                            );
+                                            default_judgement
+                                        }
+                                        Some(level) => level,
+                                        None => default_judgement,
+                                    }
+                                };
+
+                                info!(
+                                    "Notifying Watcher about {:?} judgement: {:?}",
+                                    judgement, state.context
+                                );
+                                addr.do_send(ClientCommand::ProvideJudgement(
+                                    state.context,
+                                    judgement,
+                                ));
                             }
                         }
                         Err(err) => {
@@ -321,14 +449,15 @@ impl Actor for Connector {
 
         span.in_scope(|| {
             debug!(
-                network = self.network.as_str(),
-                endpoint = self.endpoint.as_str()
+                network = self.config.name.as_str(),
+                endpoint = self.config.watcher.as_str()
             );
 
             // Note: heartbeat task remains disabled.
             //self.start_heartbeat_task(ctx);
             self.start_pending_judgements_task(ctx);
             self.start_dangling_judgements_task(ctx);
+            self.start_expired_challenges_task(ctx);
             self.start_active_display_names_task(ctx);
             self.start_judgement_candidates_task(ctx);
         });
@@ -338,15 +467,15 @@ impl Actor for Connector {
         let span = warn_span!("watcher_connection_drop");
         span.in_scope(|| {
             debug!(
-                network = self.network.as_str(),
-                endpoint = self.endpoint.as_str()
+                network = self.config.name.as_str(),
+                endpoint = self.config.watcher.as_str()
             );
         });
 
-        let endpoint = self.endpoint.clone();
-        let network = self.network;
+        let config = self.config.clone();
         let db = self.db.clone();
         let dn_verifier = self.dn_verifier.clone();
+        let second_challenges = self.second_challenges.clone();
 
         actix::spawn(
             async move {
@@ -354,9 +483,14 @@ impl Actor for Connector {
 
                 let mut counter = 0;
                 loop {
-                    if Connector::start(endpoint.clone(), network, db.clone(), dn_verifier.clone())
-                        .await
-                        .is_err()
+                    if Connector::start(
+                        config.clone(),
+                        db.clone(),
+                        dn_verifier.clone(),
+                        second_challenges.clone(),
+                    )
+                    .await
+                    .is_err()
                     {
                         warn!("Reconnection failed, retrying...");
 
@@ -389,8 +523,8 @@ impl Handler<ClientCommand> for Connector {
         // NOTE: make sure no async code comes after this.
         let _guard = span.enter();
         debug!(
-            network = self.network.as_str(),
-            endpoint = self.endpoint.as_str()
+            network = self.config.name.as_str(),
+            endpoint = self.config.watcher.as_str()
         );
 
         // If the sink (outgoing WS stream) is not configured (i.e. when
@@ -417,16 +551,20 @@ impl Handler<ClientCommand> for Connector {
         }
 
         match msg {
-            ClientCommand::ProvideJudgement(id) => {
-                debug!("Providing judgement over websocket stream: {:?}", id);
+            ClientCommand::ProvideJudgement(id, judgement) => {
+                debug!(
+                    "Providing {:?} judgement over websocket stream: {:?}",
+                    judgement, id
+                );
 
                 sink.write(Message::Text(
                     serde_json::to_string(&ResponseMessage {
                         event: EventType::JudgementResult,
-                        data: JudgementResponse {
-                            address: id.address,
-                            judgement: Judgement::Reasonable,
-                        },
+                        data: JudgementResponse::new(
+                            id.address,
+                            judgement,
+                            self.config.watcher_version(),
+                        ),
                     })
                     .unwrap()
                     .into(),
@@ -479,11 +617,20 @@ impl Handler<WatcherMessage> for Connector {
         /// Handle a judgement request.
         async fn process_request(
             db: &Database,
+            config: &NetworkConfig,
             id: IdentityContext,
             mut accounts: HashMap<AccountType, String>,
             dn_verifier: &DisplayNameVerifier,
+            second_challenges: &[RawFieldName],
             inserted_states: &Arc<RwLock<Vec<JudgementState>>>,
         ) -> Result<()> {
+            // Ignore requests of invalid addresses or of addresses which do
+            // not belong to the network of this connector.
+            if let Err(err) = id.validate(std::slice::from_ref(config)) {
+                warn!("Ignoring judgement request of {:?}: {:?}", id, err);
+                return Ok(());
+            }
+
             // Decode display name if appropriate.
             if let Some((_, val)) = accounts
                 .iter_mut()
@@ -492,7 +639,34 @@ impl Handler<WatcherMessage> for Connector {
                 try_decode_hex(val);
             }
 
-            let state = JudgementState::new(id, accounts.into_iter().map(|a| a.into()).collect());
+            // Verify accounts specified in the additional fields, unless
+            // specified explicitly.
+            if let Some(additional) = accounts.get(&AccountType::Additional) {
+                let extra: Vec<(AccountType, String)> = parse_additional(additional)
+                    .into_iter()
+                    .filter_map(|(key, val)| {
+                        AccountType::from_additional_key(&key).map(|ty| (ty, val))
+                    })
+                    .collect();
+
+                for (ty, val) in extra {
+                    accounts.entry(ty).or_insert(val);
+                }
+            }
+
+            let mut state = JudgementState::new(
+                id,
+                accounts.into_iter().map(|a| a.into()).collect(),
+                db.challenge_format(),
+            );
+
+            for field in &mut state.fields {
+                if let Some(name) = field.value.raw_field_name() {
+                    if second_challenges.contains(&name) {
+                        field.require_second_challenge(db.challenge_format());
+                    }
+                }
+            }
 
             // Add the judgement state that's about to get inserted into the
             // local queue which is then fetched from the unit tests.
@@ -522,9 +696,11 @@ impl Handler<WatcherMessage> for Connector {
         // Update timestamp
         self.last_watcher_msg = Timestamp::now();
 
-        let network = self.network;
+        let config = self.config.clone();
+        let network = config.name.clone();
         let db = self.db.clone();
         let dn_verifier = self.dn_verifier.clone();
+        let second_challenges = self.second_challenges.clone();
         let inserted_states = Arc::clone(&self.inserted_states);
 
         Box::pin(
@@ -549,27 +725,27 @@ impl Handler<WatcherMessage> for Connector {
                     }
                     WatcherMessage::NewJudgementRequest(data) => {
                         let id = IdentityContext::new(data.address, network);
-                        process_request(&db, id, data.accounts, &dn_verifier, &inserted_states).await?;
+                        process_request(&db, &config, id, data.accounts, &dn_verifier, &second_challenges, &inserted_states).await?;
                     }
                     WatcherMessage::PendingJudgementsRequests(data) => {
                         // Convert data.
                         let data: Vec<(IdentityContext, HashMap<AccountType, String>)> = data
                             .into_iter()
                             .map(|req| (
-                                IdentityContext::new(req.address, network),
+                                IdentityContext::new(req.address, network.clone()),
                                 req.accounts
                             ))
                             .collect();
 
                         for (context, accounts) in data {
-                            process_request(&db, context, accounts, &dn_verifier, &inserted_states).await?;
+                            process_request(&db, &config, context, accounts, &dn_verifier, &second_challenges, &inserted_states).await?;
                         }
                     }
                     WatcherMessage::ActiveDisplayNames(data) => {
                         for mut name in data {
                             name.try_decode_hex();
 
-                            let context = IdentityContext::new(name.address, network);
+                            let context = IdentityContext::new(name.address, network.clone());
                             let entry = DisplayNameEntry {
                                 context,
                                 display_name: name.display_name,
@@ -652,8 +828,8 @@ impl StreamHandler<std::result::Result<Frame, WsProtocolError>> for Connector {
         let span = debug_span!("handling_websocket_message");
         span.in_scope(|| {
             debug!(
-                network = self.network.as_str(),
-                endpoint = self.endpoint.as_str()
+                network = self.config.name.as_str(),
+                endpoint = self.config.watcher.as_str()
             );
 
             let addr = ctx.address();
@@ -689,6 +865,22 @@ pub enum AccountType {
     Image,
     #[serde(rename = "additional")]
     Additional,
+    #[serde(rename = "discord")]
+    Discord,
+    #[serde(rename = "github")]
+    GitHub,
+}
+
+impl AccountType {
+    /// Accounts which are commonly specified in the additional fields, by
+    /// the given key.
+    fn from_additional_key(key: &str) -> Option<Self> {
+        match key.trim().to_lowercase().as_str() {
+            "discord" => Some(AccountType::Discord),
+            "github" => Some(AccountType::GitHub),
+            _ => None,
+        }
+    }
 }
 
 impl From<(AccountType, String)> for IdentityFieldValue {
@@ -702,19 +894,110 @@ impl From<(AccountType, String)> for IdentityFieldValue {
             AccountType::Web => IdentityFieldValue::Web(value),
             AccountType::Twitter => IdentityFieldValue::Twitter(value.to_lowercase()),
             AccountType::Matrix => IdentityFieldValue::Matrix(value),
-            AccountType::PGPFingerprint => IdentityFieldValue::PGPFingerprint(()),
-            AccountType::Image => IdentityFieldValue::Image(()),
-            AccountType::Additional => IdentityFieldValue::Additional(()),
+            AccountType::PGPFingerprint => {
+                IdentityFieldValue::PGPFingerprint(normalize_fingerprint(&value))
+            }
+            AccountType::Image => IdentityFieldValue::Image(value),
+            AccountType::Additional => IdentityFieldValue::Additional(parse_additional(&value)),
+            AccountType::Discord => IdentityFieldValue::Discord(discord::normalize_handle(&value)),
+            AccountType::GitHub => IdentityFieldValue::GitHub(github::normalize_handle(&value)),
         }
     }
 }
 
+/// Converts the fingerprint into the format as used by GnuPG: uppercase hex
+/// without whitespace or `0x` prefix.
+fn normalize_fingerprint(fingerprint: &str) -> String {
+    let fingerprint: String = fingerprint.chars().filter(|c| !c.is_whitespace()).collect();
+
+    fingerprint
+        .strip_prefix("0x")
+        .unwrap_or(&fingerprint)
+        .to_uppercase()
+}
+
+/// The additional fields are sent by the Watcher as JSON encoded key/value
+/// pairs, either as a list of pairs or as an object. If the value cannot be
+/// decoded, it is kept as is (with an empty key).
+fn parse_additional(value: &str) -> Vec<(String, String)> {
+    let mut pairs = serde_json::from_str::<Vec<(String, String)>>(value)
+        .or_else(|_| {
+            serde_json::from_str::<BTreeMap<String, String>>(value)
+                .map(|pairs| pairs.into_iter().collect())
+        })
+        .unwrap_or_else(|_| vec![(String::new(), value.to_string())]);
+
+    // Same encoding as display names.
+    for (key, value) in &mut pairs {
+        try_decode_hex(key);
+        try_decode_hex(value);
+    }
+
+    pairs
+}
+
 #[cfg(test)]
 pub mod tests {
     use super::*;
+    use crate::primitives::ChainName;
     use crate::{Database, DisplayNameConfig};
     use tokio::sync::mpsc::UnboundedReceiver;
 
+    #[test]
+    fn judgement_response_versions() {
+        let address = IdentityContext::alice().address;
+
+        // The first version does not include the version field.
+        let resp = JudgementResponse::new(address.clone(), Judgement::Reasonable, 1);
+        assert_eq!(
+            serde_json::to_string(&resp).unwrap(),
+            r#"{"address":"1a2YiGNu1UUhJtihq8961c7FZtWGQuWDVMWTNBKJdmpGhZP","judgement":"reasonable"}"#
+        );
+
+        let resp = JudgementResponse::new(address, Judgement::KnownGood, WATCHER_VERSION);
+        assert_eq!(
+            serde_json::to_string(&resp).unwrap(),
+            r#"{"version":2,"address":"1a2YiGNu1UUhJtihq8961c7FZtWGQuWDVMWTNBKJdmpGhZP","judgement":"knownGood"}"#
+        );
+
+        // Both spellings are accepted in the config.
+        assert_eq!(
+            serde_yaml::from_str::<Judgement>("low_quality").unwrap(),
+            Judgement::LowQuality
+        );
+        assert_eq!(
+            serde_yaml::from_str::<Judgement>("outOfDate").unwrap(),
+            Judgement::OutOfDate
+        );
+    }
+
+    #[test]
+    fn additional_fields() {
+        let expected = vec![
+            ("discord".to_string(), "alice#1234".to_string()),
+            ("github".to_string(), "alice".to_string()),
+        ];
+
+        assert_eq!(
+            parse_additional(r#"[["discord","alice#1234"],["github","alice"]]"#),
+            expected
+        );
+        assert_eq!(
+            parse_additional(r#"{"discord":"alice#1234","github":"alice"}"#),
+            expected
+        );
+        // Hex encoded.
+        assert_eq!(
+            parse_additional(r#"[["0x646973636f7264","alice#1234"],["github","alice"]]"#),
+            expected
+        );
+        // Unknown format.
+        assert_eq!(
+            parse_additional("alice"),
+            vec![(String::new(), "alice".to_string())]
+        );
+    }
+
     impl JudgementRequest {
         pub fn alice() -> Self {
             JudgementRequest {
@@ -759,6 +1042,26 @@ pub mod tests {
 
     impl ConnectorMocker {
         pub fn new(db: Database) -> Self {
+            Self::with_second_challenges(db, vec![])
+        }
+        pub fn with_second_challenges(db: Database, second_challenges: Vec<RawFieldName>) -> Self {
+            Self::with_network(
+                db,
+                NetworkConfig {
+                    name: ChainName::from("polkadot"),
+                    ss58_prefix: 0,
+                    watcher: String::new(),
+                    watcher_version: Some(WATCHER_VERSION),
+                    default_judgement: None,
+                },
+                second_challenges,
+            )
+        }
+        pub fn with_network(
+            db: Database,
+            network: NetworkConfig,
+            second_challenges: Vec<RawFieldName>,
+        ) -> Self {
             let dn_config = DisplayNameConfig {
                 enabled: false,
                 limit: 0.85,
@@ -766,7 +1069,7 @@ pub mod tests {
 
             let dn_verifier = DisplayNameVerifier::new(db.clone(), dn_config);
             let (addr, queue, inserted_states) =
-                Connector::start_testing(ChainName::Polkadot, db, dn_verifier);
+                Connector::start_testing(network, db, dn_verifier, second_challenges);
 
             ConnectorMocker {
                 queue,
@@ -791,7 +1094,7 @@ pub mod tests {
 
             while let Ok(msg) = self.queue.try_recv() {
                 match msg {
-                    ClientCommand::ProvideJudgement(_) => counter.provide_judgement += 1,
+                    ClientCommand::ProvideJudgement(_, _) => counter.provide_judgement += 1,
                     ClientCommand::RequestPendingJudgements => {
                         counter.request_pending_judgements += 1
                     }
@@ -816,9 +1119,10 @@ pub mod tests {
 
     impl Connector {
         fn start_testing(
-            network: ChainName,
+            config: NetworkConfig,
             db: Database,
             dn_verifier: DisplayNameVerifier,
+            second_challenges: Vec<RawFieldName>,
         ) -> (
             Addr<Connector>,
             UnboundedReceiver<ClientCommand>,
@@ -833,8 +1137,8 @@ pub mod tests {
                 sink: None,
                 db,
                 dn_verifier,
-                endpoint: "".to_string(),
-                network,
+                second_challenges,
+                config,
                 outgoing,
                 inserted_states: Arc::clone(&inserted_states),
                 last_watcher_msg: Timestamp::now(),
@@ -1,24 +1,36 @@
 use crate::adapters::admin::RawFieldName;
-use crate::api::VerifyChallenge;
-use crate::connector::DisplayNameEntry;
+use crate::api::{RegenerateChallenge, VerifyChallenge};
+use crate::challenge::{self, ChallengeFormat};
+use crate::connector::{DisplayNameEntry, Judgement};
 use crate::primitives::{
-    ChainName, ChallengeType, Event, ExpectedMessage, ExternalMessage, IdentityContext,
-    IdentityFieldValue, JudgementState, NotificationMessage, Timestamp,
+    ChainName, ChallengeType, EmailAuthentication, Event, ExpectedMessage, ExternalMessage,
+    ExternalMessageType, FailureReason, IdentityContext, IdentityField, IdentityFieldValue,
+    JudgementState, NotificationMessage, Timestamp,
 };
-use crate::Result;
+use crate::{ChallengeConfig, LockoutConfig, Result};
 use bson::{doc, from_document, to_bson, to_document, Bson, Document};
 use futures::StreamExt;
 use mongodb::options::UpdateOptions;
 use mongodb::{Client, Database as MongoDb};
 use rand::{thread_rng, Rng};
 use serde::Serialize;
+use std::time::{Duration, UNIX_EPOCH};
 
 const IDENTITY_COLLECTION: &str = "identities";
 const EVENT_COLLECTION: &str = "event_log";
 const DISPLAY_NAMES: &str = "display_names";
+const EMAIL_CURSORS: &str = "email_cursors";
+const EMAIL_LINKS: &str = "email_links";
+const MESSAGE_CURSORS: &str = "message_cursors";
 
 const DANGLING_THRESHOLD: u64 = 3600; // one hour
 
+// Maximum amount of words of a message that are checked for challenges of
+// other identities.
+const MAX_CHALLENGE_TOKENS: usize = 100;
+// Minimum age of a challenge, in seconds, before users can request a new one.
+const MIN_REGENERATION_INTERVAL: u64 = 60;
+
 /// Convenience trait. Converts a value to BSON.
 trait ToBson {
     fn to_bson(&self) -> Result<Bson>;
@@ -34,17 +46,77 @@ impl<T: Serialize> ToBson for T {
     }
 }
 
+/// Tracks the last processed message of an IMAP mailbox.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct EmailCursor {
+    pub uid_validity: u32,
+    pub last_uid: u32,
+}
+
+/// Tracks the last processed message of a chat, e.g. a Discord channel.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub struct MessageCursor {
+    pub last_id: u64,
+}
+
 #[derive(Debug, Clone)]
 pub struct Database {
     db: MongoDb,
+    lockout: Option<LockoutConfig>,
+    challenge: Option<ChallengeConfig>,
 }
 
 impl Database {
     pub async fn new(uri: &str, db: &str) -> Result<Self> {
         Ok(Database {
             db: Client::with_uri_str(uri).await?.database(db),
+            lockout: None,
+            challenge: None,
         })
     }
+    /// Locks fields after too many failed attempts. Disabled if not specified.
+    pub fn lockout(mut self, config: Option<LockoutConfig>) -> Self {
+        self.lockout = config;
+        self
+    }
+    /// Challenges do not expire if not specified.
+    pub fn challenge(mut self, config: Option<ChallengeConfig>) -> Self {
+        self.challenge = config;
+        self
+    }
+    /// The format of newly generated challenges.
+    pub fn challenge_format(&self) -> ChallengeFormat {
+        self.challenge
+            .as_ref()
+            .and_then(|config| config.format)
+            .unwrap_or_default()
+    }
+    fn requires_signature(&self) -> bool {
+        self.challenge
+            .as_ref()
+            .and_then(|config| config.require_signature)
+            .unwrap_or(false)
+    }
+    /// Creates the indexes which are required by the registrar. Existing
+    /// indexes are left untouched.
+    pub async fn create_indexes(&self) -> Result<()> {
+        // Used verification links are removed once they have expired.
+        self.db
+            .run_command(
+                doc! {
+                    "createIndexes": EMAIL_LINKS,
+                    "indexes": [{
+                        "key": { "expire_at": 1 },
+                        "name": "expire_at_ttl",
+                        "expireAfterSeconds": 0,
+                    }],
+                },
+                None,
+            )
+            .await?;
+
+        Ok(())
+    }
     /// Simply checks if a connection could be established to the database.
     pub async fn connectivity_check(&self) -> Result<()> {
         self.db
@@ -158,7 +230,12 @@ impl Database {
         // Set the appropriate types for verification.
         let update = match field {
             // For "ChallengeType::ExpectedMessage".
-            RawFieldName::Twitter | RawFieldName::Matrix => {
+            RawFieldName::Twitter
+            | RawFieldName::Matrix
+            | RawFieldName::Web
+            | RawFieldName::PGPFingerprint
+            | RawFieldName::Discord
+            | RawFieldName::GitHub => {
                 doc! {
                     "$set": {
                         "fields.$.challenge.content.expected.is_verified": true,
@@ -183,7 +260,7 @@ impl Database {
                 }
             }
             // For "ChallengeType::Unsupported".
-            RawFieldName::LegalName | RawFieldName::Web => {
+            RawFieldName::LegalName | RawFieldName::Image | RawFieldName::Additional => {
                 doc! {
                     "$set": {
                         "fields.$.challenge.content.is_verified": true,
@@ -202,14 +279,42 @@ impl Database {
             .update_one(
                 doc! {
                     "context": context.to_bson()?,
-                    "fields.value.type": field.to_string(),
+                    "fields.value.type": IdentityFieldValue::type_tag(field)?,
                 },
                 update,
                 None,
             )
             .await?;
 
-        if res.modified_count == 0 {
+        let mut modified = res.modified_count;
+
+        // Twitter and Matrix fields might require a second challenge,
+        // depending on the configuration. The first challenge might already
+        // be verified, so this is checked before the early return.
+        if matches!(field, RawFieldName::Twitter | RawFieldName::Matrix) {
+            modified += coll
+                .update_one(
+                    doc! {
+                        "context": context.to_bson()?,
+                        "fields": {
+                            "$elemMatch": {
+                                "value.type": IdentityFieldValue::type_tag(field)?,
+                                "challenge.content.second": { "$type": "object" },
+                            }
+                        },
+                    },
+                    doc! {
+                        "$set": {
+                            "fields.$.challenge.content.second.is_verified": true,
+                        }
+                    },
+                    None,
+                )
+                .await?
+                .modified_count;
+        }
+
+        if modified == 0 {
             return Ok(None);
         }
 
@@ -269,78 +374,94 @@ impl Database {
             let context = id_state.context.clone();
             let field_value = field_state.value.clone();
 
-            let challenge = &mut field_state.challenge;
-            if !challenge.is_verified() {
-                match challenge {
-                    ChallengeType::ExpectedMessage {
-                        ref mut expected,
-                        second,
-                    } => {
-                        // Only proceed if the expected challenge has not been verified yet.
-                        if !expected.is_verified {
-                            if expected.verify_message(message) {
-                                // Update field state. Be more specific with the query in order
-                                // to verify the correct field (in theory, there could be
-                                // multiple pending requests with the same external account
-                                // specified).
-                                coll.update_one(
-                                    doc! {
-                                        "context": context.to_bson()?,
-                                        "fields.value": message.origin.to_bson()?,
-                                    },
-                                    doc! {
-                                        "$set": {
-                                            "fields.$.challenge.content.expected.is_verified": true,
-                                        }
-                                    },
-                                    None,
-                                )
-                                .await?;
-
-                                self.insert_event(NotificationMessage::FieldVerified {
-                                    context: context.clone(),
-                                    field: field_value.clone(),
-                                })
-                                .await?;
-
-                                if second.is_some() {
-                                    self.insert_event(
-                                        NotificationMessage::AwaitingSecondChallenge {
-                                            context: context.clone(),
-                                            field: field_value,
-                                        },
-                                    )
-                                    .await?;
+            // Ignore any attempts during the cooldown.
+            if field_state.is_locked() {
+                debug!("Ignoring message for locked field {:?}", field_value);
+                continue;
+            }
+
+            match &mut field_state.challenge {
+                ChallengeType::ExpectedMessage {
+                    ref mut expected,
+                    second,
+                } => {
+                    if expected.is_verified {
+                        // The challenge was already used, either while the second
+                        // challenge is pending or after the field was verified.
+                        if expected.verify_message(message) {
+                            let reason = match second {
+                                Some(second) if !second.is_verified => {
+                                    FailureReason::StaleChallenge
                                 }
-                            } else {
-                                // Update field state.
-                                coll.update_many(
-                                    doc! {
-                                        "context": context.to_bson()?,
-                                        "fields.value": message.origin.to_bson()?,
-                                    },
-                                    doc! {
-                                        "$inc": {
-                                            "fields.$.failed_attempts": 1isize.to_bson()?,
-                                        }
-                                    },
-                                    None,
-                                )
-                                .await?;
-
-                                self.insert_event(NotificationMessage::FieldVerificationFailed {
-                                    context: context.clone(),
-                                    field: field_value,
-                                })
-                                .await?;
-                            }
+                                _ => FailureReason::AlreadyVerified,
+                            };
+
+                            self.insert_event(NotificationMessage::FieldVerificationFailed {
+                                context: context.clone(),
+                                field: field_value,
+                                reason,
+                            })
+                            .await?;
                         }
+                    } else if expected.verify_message(message) {
+                        let mut update = doc! {
+                            "fields.$.challenge.content.expected.is_verified": true,
+                        };
+
+                        // The second challenge is only sent now.
+                        if second.is_some() {
+                            update.insert(
+                                "fields.$.challenge.content.second.issued_at",
+                                Timestamp::now().to_bson()?,
+                            );
+                        }
+
+                        // Update field state. Be more specific with the query in order
+                        // to verify the correct field (in theory, there could be
+                        // multiple pending requests with the same external account
+                        // specified).
+                        coll.update_one(
+                            doc! {
+                                "context": context.to_bson()?,
+                                "fields.value": message.origin.to_bson()?,
+                            },
+                            doc! {
+                                "$set": update,
+                            },
+                            None,
+                        )
+                        .await?;
+
+                        self.insert_event(NotificationMessage::FieldVerified {
+                            context: context.clone(),
+                            field: field_value.clone(),
+                        })
+                        .await?;
+
+                        if second.is_some() {
+                            self.insert_event(NotificationMessage::AwaitingSecondChallenge {
+                                context: context.clone(),
+                                field: field_value,
+                            })
+                            .await?;
+                        }
+                    } else {
+                        self.record_failed_attempt(&context, &field_value).await?;
+
+                        self.insert_event(NotificationMessage::FieldVerificationFailed {
+                            context: context.clone(),
+                            field: field_value.clone(),
+                            reason: FailureReason::WrongChallenge,
+                        })
+                        .await?;
+
+                        self.enforce_lockout(&context, &field_value).await?;
                     }
-                    _ => {
-                        return Err(anyhow!(
-                            "Invalid challenge type when verifying message. This is a bug"
-                        ))
-                    }
+                }
+                _ => {
+                    return Err(anyhow!(
+                        "Invalid challenge type when verifying message. This is a bug"
+                    ))
                 }
             }
 
@@ -348,13 +469,361 @@ impl Database {
             self.process_fully_verified(&id_state).await?;
         }
 
+        self.check_unrelated_challenges(message).await?;
+
+        Ok(())
+    }
+    /// Notifies the owners of pending challenges which were sent from an
+    /// account that is not part of the corresponding identity.
+    async fn check_unrelated_challenges(&self, message: &ExternalMessage) -> Result<()> {
+        let coll = self.db.collection::<JudgementState>(IDENTITY_COLLECTION);
+
+        let mut tokens: Vec<String> = message
+            .values
+            .iter()
+            .flat_map(|value| challenge::candidates(value.as_str(), MAX_CHALLENGE_TOKENS))
+            // Messages can consist of many values.
+            .take(challenge::MAX_CANDIDATES)
+            .collect();
+
+        tokens.sort();
+        tokens.dedup();
+
+        if tokens.is_empty() {
+            return Ok(());
+        }
+
+        let origin = message.origin.to_document()?;
+        let mut cursor = coll
+            .find(
+                doc! {
+                    "fields": {
+                        "$elemMatch": {
+                            "value.type": origin.get_str("type")?,
+                            "challenge.content.expected.value": {
+                                "$in": tokens,
+                            },
+                        }
+                    }
+                },
+                None,
+            )
+            .await?;
+
+        while let Some(state) = cursor.next().await {
+            let state = state?;
+
+            for field in &state.fields {
+                if !field.value.matches_origin_type(message) || field.value.matches_origin(message)
+                {
+                    continue;
+                }
+
+                if let ChallengeType::ExpectedMessage { expected, .. } = &field.challenge {
+                    if !expected.is_verified && expected.clone().verify_message(message) {
+                        warn!(
+                            "Challenge of {:?} was sent from unrelated account {:?}",
+                            field.value, message.origin
+                        );
+
+                        self.insert_event(NotificationMessage::FieldVerificationFailed {
+                            context: state.context.clone(),
+                            field: field.value.clone(),
+                            reason: FailureReason::UnrelatedAccount,
+                        })
+                        .await?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+    async fn record_failed_attempt(
+        &self,
+        context: &IdentityContext,
+        field: &IdentityFieldValue,
+    ) -> Result<()> {
+        let coll = self.db.collection::<JudgementState>(IDENTITY_COLLECTION);
+
+        coll.update_one(
+            doc! {
+                "context": context.to_bson()?,
+                "fields.value": field.to_bson()?,
+            },
+            doc! {
+                "$inc": {
+                    "failed_attempts": 1isize.to_bson()?,
+                    "fields.$.failed_attempts": 1isize.to_bson()?,
+                }
+            },
+            None,
+        )
+        .await?;
+
+        Ok(())
+    }
+    /// Locks the field, respectively all fields of the identity, if the failed
+    /// attempts exceed the configured thresholds. The pending challenges are
+    /// regenerated and the failed attempts are reset.
+    async fn enforce_lockout(
+        &self,
+        context: &IdentityContext,
+        field: &IdentityFieldValue,
+    ) -> Result<()> {
+        let coll = self.db.collection::<JudgementState>(IDENTITY_COLLECTION);
+
+        let lockout = match &self.lockout {
+            Some(lockout) => lockout,
+            None => return Ok(()),
+        };
+
+        let mut state = match self.fetch_judgement_state(context).await? {
+            Some(state) => state,
+            None => return Ok(()),
+        };
+
+        let identity_attempts = state.failed_attempts;
+        let field_attempts = state
+            .fields
+            .iter()
+            .find(|f| &f.value == field)
+            .map(|f| f.failed_attempts)
+            .unwrap_or(0);
+
+        let exceeded = |threshold: Option<usize>, attempts: usize| {
+            threshold.map(|t| attempts >= t).unwrap_or(false)
+        };
+
+        let locked_field = if exceeded(lockout.identity_threshold, identity_attempts) {
+            None
+        } else if exceeded(lockout.field_threshold, field_attempts) {
+            Some(field.clone())
+        } else {
+            return Ok(());
+        };
+
+        let until = Timestamp::with_offset(lockout.cooldown);
+
+        warn!(
+            "Too many failed attempts of {:?} ({:?}), locked until {}",
+            context,
+            locked_field,
+            until.raw()
+        );
+
+        // The identity counter is only reset by the lockout of the identity.
+        if locked_field.is_none() {
+            coll.update_one(
+                doc! {
+                    "context": context.to_bson()?,
+                },
+                doc! {
+                    "$set": {
+                        "failed_attempts": 0isize.to_bson()?,
+                    }
+                },
+                None,
+            )
+            .await?;
+        }
+
+        // Only update the affected fields, which might be modified
+        // concurrently otherwise.
+        let mut resend = vec![];
+        for field_state in &mut state.fields {
+            if locked_field.is_some() && locked_field.as_ref() != Some(&field_state.value) {
+                continue;
+            }
+
+            if field_state.regenerate_challenge(self.challenge_format()) {
+                resend.push(field_state.value.clone());
+            }
+
+            coll.update_one(
+                doc! {
+                    "context": context.to_bson()?,
+                    "fields.value": field_state.value.to_bson()?,
+                },
+                doc! {
+                    "$set": {
+                        "fields.$.failed_attempts": 0isize.to_bson()?,
+                        "fields.$.locked_until": until.to_bson()?,
+                        "fields.$.challenge": field_state.challenge.to_bson()?,
+                    }
+                },
+                None,
+            )
+            .await?;
+        }
+
+        self.insert_event(NotificationMessage::LockedOut {
+            context: context.clone(),
+            field: locked_field,
+            until,
+        })
+        .await?;
+
+        // Send the new second challenges.
+        for field in resend {
+            self.insert_event(NotificationMessage::AwaitingSecondChallenge {
+                context: context.clone(),
+                field,
+            })
+            .await?;
+        }
+
+        Ok(())
+    }
+    /// Regenerates the pending challenge of the field and notifies the user.
+    async fn replace_challenge(
+        &self,
+        context: &IdentityContext,
+        field_state: &mut IdentityField,
+    ) -> Result<()> {
+        let coll = self.db.collection::<JudgementState>(IDENTITY_COLLECTION);
+
+        let resend = field_state.regenerate_challenge(self.challenge_format());
+
+        coll.update_one(
+            doc! {
+                "context": context.to_bson()?,
+                "fields.value": field_state.value.to_bson()?,
+            },
+            doc! {
+                "$set": {
+                    "fields.$.challenge": field_state.challenge.to_bson()?,
+                }
+            },
+            None,
+        )
+        .await?;
+
+        self.insert_event(NotificationMessage::ChallengeRegenerated {
+            context: context.clone(),
+            field: field_state.value.clone(),
+        })
+        .await?;
+
+        // Send the new second challenge.
+        if resend {
+            self.insert_event(NotificationMessage::AwaitingSecondChallenge {
+                context: context.clone(),
+                field: field_state.value.clone(),
+            })
+            .await?;
+        }
+
+        Ok(())
+    }
+    /// Regenerates all pending challenges of the network which are older than
+    /// the configured TTL.
+    pub async fn process_expired_challenges(&self, network: &ChainName) -> Result<()> {
+        let coll = self.db.collection::<JudgementState>(IDENTITY_COLLECTION);
+
+        let ttl = match self.challenge.as_ref().and_then(|config| config.ttl) {
+            Some(ttl) => ttl,
+            None => return Ok(()),
+        };
+
+        let mut cursor = coll
+            .find(
+                doc! {
+                    "context.chain": network.as_str().to_bson()?,
+                    "is_fully_verified": false,
+                },
+                None,
+            )
+            .await?;
+
+        while let Some(state) = cursor.next().await {
+            let mut state = state?;
+            let inserted = state.inserted_timestamp.clone();
+
+            for field_state in &mut state.fields {
+                let expired = field_state
+                    .pending_challenge()
+                    .map(|challenge| challenge.is_expired(ttl, &inserted))
+                    .unwrap_or(false);
+
+                if expired {
+                    debug!(
+                        "Challenge of {:?} ({:?}) expired",
+                        field_state.value, state.context
+                    );
+                    self.replace_challenge(&state.context, field_state).await?;
+                }
+            }
+        }
+
         Ok(())
     }
+    /// Regenerates the pending challenge on request of the user. Returns
+    /// `false` if there is no pending challenge, the field is locked or the
+    /// challenge was issued too recently.
+    pub async fn regenerate_challenge(&self, request: RegenerateChallenge) -> Result<bool> {
+        let context = &request.context;
+        let field = &request.entry;
+
+        let mut state = match self.fetch_judgement_state(context).await? {
+            Some(state) => state,
+            None => return Ok(false),
+        };
+
+        let inserted = state.inserted_timestamp.clone();
+        let field_state = match state.fields.iter_mut().find(|f| &f.value == field) {
+            Some(field_state) => field_state,
+            None => return Ok(false),
+        };
+
+        if field_state.is_locked() {
+            return Ok(false);
+        }
+
+        // Prevents users from flooding inboxes with second challenges.
+        let recent = match field_state.pending_challenge() {
+            Some(challenge) => !challenge.is_expired(MIN_REGENERATION_INTERVAL, &inserted),
+            None => return Ok(false),
+        };
+
+        if recent {
+            return Ok(false);
+        }
+
+        self.replace_challenge(context, field_state).await?;
+
+        Ok(true)
+    }
+    /// Lifts the lockout of all fields of the identity. Returns `false` if the
+    /// identity was not found.
+    pub async fn unlock(&self, context: &IdentityContext) -> Result<bool> {
+        let coll = self.db.collection::<JudgementState>(IDENTITY_COLLECTION);
+
+        let res = coll
+            .update_one(
+                doc! {
+                    "context": context.to_bson()?,
+                },
+                doc! {
+                    "$set": {
+                        "failed_attempts": 0isize.to_bson()?,
+                        "fields.$[].locked_until": Bson::Null,
+                        "fields.$[].failed_attempts": 0isize.to_bson()?,
+                    }
+                },
+                None,
+            )
+            .await?;
+
+        Ok(res.matched_count > 0)
+    }
     /// Check if all fields have been verified.
     async fn process_fully_verified(&self, state: &JudgementState) -> Result<()> {
         let coll = self.db.collection::<JudgementState>(IDENTITY_COLLECTION);
 
-        if state.check_full_verification() {
+        let signature_verified = !self.requires_signature() || state.is_signature_verified();
+
+        if state.check_full_verification() && signature_verified {
             // Create a timed delay for issuing judgments. Between 30 seconds to
             // 5 minutes. This is used to prevent timing attacks where a user
             // updates the identity right before the judgement is issued.
@@ -406,10 +875,60 @@ impl Database {
 
         Ok(())
     }
-    pub async fn verify_second_challenge(&self, mut request: VerifyChallenge) -> Result<bool> {
+    /// Marks the signature challenge as verified, after the signature was
+    /// checked against the address. Returns `false` if the identity was not
+    /// found.
+    pub async fn verify_signature_challenge(&self, context: &IdentityContext) -> Result<bool> {
         let coll = self.db.collection::<JudgementState>(IDENTITY_COLLECTION);
 
-        let mut verified = false;
+        let res = coll
+            .update_one(
+                doc! {
+                    "context": context.to_bson()?,
+                    "signature_challenge.is_verified": false,
+                },
+                doc! {
+                    "$set": {
+                        "signature_challenge.is_verified": true,
+                    }
+                },
+                None,
+            )
+            .await?;
+
+        if res.modified_count == 0 {
+            // Already verified or not found.
+            return Ok(self.fetch_judgement_state(context).await?.is_some());
+        }
+
+        self.insert_event(NotificationMessage::SignatureVerified {
+            context: context.clone(),
+        })
+        .await?;
+
+        // Check full verification status.
+        if let Some(state) = self.fetch_judgement_state(context).await? {
+            self.process_fully_verified(&state).await?;
+        }
+
+        Ok(true)
+    }
+    pub async fn verify_second_challenge(&self, request: VerifyChallenge) -> Result<bool> {
+        self.verify_second_challenge_with(request, false).await
+    }
+    /// Like `verify_second_challenge`, but ignores fields which require the
+    /// second challenge to be entered manually.
+    pub async fn verify_second_challenge_link(&self, request: VerifyChallenge) -> Result<bool> {
+        self.verify_second_challenge_with(request, true).await
+    }
+    async fn verify_second_challenge_with(
+        &self,
+        mut request: VerifyChallenge,
+        from_link: bool,
+    ) -> Result<bool> {
+        let coll = self.db.collection::<JudgementState>(IDENTITY_COLLECTION);
+
+        let mut verified = false;
 
         // Trim received challenge, just in case.
         request.challenge = request.challenge.trim().to_string();
@@ -435,6 +954,23 @@ impl Database {
             let context = state.context.clone();
             let field_value = field_state.value.clone();
 
+            // Ignore any attempts during the cooldown.
+            if field_state.is_locked() {
+                debug!(
+                    "Ignoring second challenge for locked field {:?}",
+                    field_value
+                );
+                continue;
+            }
+
+            if from_link && field_state.untrusted {
+                debug!(
+                    "Ignoring verification link for untrusted field {:?}",
+                    field_value
+                );
+                continue;
+            }
+
             match &mut field_state.challenge {
                 ChallengeType::ExpectedMessage {
                     expected: _,
@@ -447,35 +983,47 @@ impl Database {
                     }
 
                     let second = second.as_mut().unwrap();
-                    if request.challenge.contains(&second.value) {
+                    if second.matches(&request.challenge) {
+                        // The challenge is matched tolerantly, so the update
+                        // must not filter on the submitted value.
+                        let res = coll
+                            .update_one(
+                                doc! {
+                                    "context": context.to_bson()?,
+                                    "fields.value": field_value.to_bson()?,
+                                },
+                                doc! {
+                                    "$set": {
+                                        "fields.$.challenge.content.second.is_verified": true.to_bson()?,
+                                    }
+                                },
+                                None,
+                            )
+                            .await?;
+
+                        // Already verified.
+                        if res.modified_count == 0 {
+                            continue;
+                        }
+
                         second.set_verified();
                         verified = true;
 
-                        coll.update_one(
-                            doc! {
-                                "fields.value": request.entry.to_bson()?,
-                                "fields.challenge.content.second.value": request.challenge.to_bson()?,
-                            },
-                            doc! {
-                                "$set": {
-                                    "fields.$.challenge.content.second.is_verified": true.to_bson()?,
-                                }
-                            },
-                            None,
-                        )
-                        .await?;
-
                         self.insert_event(NotificationMessage::SecondFieldVerified {
                             context: context.clone(),
                             field: field_value.clone(),
                         })
                         .await?;
                     } else {
+                        self.record_failed_attempt(&context, &field_value).await?;
+
                         self.insert_event(NotificationMessage::SecondFieldVerificationFailed {
                             context: context.clone(),
                             field: field_value.clone(),
                         })
                         .await?;
+
+                        self.enforce_lockout(&context, &field_value).await?;
                     }
                 }
                 _ => {
@@ -489,6 +1037,27 @@ impl Database {
 
         Ok(verified)
     }
+    /// Verifies the PGP fingerprint field of the given identity. The caller
+    /// must ensure that the message was signed by the key of the fingerprint,
+    /// as specified in the message origin. Returns whether the field is
+    /// verified.
+    pub async fn verify_pgp_message(
+        &self,
+        context: &IdentityContext,
+        message: &ExternalMessage,
+    ) -> Result<bool> {
+        self.verify_message(message).await?;
+
+        Ok(self
+            .fetch_judgement_state(context)
+            .await?
+            .map(|state| {
+                state.fields.iter().any(|field| {
+                    field.value.matches_origin(message) && field.challenge.is_verified()
+                })
+            })
+            .unwrap_or(false))
+    }
     pub async fn fetch_second_challenge(
         &self,
         context: &IdentityContext,
@@ -584,9 +1153,315 @@ impl Database {
             Ok(None)
         }
     }
+    /// Fetches the values of all fields of the given type whose (first)
+    /// challenge has not been verified yet. Used by adapters which actively
+    /// look up the challenge, rather than waiting for messages.
+    pub async fn fetch_unverified_fields(
+        &self,
+        field: &RawFieldName,
+    ) -> Result<Vec<IdentityFieldValue>> {
+        let coll = self.db.collection::<JudgementState>(IDENTITY_COLLECTION);
+
+        let mut cursor = coll
+            .find(
+                doc! {
+                    "fields": {
+                        "$elemMatch": {
+                            "value.type": IdentityFieldValue::type_tag(field)?,
+                            "challenge.content.expected.is_verified": false,
+                        }
+                    }
+                },
+                None,
+            )
+            .await?;
+
+        let mut values = vec![];
+        while let Some(state) = cursor.next().await {
+            for field_state in state?.fields {
+                if field_state.value.raw_field_name().as_ref() == Some(field)
+                    && !field_state.challenge.is_verified()
+                    && !values.contains(&field_state.value)
+                {
+                    values.push(field_state.value);
+                }
+            }
+        }
+
+        Ok(values)
+    }
+    pub async fn fetch_email_cursor(&self, mailbox: &str) -> Result<Option<EmailCursor>> {
+        let coll = self.db.collection::<EmailCursor>(EMAIL_CURSORS);
+
+        Ok(coll
+            .find_one(
+                doc! {
+                    "_id": mailbox,
+                },
+                None,
+            )
+            .await?)
+    }
+    /// Resets the cursor of the mailbox to the new `UIDVALIDITY`, unless it
+    /// was already changed by another instance since the cursor was fetched.
+    pub async fn reset_email_cursor(
+        &self,
+        mailbox: &str,
+        previous: Option<&EmailCursor>,
+        uid_validity: u32,
+    ) -> Result<()> {
+        let coll = self.db.collection::<EmailCursor>(EMAIL_CURSORS);
+
+        let cursor = EmailCursor {
+            uid_validity,
+            last_uid: 0,
+        };
+
+        if let Some(previous) = previous {
+            coll.update_one(
+                doc! {
+                    "_id": mailbox,
+                    "uid_validity": previous.uid_validity.to_bson()?,
+                },
+                doc! {
+                    "$set": cursor.to_document()?,
+                },
+                None,
+            )
+            .await?;
+        } else {
+            coll.update_one(
+                doc! {
+                    "_id": mailbox,
+                },
+                doc! {
+                    "$setOnInsert": cursor.to_document()?,
+                },
+                {
+                    let mut opt = UpdateOptions::default();
+                    opt.upsert = Some(true);
+                    Some(opt)
+                },
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
+    /// Advances the cursor of the mailbox to the given UID. Returns `false` if
+    /// the message was already processed, possibly by another instance.
+    pub async fn claim_email_uid(
+        &self,
+        mailbox: &str,
+        uid_validity: u32,
+        uid: u32,
+    ) -> Result<bool> {
+        let coll = self.db.collection::<EmailCursor>(EMAIL_CURSORS);
+
+        let res = coll
+            .update_one(
+                doc! {
+                    "_id": mailbox,
+                    "uid_validity": uid_validity.to_bson()?,
+                    "last_uid": {
+                        "$lt": uid.to_bson()?,
+                    },
+                },
+                doc! {
+                    "$set": {
+                        "last_uid": uid.to_bson()?,
+                    }
+                },
+                None,
+            )
+            .await?;
+
+        Ok(res.modified_count == 1)
+    }
+    pub async fn fetch_message_cursor(&self, chat: &str) -> Result<Option<MessageCursor>> {
+        let coll = self.db.collection::<MessageCursor>(MESSAGE_CURSORS);
+
+        Ok(coll
+            .find_one(
+                doc! {
+                    "_id": chat,
+                },
+                None,
+            )
+            .await?)
+    }
+    /// Advances the cursor of the chat to the given message Id, unless it
+    /// already points to a more recent message.
+    pub async fn advance_message_cursor(&self, chat: &str, last_id: u64) -> Result<()> {
+        let coll = self.db.collection::<MessageCursor>(MESSAGE_CURSORS);
+
+        coll.update_one(
+            doc! {
+                "_id": chat,
+            },
+            doc! {
+                "$max": {
+                    "last_id": last_id.to_bson()?,
+                }
+            },
+            {
+                let mut opt = UpdateOptions::default();
+                opt.upsert = Some(true);
+                Some(opt)
+            },
+        )
+        .await?;
+
+        Ok(())
+    }
+    /// Marks the email verification link with the given signature as used.
+    /// Returns `false` if it was already used.
+    pub async fn claim_email_link(&self, signature: &str, expires: u64) -> Result<bool> {
+        let coll = self.db.collection::<Document>(EMAIL_LINKS);
+
+        let res = coll
+            .update_one(
+                doc! {
+                    "_id": signature,
+                },
+                doc! {
+                    "$setOnInsert": {
+                        "expires": expires.to_bson()?,
+                        // TTL indexes require a date.
+                        "expire_at": bson::DateTime::from_system_time(
+                            UNIX_EPOCH + Duration::from_secs(expires)
+                        ),
+                    }
+                },
+                {
+                    let mut opt = UpdateOptions::default();
+                    opt.upsert = Some(true);
+                    Some(opt)
+                },
+            )
+            .await?;
+
+        Ok(res.upserted_id.is_some())
+    }
+    /// Releases a claimed email verification link, so it can be used again.
+    /// Used if the link could not complete the challenge.
+    pub async fn release_email_link(&self, signature: &str) -> Result<()> {
+        let coll = self.db.collection::<Document>(EMAIL_LINKS);
+
+        coll.delete_one(
+            doc! {
+                "_id": signature,
+            },
+            None,
+        )
+        .await?;
+
+        Ok(())
+    }
+    /// Whether the last email received from the address passed the
+    /// authentication checks. Always `false` if the checks are disabled.
+    pub async fn email_authentication_passed(&self, field: &IdentityFieldValue) -> Result<bool> {
+        let coll = self.db.collection::<JudgementState>(IDENTITY_COLLECTION);
+
+        Ok(coll
+            .find_one(
+                doc! {
+                    "fields": {
+                        "$elemMatch": {
+                            "value": field.to_bson()?,
+                            "authentication.passed": true,
+                        }
+                    }
+                },
+                None,
+            )
+            .await?
+            .is_some())
+    }
+    /// Records the authentication results of the last received email on the
+    /// corresponding fields.
+    pub async fn set_email_authentication(
+        &self,
+        origin: &ExternalMessageType,
+        authentication: &EmailAuthentication,
+    ) -> Result<()> {
+        let coll = self.db.collection::<JudgementState>(IDENTITY_COLLECTION);
+
+        coll.update_many(
+            doc! {
+                "fields.value": origin.to_bson()?,
+            },
+            doc! {
+                "$set": {
+                    "fields.$.authentication": authentication.to_bson()?,
+                }
+            },
+            None,
+        )
+        .await?;
+
+        Ok(())
+    }
+    /// Requires the second challenge of the corresponding pending fields to
+    /// be entered manually, verification links are refused. Adds a second
+    /// challenge to fields which do not have one yet.
+    pub async fn require_second_challenge(&self, origin: &ExternalMessageType) -> Result<()> {
+        let coll = self.db.collection::<JudgementState>(IDENTITY_COLLECTION);
+
+        coll.update_many(
+            doc! {
+                "fields": {
+                    "$elemMatch": {
+                        "value": origin.to_bson()?,
+                        "challenge.type": "expected_message",
+                        "challenge.content.expected.is_verified": false,
+                    }
+                }
+            },
+            doc! {
+                "$set": {
+                    "fields.$.untrusted": true,
+                }
+            },
+            None,
+        )
+        .await?;
+
+        let filter = doc! {
+            "fields": {
+                "$elemMatch": {
+                    "value": origin.to_bson()?,
+                    "challenge.type": "expected_message",
+                    "challenge.content.second": Bson::Null,
+                }
+            }
+        };
+
+        // Each identity gets its own challenge.
+        let mut cursor = coll.find(filter.clone(), None).await?;
+        while let Some(state) = cursor.next().await {
+            let state = state?;
+
+            let mut filter = filter.clone();
+            filter.insert("context", state.context.to_bson()?);
+
+            coll.update_one(
+                filter,
+                doc! {
+                    "$set": {
+                        "fields.$.challenge.content.second": ExpectedMessage::generate(self.challenge_format()).to_bson()?,
+                    }
+                },
+                None,
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
     pub async fn fetch_judgement_candidates(
         &self,
-        network: ChainName,
+        network: &ChainName,
     ) -> Result<Vec<JudgementState>> {
         let coll = self.db.collection::<JudgementState>(IDENTITY_COLLECTION);
 
@@ -594,11 +1469,18 @@ impl Database {
             .find(
                 doc! {
                     "context.chain": network.as_str().to_bson()?,
-                    "is_fully_verified": true,
                     "judgement_submitted": false,
-                    "issue_judgement_at": {
-                        "$lt": Timestamp::now().to_bson()?,
-                    }
+                    "$or": [
+                        {
+                            "is_fully_verified": true,
+                            "issue_judgement_at": {
+                                "$lt": Timestamp::now().to_bson()?,
+                            }
+                        },
+                        {
+                            "is_erroneous": true,
+                        }
+                    ]
                 },
                 None,
             )
@@ -661,6 +1543,15 @@ impl Database {
             let _ = self
                 .verify_manually(context, &RawFieldName::Matrix, false)
                 .await?;
+            let _ = self
+                .verify_manually(context, &RawFieldName::PGPFingerprint, false)
+                .await?;
+            let _ = self
+                .verify_manually(context, &RawFieldName::Discord, false)
+                .await?;
+            let _ = self
+                .verify_manually(context, &RawFieldName::GitHub, false)
+                .await?;
 
             self.insert_event(NotificationMessage::FullManualVerification {
                 context: context.clone(),
@@ -672,6 +1563,74 @@ impl Database {
             Ok(false)
         }
     }
+    /// Marks the identity as fraudulent, which issues an `erroneous` judgement
+    /// instead of a `reasonable` one. Returns `None` if the identity was not
+    /// found and `Some(false)` if a judgement has already been submitted.
+    pub async fn mark_erroneous(&self, context: &IdentityContext) -> Result<Option<bool>> {
+        let coll = self.db.collection::<JudgementState>(IDENTITY_COLLECTION);
+
+        let res = coll
+            .update_one(
+                doc! {
+                    "context": context.to_bson()?,
+                    "judgement_submitted": false,
+                },
+                doc! {
+                    "$set": {
+                        "is_erroneous": true,
+                        "issue_judgement_at": Timestamp::now().to_bson()?,
+                    }
+                },
+                None,
+            )
+            .await?;
+
+        if res.matched_count == 1 {
+            return Ok(Some(true));
+        }
+
+        // Determine whether the identity exists at all.
+        let state = coll
+            .find_one(
+                doc! {
+                    "context": context.to_bson()?,
+                },
+                None,
+            )
+            .await?;
+
+        Ok(state.map(|_| false))
+    }
+    /// Sets the judgement which is issued once the identity is fully
+    /// verified. Returns `false` if the identity was not found, a judgement
+    /// has already been submitted or the identity was marked as erroneous.
+    pub async fn set_judgement_level(
+        &self,
+        context: &IdentityContext,
+        judgement: Judgement,
+    ) -> Result<bool> {
+        let coll = self.db.collection::<JudgementState>(IDENTITY_COLLECTION);
+
+        let res = coll
+            .update_one(
+                doc! {
+                    "context": context.to_bson()?,
+                    "judgement_submitted": false,
+                    "is_erroneous": {
+                        "$ne": true,
+                    },
+                },
+                doc! {
+                    "$set": {
+                        "judgement_level": judgement.to_bson()?,
+                    }
+                },
+                None,
+            )
+            .await?;
+
+        Ok(res.matched_count == 1)
+    }
     pub async fn set_judged(&self, context: &IdentityContext) -> Result<()> {
         let coll = self.db.collection::<JudgementState>(IDENTITY_COLLECTION);
 
@@ -59,7 +59,7 @@ impl DisplayNameVerifier {
         };
 
         let violations = self
-            .check_similarities(name, state.context.chain, Some(&state.context))
+            .check_similarities(name, state.context.chain.clone(), Some(&state.context))
             .await?;
 
         if !violations.is_empty() {
@@ -8,8 +8,12 @@ extern crate serde;
 extern crate async_trait;
 
 use actix::clock::sleep;
+use adapters::admin::RawFieldName;
+use adapters::email::{AuthenticationPolicy, SearchStrategy};
 use adapters::matrix::MatrixHandle;
-use primitives::ChainName;
+use challenge::ChallengeFormat;
+use connector::Judgement;
+use primitives::{check_networks, ChainName};
 use std::fs;
 use std::time::Duration;
 
@@ -23,18 +27,25 @@ use notifier::run_session_notifier;
 
 mod adapters;
 mod api;
+mod challenge;
 mod connector;
 mod database;
 mod display_name;
 mod notifier;
 mod primitives;
+mod ss58;
 #[cfg(test)]
 mod tests;
 
 #[derive(Debug, Deserialize)]
 pub struct Config {
     pub db: DatabaseConfig,
+    pub networks: Vec<NetworkConfig>,
     pub instance: InstanceType,
+    // Disabled if not specified.
+    pub lockout: Option<LockoutConfig>,
+    // Challenges do not expire if not specified.
+    pub challenge: Option<ChallengeConfig>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -52,6 +63,32 @@ pub struct SingleInstanceConfig {
     pub notifier: NotifierConfig,
 }
 
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct NetworkConfig {
+    // E.g. `polkadot`, as used by the UI and the database.
+    pub name: ChainName,
+    pub ss58_prefix: u16,
+    // Websocket endpoint of the Watcher of this network. Only used by the
+    // adapter listener.
+    pub watcher: String,
+    // Version of the message format supported by the Watcher. Version 1 only
+    // supports the `reasonable` and `erroneous` judgements. Defaults to `1`.
+    pub watcher_version: Option<u32>,
+    // Issued unless an admin chose a different judgement. Defaults to
+    // `reasonable`.
+    pub default_judgement: Option<Judgement>,
+}
+
+impl NetworkConfig {
+    pub fn watcher_version(&self) -> u32 {
+        self.watcher_version.unwrap_or(1)
+    }
+    pub fn default_judgement(&self) -> Judgement {
+        self.default_judgement.unwrap_or(Judgement::Reasonable)
+    }
+}
+
 #[derive(Debug, Deserialize, Clone)]
 #[serde(rename_all = "snake_case")]
 pub struct DatabaseConfig {
@@ -59,29 +96,53 @@ pub struct DatabaseConfig {
     pub name: String,
 }
 
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct LockoutConfig {
+    // Failed attempts of a single field, respectively of all fields of an
+    // identity, until the challenge is regenerated and further attempts are
+    // ignored. No limit if not specified.
+    pub field_threshold: Option<usize>,
+    pub identity_threshold: Option<usize>,
+    // In seconds.
+    pub cooldown: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct ChallengeConfig {
+    // In seconds. Pending challenges are regenerated after expiry. Challenges
+    // do not expire if not specified.
+    pub ttl: Option<u64>,
+    // Either `hex`, `words` or `alphanumeric`. Defaults to `hex`.
+    pub format: Option<ChallengeFormat>,
+    // Requires the owner of the address to sign the signature challenge
+    // before a judgement is issued. Disabled by default.
+    pub require_signature: Option<bool>,
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub struct NotifierConfig {
     pub api_address: String,
     pub display_name: DisplayNameConfig,
+    // Must match the secret of the email adapter. Verification links are
+    // rejected if not specified.
+    pub verification_link_secret: Option<String>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub struct AdapterConfig {
-    pub watcher: Vec<WatcherConfig>,
     pub matrix: MatrixConfig,
     pub twitter: TwitterConfig,
     pub email: EmailConfig,
+    pub web: Option<WebConfig>,
+    pub discord: Option<DiscordConfig>,
+    pub github: Option<GitHubConfig>,
     pub display_name: DisplayNameConfig,
 }
 
-#[derive(Debug, Clone, Deserialize)]
-pub struct WatcherConfig {
-    pub network: ChainName,
-    pub endpoint: String,
-}
-
 #[derive(Debug, Clone, Deserialize)]
 pub struct DisplayNameConfig {
     pub enabled: bool,
@@ -97,6 +158,9 @@ pub struct MatrixConfig {
     pub password: String,
     pub db_path: String,
     pub admins: Option<Vec<MatrixHandle>>,
+    // Requires a second challenge, sent to the user via direct message.
+    // Disabled by default.
+    pub second_challenge: Option<bool>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -107,7 +171,26 @@ pub struct TwitterConfig {
     pub api_secret: String,
     pub token: String,
     pub token_secret: String,
+    // Defaults to `https://api.twitter.com` if not specified.
+    pub api_base: Option<String>,
     pub request_interval: u64,
+    // Requires a second challenge, sent to the user via direct message.
+    // Disabled by default.
+    pub second_challenge: Option<bool>,
+    // Uses the default wording if not specified.
+    pub messages: Option<TwitterMessagesConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct TwitterMessagesConfig {
+    // Supports the `{challenge}` placeholder.
+    pub second_challenge: Option<String>,
+    pub verified: Option<String>,
+    pub wrong_challenge: Option<String>,
+    pub unrelated_account: Option<String>,
+    pub stale_challenge: Option<String>,
+    pub already_verified: Option<String>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -116,10 +199,88 @@ pub struct EmailConfig {
     pub enabled: bool,
     pub smtp_server: String,
     pub imap_server: String,
+    // Defaults to `993`.
+    pub imap_port: Option<u16>,
+    // Either `since`, `unseen` or `gmail`. Defaults to `since`.
+    pub search_strategy: Option<SearchStrategy>,
+    // Either `reject`, `flag` or `require_second_challenge`. The DKIM, SPF and
+    // DMARC checks are disabled if not specified.
+    pub authentication_policy: Option<AuthenticationPolicy>,
+    // The `authserv-id` of the receiving mail server, e.g. `mx.google.com`.
+    // Required if an authentication policy is specified.
+    pub authserv_id: Option<String>,
     pub inbox: String,
     pub user: String,
     pub password: String,
     pub request_interval: u64,
+    // Waits for new messages via IMAP IDLE instead of polling every
+    // `request_interval`, if supported by the server. Disabled by default.
+    pub idle: Option<bool>,
+    // Includes a link in the second challenge email, which verifies the
+    // challenge directly. Disabled if not specified.
+    pub verification_link: Option<VerificationLinkConfig>,
+    // Uses the default templates if not specified.
+    pub template: Option<EmailTemplateConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct EmailTemplateConfig {
+    // Defaults to `W3F Registrar Verification Service`.
+    pub subject: Option<String>,
+    // Path to the plain text template.
+    pub text: Option<String>,
+    // Path to the HTML template.
+    pub html: Option<String>,
+    // The web interface, used for the `{ui_link}` placeholder. Defaults to
+    // `https://registrar.web3.foundation/`.
+    pub ui_url: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct VerificationLinkConfig {
+    // The public URL of the session notifier.
+    pub api_base: String,
+    // Must match the secret of the session notifier.
+    pub secret: String,
+    // In seconds. Defaults to one day.
+    pub ttl: Option<u64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct WebConfig {
+    pub enabled: bool,
+    // Defaults to `/.well-known/polkadot-identity.txt`.
+    pub challenge_path: Option<String>,
+    // Address of the DNS resolver for TXT record lookups, e.g. `1.1.1.1:53`.
+    // Defaults to the system configuration.
+    pub dns_resolver: Option<String>,
+    pub request_interval: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct DiscordConfig {
+    pub enabled: bool,
+    pub token: String,
+    // The guild (server) which the users must be members of.
+    pub guild_id: String,
+    // Defaults to `https://discord.com/api/v10`.
+    pub api_base: Option<String>,
+    pub request_interval: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct GitHubConfig {
+    pub enabled: bool,
+    // Optional, but unauthenticated requests are heavily rate limited.
+    pub token: Option<String>,
+    // Defaults to `https://api.github.com`.
+    pub api_base: Option<String>,
+    pub request_interval: u64,
 }
 
 fn open_config() -> Result<Config> {
@@ -137,15 +298,32 @@ fn open_config() -> Result<Config> {
     Ok(config)
 }
 
-async fn config_adapter_listener(db: Database, config: AdapterConfig) -> Result<()> {
-    let watchers = config.watcher.clone();
+async fn config_adapter_listener(
+    db: Database,
+    networks: Vec<NetworkConfig>,
+    config: AdapterConfig,
+) -> Result<()> {
     let dn_config = config.display_name.clone();
-    run_adapters(config.clone(), db.clone()).await?;
-    run_connector(db, watchers, dn_config).await
+
+    // Fields which require a second challenge, besides email.
+    let mut second_challenges = vec![];
+    if config.twitter.second_challenge.unwrap_or(false) {
+        second_challenges.push(RawFieldName::Twitter);
+    }
+    if config.matrix.second_challenge.unwrap_or(false) {
+        second_challenges.push(RawFieldName::Matrix);
+    }
+
+    run_adapters(config.clone(), networks.clone(), db.clone()).await?;
+    run_connector(db, networks, dn_config, second_challenges).await
 }
 
-async fn config_session_notifier(db: Database, not_config: NotifierConfig) -> Result<()> {
-    let lookup = run_rest_api_server(not_config, db.clone()).await?;
+async fn config_session_notifier(
+    db: Database,
+    networks: Vec<NetworkConfig>,
+    not_config: NotifierConfig,
+) -> Result<()> {
+    let lookup = run_rest_api_server(not_config, networks, db.clone()).await?;
 
     actix::spawn(async move { run_session_notifier(db, lookup).await });
 
@@ -156,25 +334,31 @@ pub async fn run() -> Result<()> {
     let root = open_config()?;
     let (db_config, instance) = (root.db, root.instance);
 
+    check_networks(&root.networks)?;
+
     info!("Initializing connection to database");
-    let db = Database::new(&db_config.uri, &db_config.name).await?;
+    let db = Database::new(&db_config.uri, &db_config.name)
+        .await?
+        .lockout(root.lockout)
+        .challenge(root.challenge);
     db.connectivity_check().await?;
+    db.create_indexes().await?;
 
     match instance {
         InstanceType::AdapterListener(config) => {
             info!("Starting adapter listener instance");
-            config_adapter_listener(db, config).await?;
+            config_adapter_listener(db, root.networks, config).await?;
         }
         InstanceType::SessionNotifier(config) => {
             info!("Starting session notifier instance");
-            config_session_notifier(db, config).await?;
+            config_session_notifier(db, root.networks, config).await?;
         }
         InstanceType::SingleInstance(config) => {
             info!("Starting adapter listener and session notifier instances");
             let (adapter_config, notifier_config) = (config.adapter, config.notifier);
 
-            config_adapter_listener(db.clone(), adapter_config).await?;
-            config_session_notifier(db, notifier_config).await?;
+            config_adapter_listener(db.clone(), root.networks.clone(), adapter_config).await?;
+            config_session_notifier(db, root.networks, notifier_config).await?;
         }
     }
 
@@ -1,7 +1,10 @@
 use actix::Message;
 
 use crate::adapters::admin::RawFieldName;
-use crate::connector::DisplayNameEntry;
+use crate::challenge::ChallengeFormat;
+use crate::connector::{DisplayNameEntry, Judgement};
+use crate::ss58::{self, Ss58Address};
+use crate::{NetworkConfig, Result};
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
@@ -17,6 +20,23 @@ impl IdentityContext {
             chain: network,
         }
     }
+    /// Checks whether the address is valid and its SS58 prefix matches the
+    /// configured prefix of the network of the context.
+    pub fn validate(&self, networks: &[NetworkConfig]) -> Result<()> {
+        let network = networks
+            .iter()
+            .find(|network| network.name == self.chain)
+            .ok_or_else(|| anyhow!("unsupported network {}", self.chain.as_str()))?;
+
+        if self.address.decode()?.prefix != network.ss58_prefix {
+            return Err(anyhow!(
+                "address does not belong to the {} network",
+                self.chain.as_str()
+            ));
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
@@ -27,6 +47,29 @@ impl ChainAddress {
     pub fn as_str(&self) -> &str {
         self.0.as_str()
     }
+    /// Decodes the SS58 address, including checksum validation.
+    pub fn decode(&self) -> Result<Ss58Address> {
+        ss58::decode(self.as_str())
+    }
+    /// Detects the network of the address based on its SS58 prefix, as
+    /// specified in the configured networks. Fails if multiple networks share
+    /// the prefix, e.g. a relay chain and its parachains, in which case the
+    /// network must be specified explicitly.
+    pub fn chain(&self, networks: &[NetworkConfig]) -> Result<ChainName> {
+        let prefix = self.decode()?.prefix;
+        let mut matching = networks
+            .iter()
+            .filter(|network| network.ss58_prefix == prefix);
+
+        match (matching.next(), matching.next()) {
+            (Some(network), None) => Ok(network.name.clone()),
+            (Some(_), Some(_)) => Err(anyhow!(
+                "network prefix {} is shared by multiple networks, the network must be specified",
+                prefix
+            )),
+            (None, _) => Err(anyhow!("unsupported network prefix {}", prefix)),
+        }
+    }
 }
 
 impl From<String> for ChainAddress {
@@ -35,19 +78,34 @@ impl From<String> for ChainAddress {
     }
 }
 
-#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
-#[serde(rename_all = "snake_case")]
-pub enum ChainName {
-    Polkadot,
-    Kusama,
+/// Checks that each network is configured only once. Networks can share the
+/// same SS58 prefix, e.g. a relay chain and its parachains.
+pub fn check_networks(networks: &[NetworkConfig]) -> Result<()> {
+    for (i, network) in networks.iter().enumerate() {
+        if networks[..i].iter().any(|other| other.name == network.name) {
+            return Err(anyhow!(
+                "network '{}' is configured more than once",
+                network.name.as_str()
+            ));
+        }
+    }
+
+    Ok(())
 }
 
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ChainName(String);
+
 impl ChainName {
     pub fn as_str(&self) -> &str {
-        match self {
-            ChainName::Polkadot => "polkadot",
-            ChainName::Kusama => "kusama",
-        }
+        self.0.as_str()
+    }
+}
+
+impl From<&str> for ChainName {
+    fn from(v: &str) -> Self {
+        ChainName(v.to_string())
     }
 }
 
@@ -57,17 +115,27 @@ pub struct IdentityField {
     pub value: IdentityFieldValue,
     pub challenge: ChallengeType,
     pub failed_attempts: usize,
+    // Authentication results of the last received message. Only applies to
+    // email fields if the authentication checks are enabled.
+    #[serde(default)]
+    pub authentication: Option<EmailAuthentication>,
+    // Set if the first challenge was received in a message that failed the
+    // authentication checks. The second challenge must then be entered
+    // manually, verification links are refused.
+    #[serde(default)]
+    pub untrusted: bool,
+    // Attempts are ignored until then, after too many failed attempts.
+    #[serde(default)]
+    pub locked_until: Option<Timestamp>,
 }
 
 impl IdentityField {
-    pub fn new(val: IdentityFieldValue) -> Self {
+    pub fn new(val: IdentityFieldValue, format: ChallengeFormat) -> Self {
         use IdentityFieldValue::*;
 
         let challenge = {
             match val {
                 LegalName(_) => ChallengeType::Unsupported { is_verified: None },
-                Web(_) => ChallengeType::Unsupported { is_verified: None },
-                PGPFingerprint(_) => ChallengeType::Unsupported { is_verified: None },
                 Image(_) => ChallengeType::Unsupported { is_verified: None },
                 Additional(_) => ChallengeType::Unsupported { is_verified: None },
                 DisplayName(_) => ChallengeType::DisplayNameCheck {
@@ -75,15 +143,31 @@ impl IdentityField {
                     violations: vec![],
                 },
                 Email(_) => ChallengeType::ExpectedMessage {
-                    expected: ExpectedMessage::random(),
-                    second: Some(ExpectedMessage::random()),
+                    expected: ExpectedMessage::generate(format),
+                    second: Some(ExpectedMessage::generate(format)),
                 },
                 Twitter(_) => ChallengeType::ExpectedMessage {
-                    expected: ExpectedMessage::random(),
+                    expected: ExpectedMessage::generate(format),
                     second: None,
                 },
                 Matrix(_) => ChallengeType::ExpectedMessage {
-                    expected: ExpectedMessage::random(),
+                    expected: ExpectedMessage::generate(format),
+                    second: None,
+                },
+                Web(_) => ChallengeType::ExpectedMessage {
+                    expected: ExpectedMessage::generate(format),
+                    second: None,
+                },
+                PGPFingerprint(_) => ChallengeType::ExpectedMessage {
+                    expected: ExpectedMessage::generate(format),
+                    second: None,
+                },
+                Discord(_) => ChallengeType::ExpectedMessage {
+                    expected: ExpectedMessage::generate(format),
+                    second: None,
+                },
+                GitHub(_) => ChallengeType::ExpectedMessage {
+                    expected: ExpectedMessage::generate(format),
                     second: None,
                 },
             }
@@ -93,10 +177,95 @@ impl IdentityField {
             value: val,
             challenge,
             failed_attempts: 0,
+            authentication: None,
+            untrusted: false,
+            locked_until: None,
+        }
+    }
+    pub fn is_locked(&self) -> bool {
+        self.locked_until
+            .as_ref()
+            .map(|until| until.raw() > Timestamp::now().raw())
+            .unwrap_or(false)
+    }
+    /// The challenge which is currently expected from the user, if any.
+    pub fn pending_challenge(&self) -> Option<&ExpectedMessage> {
+        if let ChallengeType::ExpectedMessage { expected, second } = &self.challenge {
+            if !expected.is_verified {
+                return Some(expected);
+            } else if let Some(second) = second {
+                if !second.is_verified {
+                    return Some(second);
+                }
+            }
+        }
+
+        None
+    }
+    /// Replaces the pending challenge with a new one. Returns `true` if the
+    /// second challenge was replaced, which must be sent to the user again.
+    pub fn regenerate_challenge(&mut self, format: ChallengeFormat) -> bool {
+        if let ChallengeType::ExpectedMessage { expected, second } = &mut self.challenge {
+            if !expected.is_verified {
+                *expected = ExpectedMessage::generate(format);
+            } else if let Some(second) = second {
+                if !second.is_verified {
+                    *second = ExpectedMessage::generate(format);
+                    return true;
+                }
+            }
+        }
+
+        false
+    }
+    /// Requires a second challenge which is sent to the user directly, after
+    /// the first challenge has been verified. Only applies to fields that are
+    /// verified by an expected message.
+    pub fn require_second_challenge(&mut self, format: ChallengeFormat) {
+        if let ChallengeType::ExpectedMessage { second, .. } = &mut self.challenge {
+            if second.is_none() {
+                *second = Some(ExpectedMessage::generate(format));
+            }
         }
     }
 }
 
+/// The DKIM, SPF and DMARC results of a received email, as reported by the
+/// receiving mail server.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct EmailAuthentication {
+    pub dkim: AuthenticationCheck,
+    pub spf: AuthenticationCheck,
+    pub dmarc: AuthenticationCheck,
+    pub passed: bool,
+    pub timestamp: Timestamp,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct AuthenticationCheck {
+    // E.g. `pass`, `fail` or `none` if not reported.
+    pub result: String,
+    // The authenticated domain, if reported.
+    pub domain: Option<String>,
+    // Whether the authenticated domain is aligned with the `From` domain.
+    pub aligned: bool,
+}
+
+impl AuthenticationCheck {
+    pub fn none() -> Self {
+        AuthenticationCheck {
+            result: "none".to_string(),
+            domain: None,
+            aligned: false,
+        }
+    }
+    pub fn is_aligned_pass(&self) -> bool {
+        self.result == "pass" && self.aligned
+    }
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case", tag = "type", content = "content")]
 pub enum ChallengeType {
@@ -138,21 +307,34 @@ impl ChallengeType {
 pub struct ExpectedMessage {
     pub value: String,
     pub is_verified: bool,
+    // Not present for challenges created by older versions.
+    #[serde(default)]
+    pub issued_at: Option<Timestamp>,
+    #[serde(default)]
+    pub format: ChallengeFormat,
 }
 
 impl ExpectedMessage {
     pub fn random() -> Self {
-        use rand::{thread_rng, Rng};
-
-        let random: [u8; 16] = thread_rng().gen();
+        Self::generate(ChallengeFormat::Hex)
+    }
+    pub fn generate(format: ChallengeFormat) -> Self {
         ExpectedMessage {
-            value: hex::encode(random),
+            value: format.generate(),
             is_verified: false,
+            issued_at: Some(Timestamp::now()),
+            format,
         }
     }
+    /// Whether the challenge is older than `ttl` seconds. Challenges without an
+    /// issued-at timestamp fall back to `fallback`.
+    pub fn is_expired(&self, ttl: u64, fallback: &Timestamp) -> bool {
+        let issued_at = self.issued_at.as_ref().unwrap_or(fallback);
+        issued_at.raw().saturating_add(ttl) <= Timestamp::now().raw()
+    }
     pub fn verify_message(&mut self, message: &ExternalMessage) -> bool {
         for value in &message.values {
-            if value.0.contains(&self.value) {
+            if self.matches(&value.0) {
                 self.set_verified();
                 return true;
             }
@@ -160,6 +342,11 @@ impl ExpectedMessage {
 
         false
     }
+    /// Whether the text contains the challenge, tolerating case and
+    /// whitespace for the human-friendly formats.
+    pub fn matches(&self, text: &str) -> bool {
+        self.format.matches(&self.value, text)
+    }
     pub fn set_verified(&mut self) {
         self.is_verified = true;
     }
@@ -178,9 +365,19 @@ pub enum IdentityFieldValue {
     Web(String),
     Twitter(String),
     Matrix(String),
-    PGPFingerprint(()),
-    Image(()),
-    Additional(()),
+    // Older entries did not store the fingerprint. The tag is kept as is, since
+    // it is part of the stored state and the API.
+    #[serde(deserialize_with = "null_as_default")]
+    PGPFingerprint(String),
+    // Older entries did not store the image.
+    #[serde(deserialize_with = "null_as_default")]
+    Image(String),
+    // Key/value pairs. Older entries did not store those.
+    #[serde(deserialize_with = "null_as_default")]
+    Additional(Vec<(String, String)>),
+    Discord(String),
+    #[serde(rename = "github")]
+    GitHub(String),
 }
 
 impl IdentityFieldValue {
@@ -198,9 +395,96 @@ impl IdentityFieldValue {
                 ExternalMessageType::Matrix(n2) => n1 == n2,
                 _ => false,
             },
+            IdentityFieldValue::Web(n1) => match &message.origin {
+                ExternalMessageType::Web(n2) => n1 == n2,
+                _ => false,
+            },
+            IdentityFieldValue::PGPFingerprint(n1) => match &message.origin {
+                ExternalMessageType::PGPFingerprint(n2) => n1 == n2,
+                _ => false,
+            },
+            IdentityFieldValue::Discord(n1) => match &message.origin {
+                ExternalMessageType::Discord(n2) => n1 == n2,
+                _ => false,
+            },
+            IdentityFieldValue::GitHub(n1) => match &message.origin {
+                ExternalMessageType::GitHub(n2) => n1 == n2,
+                _ => false,
+            },
             _ => false,
         }
     }
+    /// Whether the field is of the same type as the origin of the message,
+    /// e.g. both are email addresses.
+    pub fn matches_origin_type(&self, message: &ExternalMessage) -> bool {
+        use ExternalMessageType as T;
+        use IdentityFieldValue as F;
+
+        matches!(
+            (self, &message.origin),
+            (F::Email(_), T::Email(_))
+                | (F::Twitter(_), T::Twitter(_))
+                | (F::Matrix(_), T::Matrix(_))
+                | (F::Web(_), T::Web(_))
+                | (F::PGPFingerprint(_), T::PGPFingerprint(_))
+                | (F::Discord(_), T::Discord(_))
+                | (F::GitHub(_), T::GitHub(_))
+        )
+    }
+    /// Returns the field name as used by the admin interface, if the field can
+    /// be addressed individually.
+    pub fn raw_field_name(&self) -> Option<RawFieldName> {
+        match self {
+            IdentityFieldValue::LegalName(_) => Some(RawFieldName::LegalName),
+            IdentityFieldValue::DisplayName(_) => Some(RawFieldName::DisplayName),
+            IdentityFieldValue::Email(_) => Some(RawFieldName::Email),
+            IdentityFieldValue::Web(_) => Some(RawFieldName::Web),
+            IdentityFieldValue::Twitter(_) => Some(RawFieldName::Twitter),
+            IdentityFieldValue::Matrix(_) => Some(RawFieldName::Matrix),
+            IdentityFieldValue::PGPFingerprint(_) => Some(RawFieldName::PGPFingerprint),
+            IdentityFieldValue::Image(_) => Some(RawFieldName::Image),
+            IdentityFieldValue::Additional(_) => Some(RawFieldName::Additional),
+            IdentityFieldValue::Discord(_) => Some(RawFieldName::Discord),
+            IdentityFieldValue::GitHub(_) => Some(RawFieldName::GitHub),
+        }
+    }
+    /// Returns the `type` tag under which values of the given field are
+    /// stored, as produced by the serde representation of the value.
+    pub fn type_tag(field: &RawFieldName) -> Result<String> {
+        let value = match field {
+            RawFieldName::LegalName => IdentityFieldValue::LegalName(String::new()),
+            RawFieldName::DisplayName => IdentityFieldValue::DisplayName(String::new()),
+            RawFieldName::Email => IdentityFieldValue::Email(String::new()),
+            RawFieldName::Web => IdentityFieldValue::Web(String::new()),
+            RawFieldName::Twitter => IdentityFieldValue::Twitter(String::new()),
+            RawFieldName::Matrix => IdentityFieldValue::Matrix(String::new()),
+            RawFieldName::PGPFingerprint => IdentityFieldValue::PGPFingerprint(String::new()),
+            RawFieldName::Image => IdentityFieldValue::Image(String::new()),
+            RawFieldName::Additional => IdentityFieldValue::Additional(vec![]),
+            RawFieldName::Discord => IdentityFieldValue::Discord(String::new()),
+            RawFieldName::GitHub => IdentityFieldValue::GitHub(String::new()),
+            RawFieldName::All => {
+                return Err(anyhow!("field name 'all' has no stored type"));
+            }
+        };
+
+        serde_json::to_value(&value)?
+            .get("type")
+            .and_then(|tag| tag.as_str())
+            .map(|tag| tag.to_string())
+            .ok_or_else(|| anyhow!("failed to determine the stored type of {}", field))
+    }
+}
+
+/// Deserializes `null` as the default value of the type.
+fn null_as_default<'de, D, T>(deserializer: D) -> std::result::Result<T, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Default + serde::Deserialize<'de>,
+{
+    use serde::Deserialize;
+
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
@@ -212,6 +496,12 @@ pub struct JudgementStateBlanked {
     pub completion_timestamp: Option<Timestamp>,
     pub judgement_submitted: bool,
     pub fields: Vec<IdentityFieldBlanked>,
+    #[serde(default)]
+    pub signature_challenge: Option<ExpectedMessage>,
+    #[serde(default)]
+    pub is_erroneous: bool,
+    #[serde(default)]
+    pub judgement_level: Option<Judgement>,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
@@ -220,6 +510,8 @@ pub struct IdentityFieldBlanked {
     pub value: IdentityFieldValue,
     pub challenge: ChallengeTypeBlanked,
     failed_attempts: usize,
+    authentication: Option<EmailAuthentication>,
+    locked_until: Option<Timestamp>,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
@@ -278,8 +570,13 @@ impl From<JudgementState> for JudgementStateBlanked {
                         }
                     },
                     failed_attempts: f.failed_attempts,
+                    authentication: f.authentication,
+                    locked_until: f.locked_until,
                 })
                 .collect(),
+            signature_challenge: s.signature_challenge,
+            is_erroneous: s.is_erroneous,
+            judgement_level: s.judgement_level,
         }
     }
 }
@@ -294,10 +591,31 @@ pub struct JudgementState {
     pub judgement_submitted: bool,
     pub issue_judgement_at: Option<Timestamp>,
     pub fields: Vec<IdentityField>,
+    // Signed with the key of the address, proving that the owner of the
+    // address requested the judgement. Not present for requests created by
+    // older versions.
+    #[serde(default)]
+    pub signature_challenge: Option<ExpectedMessage>,
+    // Judged as fraudulent by an admin. An `erroneous` judgement is issued
+    // instead, independent of the verification state.
+    #[serde(default)]
+    pub is_erroneous: bool,
+    // Chosen by an admin. The default judgement of the network is issued if
+    // not specified.
+    #[serde(default)]
+    pub judgement_level: Option<Judgement>,
+    // Failed attempts of all fields, which are not reset by the lockout of a
+    // single field.
+    #[serde(default)]
+    pub failed_attempts: usize,
 }
 
 impl JudgementState {
-    pub fn new(context: IdentityContext, fields: Vec<IdentityFieldValue>) -> Self {
+    pub fn new(
+        context: IdentityContext,
+        fields: Vec<IdentityFieldValue>,
+        format: ChallengeFormat,
+    ) -> Self {
         JudgementState {
             context,
             is_fully_verified: false,
@@ -305,7 +623,14 @@ impl JudgementState {
             completion_timestamp: None,
             judgement_submitted: false,
             issue_judgement_at: None,
-            fields: fields.into_iter().map(IdentityField::new).collect(),
+            fields: fields
+                .into_iter()
+                .map(|val| IdentityField::new(val, format))
+                .collect(),
+            signature_challenge: Some(ExpectedMessage::generate(format)),
+            is_erroneous: false,
+            judgement_level: None,
+            failed_attempts: 0,
         }
     }
     pub fn check_full_verification(&self) -> bool {
@@ -313,6 +638,14 @@ impl JudgementState {
             .iter()
             .all(|field| field.challenge.is_verified())
     }
+    /// Requests created by older versions do not have a signature challenge
+    /// and are considered verified.
+    pub fn is_signature_verified(&self) -> bool {
+        self.signature_challenge
+            .as_ref()
+            .map(|challenge| challenge.is_verified)
+            .unwrap_or(true)
+    }
     pub fn display_name(&self) -> Option<&str> {
         self.fields
             .iter()
@@ -340,6 +673,13 @@ pub enum ExternalMessageType {
     Email(String),
     Twitter(String),
     Matrix(String),
+    Web(String),
+    // Must match the tag of `IdentityFieldValue`, which the origin is compared
+    // with.
+    PGPFingerprint(String),
+    Discord(String),
+    #[serde(rename = "github")]
+    GitHub(String),
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
@@ -393,6 +733,12 @@ impl From<String> for MessagePart {
     }
 }
 
+impl MessagePart {
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub struct Event {
@@ -432,6 +778,9 @@ pub enum NotificationMessage {
     FieldVerificationFailed {
         context: IdentityContext,
         field: IdentityFieldValue,
+        // Not present in events created by older versions.
+        #[serde(default)]
+        reason: FailureReason,
     },
     SecondFieldVerified {
         context: IdentityContext,
@@ -445,6 +794,22 @@ pub enum NotificationMessage {
         context: IdentityContext,
         field: IdentityFieldValue,
     },
+    // Too many failed attempts, the challenge was regenerated. Applies to all
+    // fields if no field is specified.
+    LockedOut {
+        context: IdentityContext,
+        field: Option<IdentityFieldValue>,
+        until: Timestamp,
+    },
+    // The owner of the address signed the signature challenge.
+    SignatureVerified {
+        context: IdentityContext,
+    },
+    // The challenge expired or the user requested a new one.
+    ChallengeRegenerated {
+        context: IdentityContext,
+        field: IdentityFieldValue,
+    },
     IdentityFullyVerified {
         context: IdentityContext,
     },
@@ -468,10 +833,13 @@ impl NotificationMessage {
             IdentityInserted { context } => context,
             IdentityUpdated { context } => context,
             FieldVerified { context, field: _ } => context,
-            FieldVerificationFailed { context, field: _ } => context,
+            FieldVerificationFailed { context, .. } => context,
             SecondFieldVerified { context, field: _ } => context,
             SecondFieldVerificationFailed { context, field: _ } => context,
             AwaitingSecondChallenge { context, field: _ } => context,
+            LockedOut { context, .. } => context,
+            ChallengeRegenerated { context, field: _ } => context,
+            SignatureVerified { context } => context,
             IdentityFullyVerified { context } => context,
             JudgementProvided { context } => context,
             ManuallyVerified { context, field: _ } => context,
@@ -480,6 +848,46 @@ impl NotificationMessage {
     }
 }
 
+/// Why a challenge was rejected.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureReason {
+    /// The message does not contain the expected challenge.
+    WrongChallenge,
+    /// The challenge was sent from an account that is not part of the
+    /// identity.
+    UnrelatedAccount,
+    /// The first challenge was already used, the second challenge is
+    /// expected instead.
+    StaleChallenge,
+    /// The field is already verified.
+    AlreadyVerified,
+}
+
+impl Default for FailureReason {
+    fn default() -> Self {
+        FailureReason::WrongChallenge
+    }
+}
+
+impl FailureReason {
+    /// A message which can be sent to the user directly.
+    pub fn description(&self) -> &'static str {
+        match self {
+            FailureReason::WrongChallenge => {
+                "The challenge is invalid, please check the web interface and try again."
+            }
+            FailureReason::UnrelatedAccount => {
+                "Your challenge was sent from an account that is not part of your identity, please check the web interface."
+            }
+            FailureReason::StaleChallenge => {
+                "The challenge was already used, please check the web interface."
+            }
+            FailureReason::AlreadyVerified => "Your account is already verified.",
+        }
+    }
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub struct IdentityJudged {
@@ -497,7 +905,7 @@ mod tests {
                 address: ChainAddress(
                     "1a2YiGNu1UUhJtihq8961c7FZtWGQuWDVMWTNBKJdmpGhZP".to_string(),
                 ),
-                chain: ChainName::Polkadot,
+                chain: ChainName::from("polkadot"),
             }
         }
         pub fn bob() -> Self {
@@ -505,7 +913,7 @@ mod tests {
                 address: ChainAddress(
                     "1b3NhsSEqWSQwS6nPGKgCrSjv9Kp13CnhraLV5Coyd8ooXB".to_string(),
                 ),
-                chain: ChainName::Polkadot,
+                chain: ChainName::from("polkadot"),
             }
         }
     }
@@ -520,11 +928,18 @@ mod tests {
                 judgement_submitted: false,
                 issue_judgement_at: None,
                 fields: vec![
-                    IdentityField::new(IdentityFieldValue::ALICE_DISPLAY_NAME()),
-                    IdentityField::new(IdentityFieldValue::ALICE_EMAIL()),
-                    IdentityField::new(IdentityFieldValue::ALICE_TWITTER()),
-                    IdentityField::new(IdentityFieldValue::ALICE_MATRIX()),
+                    IdentityField::new(
+                        IdentityFieldValue::ALICE_DISPLAY_NAME(),
+                        ChallengeFormat::Hex,
+                    ),
+                    IdentityField::new(IdentityFieldValue::ALICE_EMAIL(), ChallengeFormat::Hex),
+                    IdentityField::new(IdentityFieldValue::ALICE_TWITTER(), ChallengeFormat::Hex),
+                    IdentityField::new(IdentityFieldValue::ALICE_MATRIX(), ChallengeFormat::Hex),
                 ],
+                signature_challenge: Some(ExpectedMessage::random()),
+                is_erroneous: false,
+                judgement_level: None,
+                failed_attempts: 0,
             }
         }
         pub fn get_field<'a>(&'a self, ty: &IdentityFieldValue) -> &'a IdentityField {
@@ -544,6 +959,10 @@ mod tests {
                 ExternalMessageType::Email(n) => IdentityFieldValue::Email(n),
                 ExternalMessageType::Twitter(n) => IdentityFieldValue::Twitter(n),
                 ExternalMessageType::Matrix(n) => IdentityFieldValue::Matrix(n),
+                ExternalMessageType::Web(n) => IdentityFieldValue::Web(n),
+                ExternalMessageType::PGPFingerprint(n) => IdentityFieldValue::PGPFingerprint(n),
+                ExternalMessageType::Discord(n) => IdentityFieldValue::Discord(n),
+                ExternalMessageType::GitHub(n) => IdentityFieldValue::GitHub(n),
             }
         }
     }
@@ -629,4 +1048,115 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn expected_message_expiry() {
+        let challenge = ExpectedMessage::random();
+        assert!(!challenge.is_expired(60, &Timestamp(0)));
+        assert!(challenge.is_expired(0, &Timestamp(0)));
+
+        // Older challenges fall back to the provided timestamp.
+        let challenge = ExpectedMessage {
+            issued_at: None,
+            ..ExpectedMessage::random()
+        };
+        assert!(challenge.is_expired(60, &Timestamp(0)));
+        assert!(!challenge.is_expired(60, &Timestamp::now()));
+
+        let json = r#"{"value":"1234","is_verified":false}"#;
+        let challenge = serde_json::from_str::<ExpectedMessage>(json).unwrap();
+        assert_eq!(challenge.issued_at, None);
+    }
+
+    #[test]
+    fn pgp_fingerprint_serde() {
+        let value = IdentityFieldValue::PGPFingerprint("ABCD".to_string());
+        let json = serde_json::to_string(&value).unwrap();
+        assert_eq!(json, r#"{"type":"p_g_p_fingerprint","value":"ABCD"}"#);
+        assert_eq!(
+            serde_json::from_str::<IdentityFieldValue>(&json).unwrap(),
+            value
+        );
+
+        // Messages are matched against the field value.
+        let origin = ExternalMessageType::PGPFingerprint("ABCD".to_string());
+        assert_eq!(serde_json::to_string(&origin).unwrap(), json);
+
+        // Database queries use the same tag.
+        assert_eq!(
+            IdentityFieldValue::type_tag(&RawFieldName::PGPFingerprint).unwrap(),
+            "p_g_p_fingerprint"
+        );
+        assert_eq!(
+            IdentityFieldValue::type_tag(&RawFieldName::GitHub).unwrap(),
+            "github"
+        );
+
+        // Older entries did not store the fingerprint.
+        let json = r#"{"type":"p_g_p_fingerprint","value":null}"#;
+        assert_eq!(
+            serde_json::from_str::<IdentityFieldValue>(json).unwrap(),
+            IdentityFieldValue::PGPFingerprint(String::new())
+        );
+    }
+
+    #[test]
+    fn network_detection() {
+        let network = |name: &str, ss58_prefix| NetworkConfig {
+            name: ChainName::from(name),
+            ss58_prefix,
+            watcher: "ws://localhost:8000".to_string(),
+            watcher_version: None,
+            default_judgement: None,
+        };
+
+        // Duplicate names are rejected, but prefixes can be shared.
+        assert!(check_networks(&[network("polkadot", 0), network("polkadot", 2)]).is_err());
+
+        let networks = [
+            network("polkadot", 0),
+            network("kusama", 2),
+            network("westend", 42),
+            network("polkadot_people", 0),
+        ];
+        check_networks(&networks).unwrap();
+
+        let address =
+            ChainAddress::from("5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY".to_string());
+        assert_eq!(
+            address.chain(&networks).unwrap(),
+            ChainName::from("westend")
+        );
+
+        // Only the configured networks are supported.
+        assert!(address.chain(&networks[..2]).is_err());
+
+        let context = IdentityContext::new(address, ChainName::from("westend"));
+        assert!(context.validate(&networks).is_ok());
+        assert!(context.validate(&networks[..2]).is_err());
+
+        // The network cannot be detected if the prefix is shared.
+        let address =
+            ChainAddress::from("15oF4uVJwmo4TdGW7VfQxNLavjCXviqxT9S1MgbjMNHr6Sp5".to_string());
+        assert!(address.chain(&networks).is_err());
+        assert_eq!(
+            address.chain(&networks[..3]).unwrap(),
+            ChainName::from("polkadot")
+        );
+
+        // But the address is valid for either network.
+        for chain in ["polkadot", "polkadot_people"] {
+            let context = IdentityContext::new(address.clone(), ChainName::from(chain));
+            assert!(context.validate(&networks).is_ok());
+        }
+
+        let context = IdentityContext::new(address, ChainName::from("kusama"));
+        assert!(context.validate(&networks).is_err());
+
+        // Serialized as plain string.
+        assert_eq!(
+            serde_json::to_string(&ChainName::from("westend")).unwrap(),
+            r#""westend""#
+        );
+    }
 }
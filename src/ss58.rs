@@ -0,0 +1,130 @@
+use crate::Result;
+use blake2::{Blake2b512, Digest};
+
+// Prepended to the address data when calculating the checksum.
+const CHECKSUM_PREFIX: &[u8] = b"SS58PRE";
+// Only 32-byte account IDs are supported, which use a two byte checksum.
+const PUBLIC_KEY_LENGTH: usize = 32;
+const CHECKSUM_LENGTH: usize = 2;
+
+/// A decoded SS58 address.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Ss58Address {
+    pub prefix: u16,
+    pub public_key: [u8; PUBLIC_KEY_LENGTH],
+}
+
+fn checksum(data: &[u8]) -> [u8; CHECKSUM_LENGTH] {
+    let mut hasher = Blake2b512::new();
+    hasher.update(CHECKSUM_PREFIX);
+    hasher.update(data);
+    let hash = hasher.finalize();
+
+    let mut checksum = [0; CHECKSUM_LENGTH];
+    checksum.copy_from_slice(&hash[..CHECKSUM_LENGTH]);
+    checksum
+}
+
+/// Decodes an SS58 address and validates its checksum.
+pub fn decode(address: &str) -> Result<Ss58Address> {
+    let data = bs58::decode(address.trim())
+        .into_vec()
+        .map_err(|_| anyhow!("invalid base58 encoding"))?;
+
+    // Network prefixes up to 63 take one byte, prefixes up to 16383 take two.
+    let (prefix_len, prefix) = match data.first() {
+        Some(0..=63) => (1, data[0] as u16),
+        Some(64..=127) if data.len() > 1 => {
+            let lower = (data[0] << 2) | (data[1] >> 6);
+            let upper = data[1] & 0b0011_1111;
+            (2, (lower as u16) | ((upper as u16) << 8))
+        }
+        Some(_) => return Err(anyhow!("invalid network prefix")),
+        None => return Err(anyhow!("empty address")),
+    };
+
+    if data.len() != prefix_len + PUBLIC_KEY_LENGTH + CHECKSUM_LENGTH {
+        return Err(anyhow!("invalid address length"));
+    }
+
+    let (body, expected) = data.split_at(data.len() - CHECKSUM_LENGTH);
+    if checksum(body) != expected {
+        return Err(anyhow!("invalid address checksum"));
+    }
+
+    let mut public_key = [0; PUBLIC_KEY_LENGTH];
+    public_key.copy_from_slice(&body[prefix_len..]);
+
+    Ok(Ss58Address { prefix, public_key })
+}
+
+/// Encodes the public key as SS58 address of the given network prefix.
+#[cfg(test)]
+pub fn encode(prefix: u16, public_key: &[u8; PUBLIC_KEY_LENGTH]) -> String {
+    let mut data = match prefix {
+        0..=63 => vec![prefix as u8],
+        _ => {
+            let first = ((prefix & 0b0000_0000_1111_1100) as u8) >> 2;
+            let second = ((prefix >> 8) as u8) | ((prefix & 0b0000_0000_0000_0011) as u8) << 6;
+            vec![first | 0b0100_0000, second]
+        }
+    };
+
+    data.extend_from_slice(public_key);
+    let checksum = checksum(&data);
+    data.extend_from_slice(&checksum);
+
+    bs58::encode(data).into_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_known_addresses() {
+        // Alice on Polkadot and Kusama.
+        let polkadot = decode("15oF4uVJwmo4TdGW7VfQxNLavjCXviqxT9S1MgbjMNHr6Sp5").unwrap();
+        let kusama = decode("HNZata7iMYWmk5RvZRTiAsSDhV8366zq2YGb3tLH5Upf74F").unwrap();
+
+        assert_eq!(polkadot.prefix, 0);
+        assert_eq!(kusama.prefix, 2);
+        assert_eq!(polkadot.public_key, kusama.public_key);
+        assert_eq!(
+            hex::encode(polkadot.public_key),
+            "d43593c715fdd31c61141abd04a99fd6822c8558854ccde39a5684e7a56da27d"
+        );
+    }
+
+    #[test]
+    fn encode_decode() {
+        for prefix in [0, 2, 42, 63, 64, 1284, 16383] {
+            let address = encode(prefix, &[7; 32]);
+            let decoded = decode(&address).unwrap();
+
+            assert_eq!(decoded.prefix, prefix);
+            assert_eq!(decoded.public_key, [7; 32]);
+        }
+
+        assert_eq!(
+            encode(
+                0,
+                &decode("HNZata7iMYWmk5RvZRTiAsSDhV8366zq2YGb3tLH5Upf74F")
+                    .unwrap()
+                    .public_key
+            ),
+            "15oF4uVJwmo4TdGW7VfQxNLavjCXviqxT9S1MgbjMNHr6Sp5"
+        );
+    }
+
+    #[test]
+    fn decode_invalid() {
+        // Invalid characters.
+        assert!(decode("0OIl").is_err());
+        assert!(decode("").is_err());
+        // Too short.
+        assert!(decode("Alice").is_err());
+        // Modified last character, invalidating the checksum.
+        assert!(decode("15oF4uVJwmo4TdGW7VfQxNLavjCXviqxT9S1MgbjMNHr6Sp6").is_err());
+    }
+}
@@ -1,13 +1,18 @@
 use super::*;
-use crate::api::VerifyChallenge;
+use crate::adapters::admin::RawFieldName;
+use crate::api::signature_verification::tests::encode_address;
 use crate::api::{JsonResult, ResponseAccountState};
+use crate::api::{RegenerateChallenge, VerifyChallenge};
+use crate::challenge::ChallengeFormat;
 use crate::connector::WatcherMessage;
 use crate::primitives::{
-    ExpectedMessage, ExternalMessage, ExternalMessageType, IdentityContext, MessageId,
-    NotificationMessage, Timestamp,
+    ChallengeType, ExpectedMessage, ExternalMessage, ExternalMessageType, FailureReason,
+    IdentityContext, MessageId, NotificationMessage, Timestamp,
 };
+use crate::{ChallengeConfig, LockoutConfig};
 use actix_http::StatusCode;
 use futures::{FutureExt, SinkExt, StreamExt};
+use schnorrkel::Keypair;
 
 #[actix::test]
 async fn current_judgement_state_single_identity() {
@@ -254,6 +259,7 @@ async fn verify_invalid_message_bad_challenge() {
         notifications: vec![NotificationMessage::FieldVerificationFailed {
             context: alice.context.clone(),
             field: F::ALICE_EMAIL(),
+            reason: FailureReason::WrongChallenge,
         }],
     };
 
@@ -309,8 +315,19 @@ async fn verify_invalid_message_bad_origin() {
         })
         .await;
 
-    // No response is sent. The service ignores unknown senders.
-    assert!(stream.next().now_or_never().is_none());
+    // The state is unaffected, but Alice is informed about the unrelated
+    // account.
+    let expected = ResponseAccountState {
+        state: alice.clone().into(),
+        notifications: vec![NotificationMessage::FieldVerificationFailed {
+            context: alice.context.clone(),
+            field: F::ALICE_EMAIL(),
+            reason: FailureReason::UnrelatedAccount,
+        }],
+    };
+
+    let resp: JsonResult<ResponseAccountState> = stream.next().await.into();
+    assert_eq!(resp, JsonResult::Ok(expected));
 
     // Other judgement states must be unaffected (Bob).
     stream.send(IdentityContext::bob().to_ws()).await.unwrap();
@@ -392,6 +409,77 @@ async fn verify_valid_message() {
     assert!(stream.next().now_or_never().is_none());
 }
 
+#[actix::test]
+async fn verify_used_challenge() {
+    let (_db, connector, mut api, injector) = new_env().await;
+    let mut stream = api.ws_at("/api/account_status").await.unwrap();
+
+    // Insert judgement request.
+    connector.inject(alice_judgement_request()).await;
+    let states = connector.inserted_states().await;
+    let mut alice = states[0].clone();
+
+    // Subscribe to endpoint.
+    stream.send(IdentityContext::alice().to_ws()).await.unwrap();
+
+    // Check current state.
+    let resp: JsonResult<ResponseAccountState> = stream.next().await.into();
+    assert_eq!(
+        resp,
+        JsonResult::Ok(ResponseAccountState::with_no_notifications(alice.clone()))
+    );
+
+    let msg = ExternalMessage {
+        origin: ExternalMessageType::Matrix("@alice:matrix.org".to_string()),
+        id: MessageId::from(0u32),
+        timestamp: Timestamp::now(),
+        values: alice
+            .get_field(&F::ALICE_MATRIX())
+            .expected_message()
+            .to_message_parts(),
+    };
+
+    // Send valid message.
+    injector.send(msg.clone()).await;
+
+    alice
+        .get_field_mut(&F::ALICE_MATRIX())
+        .expected_message_mut()
+        .set_verified();
+
+    let resp: JsonResult<ResponseAccountState> = stream.next().await.into();
+    assert_eq!(
+        resp,
+        JsonResult::Ok(ResponseAccountState {
+            state: alice.clone().into(),
+            notifications: vec![NotificationMessage::FieldVerified {
+                context: alice.context.clone(),
+                field: F::ALICE_MATRIX(),
+            }],
+        })
+    );
+
+    // Send the same challenge again.
+    injector.send(msg).await;
+
+    // The failed attempts are not increased.
+    let resp: JsonResult<ResponseAccountState> = stream.next().await.into();
+    assert_eq!(
+        resp,
+        JsonResult::Ok(ResponseAccountState {
+            state: alice.clone().into(),
+            notifications: vec![NotificationMessage::FieldVerificationFailed {
+                context: alice.context.clone(),
+                field: F::ALICE_MATRIX(),
+                reason: FailureReason::AlreadyVerified,
+            }],
+        })
+    );
+
+    // Empty stream.
+    assert!(stream.next().now_or_never().is_none());
+}
+
 #[actix::test]
 async fn verify_valid_message_duplicate_account_name() {
     let (_db, connector, mut api, injector) = new_env().await;
@@ -668,7 +756,9 @@ async fn verify_invalid_message_awaiting_second_challenge() {
 
     assert_eq!(res.status(), StatusCode::OK);
 
-    // Check for `SecondFieldVerified` notification.
+    // Check for `SecondFieldVerificationFailed` notification.
+    *alice.get_field_mut(&F::ALICE_EMAIL()).failed_attempts_mut() = 1;
+
     let expected = ResponseAccountState {
         state: alice.clone().into(),
         notifications: vec![NotificationMessage::SecondFieldVerificationFailed {
@@ -908,3 +998,512 @@ async fn verify_full_identity() {
     // Empty stream.
     assert!(stream_alice.next().now_or_never().is_none());
 }
+
+#[actix::test]
+async fn verify_matrix_second_challenge() {
+    let (db, _connector, mut api, injector) = new_env().await;
+    let mut stream = api.ws_at("/api/account_status").await.unwrap();
+
+    // Matrix accounts require a second challenge.
+    let connector = ConnectorMocker::with_second_challenges(db, vec![RawFieldName::Matrix]);
+
+    // Insert judgement request.
+    connector.inject(alice_judgement_request()).await;
+    let states = connector.inserted_states().await;
+    let mut alice = states[0].clone();
+
+    // Subscribe to endpoint.
+    stream.send(IdentityContext::alice().to_ws()).await.unwrap();
+
+    // Check current state.
+    let resp: JsonResult<ResponseAccountState> = stream.next().await.into();
+    assert_eq!(
+        resp,
+        JsonResult::Ok(ResponseAccountState::with_no_notifications(alice.clone()))
+    );
+
+    // Send valid message.
+    injector
+        .send(ExternalMessage {
+            origin: ExternalMessageType::Matrix("@alice:matrix.org".to_string()),
+            id: MessageId::from(0u32),
+            timestamp: Timestamp::now(),
+            values: alice
+                .get_field(&F::ALICE_MATRIX())
+                .expected_message()
+                .to_message_parts(),
+        })
+        .await;
+
+    // Matrix account of Alice is now verified, but not the second challenge.
+    alice
+        .get_field_mut(&F::ALICE_MATRIX())
+        .expected_message_mut()
+        .set_verified();
+
+    // Check for `FieldVerified` notification.
+    let expected = ResponseAccountState {
+        state: alice.clone().into(),
+        notifications: vec![NotificationMessage::FieldVerified {
+            context: alice.context.clone(),
+            field: F::ALICE_MATRIX(),
+        }],
+    };
+
+    let resp: JsonResult<ResponseAccountState> = stream.next().await.into();
+    assert_eq!(resp, JsonResult::Ok(expected));
+
+    // Check for `AwaitingSecondChallenge` notification.
+    let expected = ResponseAccountState {
+        state: alice.clone().into(),
+        notifications: vec![NotificationMessage::AwaitingSecondChallenge {
+            context: alice.context.clone(),
+            field: F::ALICE_MATRIX(),
+        }],
+    };
+
+    let resp: JsonResult<ResponseAccountState> = stream.next().await.into();
+    assert_eq!(resp, JsonResult::Ok(expected));
+
+    // Verify second challenge.
+    let challenge = VerifyChallenge {
+        entry: F::ALICE_MATRIX(),
+        challenge: alice
+            .get_field(&F::ALICE_MATRIX())
+            .expected_second()
+            .value
+            .clone(),
+    };
+
+    let res = api
+        .post("/api/verify_second_challenge")
+        .send_json(&challenge)
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), StatusCode::OK);
+
+    // Second challenge of Matrix account of Alice is now verified.
+    alice
+        .get_field_mut(&F::ALICE_MATRIX())
+        .expected_second_mut()
+        .set_verified();
+
+    // Check for `SecondFieldVerified` notification.
+    let expected = ResponseAccountState {
+        state: alice.clone().into(),
+        notifications: vec![NotificationMessage::SecondFieldVerified {
+            context: alice.context.clone(),
+            field: F::ALICE_MATRIX(),
+        }],
+    };
+
+    let resp: JsonResult<ResponseAccountState> = stream.next().await.into();
+    assert_eq!(resp, JsonResult::Ok(expected));
+
+    // Empty stream.
+    assert!(stream.next().now_or_never().is_none());
+}
+
+#[actix::test]
+async fn verify_invalid_message_lockout() {
+    let (mut db, connector, _api, injector) = new_env_with(|db| {
+        db.lockout(Some(LockoutConfig {
+            field_threshold: Some(2),
+            identity_threshold: None,
+            cooldown: 3600,
+        }))
+    })
+    .await;
+
+    // Insert judgement request.
+    connector.inject(alice_judgement_request()).await;
+    let states = connector.inserted_states().await;
+    let alice = states[0].clone();
+    let old_challenge = alice
+        .get_field(&F::ALICE_EMAIL())
+        .expected_message()
+        .clone();
+
+    // Send invalid messages (bad challenge), until the threshold is reached.
+    for id in 0u32..2 {
+        injector
+            .send(ExternalMessage {
+                origin: ExternalMessageType::Email("alice@email.com".to_string()),
+                id: MessageId::from(id),
+                timestamp: Timestamp::now(),
+                values: ExpectedMessage::random().to_message_parts(),
+            })
+            .await;
+    }
+
+    // The field is locked and the challenge was regenerated.
+    let state = db
+        .fetch_judgement_state(&alice.context)
+        .await
+        .unwrap()
+        .unwrap();
+    let field = state.get_field(&F::ALICE_EMAIL());
+    assert!(field.is_locked());
+    assert_eq!(field.failed_attempts, 0);
+    assert_ne!(field.expected_message(), &old_challenge);
+
+    // Other fields are unaffected.
+    assert!(!state.get_field(&F::ALICE_TWITTER()).is_locked());
+
+    let (events, _) = db.fetch_events(0).await.unwrap();
+    assert!(events.iter().any(|event| matches!(
+        event,
+        NotificationMessage::LockedOut { field: Some(field), .. } if field == &F::ALICE_EMAIL()
+    )));
+
+    // Valid messages are ignored during the cooldown.
+    let new_challenge = field.expected_message().clone();
+    let valid = ExternalMessage {
+        origin: ExternalMessageType::Email("alice@email.com".to_string()),
+        id: MessageId::from(2u32),
+        timestamp: Timestamp::now(),
+        values: new_challenge.to_message_parts(),
+    };
+
+    injector.send(valid.clone()).await;
+
+    let state = db
+        .fetch_judgement_state(&alice.context)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(
+        !state
+            .get_field(&F::ALICE_EMAIL())
+            .expected_message()
+            .is_verified
+    );
+
+    // Lift the lockout.
+    assert!(db.unlock(&alice.context).await.unwrap());
+
+    injector
+        .send(ExternalMessage {
+            id: MessageId::from(3u32),
+            ..valid
+        })
+        .await;
+
+    let state = db
+        .fetch_judgement_state(&alice.context)
+        .await
+        .unwrap()
+        .unwrap();
+    let field = state.get_field(&F::ALICE_EMAIL());
+    assert!(!field.is_locked());
+    assert!(field.expected_message().is_verified);
+}
+
+#[actix::test]
+async fn verify_invalid_message_identity_lockout() {
+    let (mut db, connector, _api, injector) = new_env_with(|db| {
+        db.lockout(Some(LockoutConfig {
+            field_threshold: Some(2),
+            identity_threshold: Some(3),
+            cooldown: 3600,
+        }))
+    })
+    .await;
+
+    // Insert judgement request.
+    connector.inject(alice_judgement_request()).await;
+    let states = connector.inserted_states().await;
+    let alice = states[0].clone();
+
+    // Lock the email field.
+    for id in 0u32..2 {
+        injector
+            .send(ExternalMessage {
+                origin: ExternalMessageType::Email("alice@email.com".to_string()),
+                id: MessageId::from(id),
+                timestamp: Timestamp::now(),
+                values: ExpectedMessage::random().to_message_parts(),
+            })
+            .await;
+    }
+
+    let state = db
+        .fetch_judgement_state(&alice.context)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(state.get_field(&F::ALICE_EMAIL()).is_locked());
+    assert!(!state.get_field(&F::ALICE_TWITTER()).is_locked());
+    assert_eq!(state.failed_attempts, 2);
+
+    // The attempts of the locked field still count towards the identity.
+    injector
+        .send(ExternalMessage {
+            origin: ExternalMessageType::Twitter("@alice".to_string()),
+            id: MessageId::from(2u32),
+            timestamp: Timestamp::now(),
+            values: ExpectedMessage::random().to_message_parts(),
+        })
+        .await;
+
+    let state = db
+        .fetch_judgement_state(&alice.context)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(state.fields.iter().all(|field| field.is_locked()));
+    assert_eq!(state.failed_attempts, 0);
+
+    let (events, _) = db.fetch_events(0).await.unwrap();
+    assert!(events
+        .iter()
+        .any(|event| matches!(event, NotificationMessage::LockedOut { field: None, .. })));
+}
+
+#[actix::test]
+async fn expired_challenge_regenerated() {
+    let (mut db, connector, mut api, _inj) = new_env_with(|db| {
+        db.challenge(Some(ChallengeConfig {
+            ttl: Some(3),
+            format: None,
+            require_signature: None,
+        }))
+    })
+    .await;
+    let mut stream = api.ws_at("/api/account_status").await.unwrap();
+
+    // Insert judgement request.
+    connector.inject(alice_judgement_request()).await;
+    let states = connector.inserted_states().await;
+    let alice = states[0].clone();
+
+    // Subscribe to endpoint.
+    stream.send(IdentityContext::alice().to_ws()).await.unwrap();
+
+    let resp: JsonResult<ResponseAccountState> = stream.next().await.into();
+    assert_eq!(
+        resp,
+        JsonResult::Ok(ResponseAccountState::with_no_notifications(alice.clone()))
+    );
+
+    // Wait for the challenges to expire.
+    sleep(Duration::from_secs(TEST_TIMEOUT)).await;
+
+    let state = db
+        .fetch_judgement_state(&alice.context)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_ne!(
+        state.get_field(&F::ALICE_EMAIL()).expected_message(),
+        alice.get_field(&F::ALICE_EMAIL()).expected_message()
+    );
+
+    let (events, _) = db.fetch_events(0).await.unwrap();
+    assert!(events.contains(&NotificationMessage::ChallengeRegenerated {
+        context: alice.context.clone(),
+        field: F::ALICE_EMAIL(),
+    }));
+}
+
+#[actix::test]
+async fn regenerate_challenge_on_request() {
+    let (db, connector, api, _inj) = new_env().await;
+
+    let keypair = Keypair::generate();
+
+    // Insert judgement request of an address with a known key.
+    connector
+        .inject(WatcherMessage::new_judgement_request({
+            let mut req = JudgementRequest::alice();
+            req.address = encode_address(&keypair.public.to_bytes());
+            req
+        }))
+        .await;
+    let states = connector.inserted_states().await;
+    let alice = states[0].clone();
+
+    let challenge = alice.signature_challenge.clone().unwrap();
+    let signature = keypair.sign_simple(b"substrate", challenge.value.as_bytes());
+    let request = RegenerateChallenge {
+        context: alice.context.clone(),
+        entry: F::ALICE_EMAIL(),
+        signature: hex::encode(signature.to_bytes()),
+    };
+
+    // Requests which are not signed by the owner of the address are rejected.
+    let other = Keypair::generate().sign_simple(b"substrate", challenge.value.as_bytes());
+    let mut res = api
+        .post("/api/regenerate_challenge")
+        .send_json(&RegenerateChallenge {
+            signature: hex::encode(other.to_bytes()),
+            ..request.clone()
+        })
+        .await
+        .unwrap();
+
+    let resp: JsonResult<bool> = res.json().await.unwrap();
+    assert_eq!(
+        resp,
+        JsonResult::Err("The challenge was not signed by the key of the address".to_string())
+    );
+
+    // The challenge was issued too recently.
+    let mut res = api
+        .post("/api/regenerate_challenge")
+        .send_json(&request)
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), StatusCode::OK);
+    let resp: JsonResult<bool> = res.json().await.unwrap();
+    assert_eq!(resp, JsonResult::Ok(false));
+
+    let state = db
+        .fetch_judgement_state(&alice.context)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        state.get_field(&F::ALICE_EMAIL()).expected_message(),
+        alice.get_field(&F::ALICE_EMAIL()).expected_message()
+    );
+
+    // Unknown fields can not be regenerated.
+    let mut res = api
+        .post("/api/regenerate_challenge")
+        .send_json(&RegenerateChallenge {
+            entry: F::Email("unknown@email.com".to_string()),
+            ..request
+        })
+        .await
+        .unwrap();
+
+    let resp: JsonResult<bool> = res.json().await.unwrap();
+    assert_eq!(resp, JsonResult::Ok(false));
+}
+
+#[actix::test]
+async fn verify_valid_message_without_second_challenge() {
+    let (db, connector, _api, _inj) = new_env().await;
+
+    // Insert judgement request.
+    connector.inject(alice_judgement_request()).await;
+    let states = connector.inserted_states().await;
+    let alice = states[0].clone();
+
+    // Matrix fields do not have a second challenge by default.
+    let field = alice.get_field(&F::ALICE_MATRIX());
+    assert!(matches!(
+        field.challenge,
+        ChallengeType::ExpectedMessage { second: None, .. }
+    ));
+
+    db.verify_message(&ExternalMessage {
+        origin: ExternalMessageType::Matrix("@alice:matrix.org".to_string()),
+        id: MessageId::from(0u32),
+        timestamp: Timestamp::now(),
+        values: field.expected_message().to_message_parts(),
+    })
+    .await
+    .unwrap();
+
+    let state = db
+        .fetch_judgement_state(&alice.context)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(
+        state
+            .get_field(&F::ALICE_MATRIX())
+            .expected_message()
+            .is_verified
+    );
+}
+
+#[actix::test]
+async fn verify_valid_message_word_challenge() {
+    let (db, connector, _api, injector) = new_env_with(|db| {
+        db.challenge(Some(ChallengeConfig {
+            ttl: None,
+            format: Some(ChallengeFormat::Words),
+            require_signature: None,
+        }))
+    })
+    .await;
+
+    // Insert judgement request.
+    connector.inject(alice_judgement_request()).await;
+    let states = connector.inserted_states().await;
+    let alice = states[0].clone();
+
+    let challenge = alice.get_field(&F::ALICE_TWITTER()).expected_message();
+    assert_eq!(challenge.format, ChallengeFormat::Words);
+
+    // Send the challenge with different casing and whitespace.
+    let typed = challenge.value.to_uppercase().replace(' ', "  ");
+    injector
+        .send(ExternalMessage {
+            origin: ExternalMessageType::Twitter("@alice".to_string()),
+            id: MessageId::from(0u32),
+            timestamp: Timestamp::now(),
+            values: vec![format!("Challenge: {}", typed).into()],
+        })
+        .await;
+
+    let state = db
+        .fetch_judgement_state(&alice.context)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(
+        state
+            .get_field(&F::ALICE_TWITTER())
+            .expected_message()
+            .is_verified
+    );
+}
+
+#[actix::test]
+async fn verify_second_word_challenge() {
+    let (db, connector, _api, _) = new_env_with(|db| {
+        db.challenge(Some(ChallengeConfig {
+            ttl: None,
+            format: Some(ChallengeFormat::Words),
+            require_signature: None,
+        }))
+    })
+    .await;
+
+    // Insert judgement request.
+    connector.inject(alice_judgement_request()).await;
+    let states = connector.inserted_states().await;
+    let alice = states[0].clone();
+
+    let challenge = alice.get_field(&F::ALICE_EMAIL()).expected_second();
+    assert_eq!(challenge.format, ChallengeFormat::Words);
+
+    // Enter the challenge with different casing and whitespace.
+    let verified = db
+        .verify_second_challenge(VerifyChallenge {
+            entry: F::ALICE_EMAIL(),
+            challenge: challenge.value.to_uppercase().replace(' ', "  "),
+        })
+        .await
+        .unwrap();
+    assert!(verified);
+
+    // The stored field is verified.
+    let state = db
+        .fetch_judgement_state(&alice.context)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(
+        state
+            .get_field(&F::ALICE_EMAIL())
+            .expected_second()
+            .is_verified
+    );
+}
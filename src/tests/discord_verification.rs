@@ -0,0 +1,142 @@
+use super::*;
+use crate::adapters::discord::tests::DiscordMock;
+use crate::adapters::discord::DiscordBuilder;
+use crate::api::{JsonResult, ResponseAccountState};
+use crate::primitives::{ExpectedMessage, FailureReason, IdentityContext, NotificationMessage};
+use futures::{FutureExt, SinkExt, StreamExt};
+
+async fn start_discord_adapter(db: &Database, api: &TestServer) {
+    let discord_client = DiscordBuilder::new(db.clone())
+        .token("token".to_string())
+        .guild_id("guild".to_string())
+        .api_base(Some(api.url("")))
+        .build()
+        .unwrap();
+
+    let listener = AdapterListener::new(db.clone()).await;
+    listener.start_message_adapter(discord_client, 1).await;
+
+    // Give the adapter enough time to fetch and process the messages.
+    sleep(Duration::from_secs(TEST_TIMEOUT)).await;
+}
+
+#[actix::test]
+async fn verify_discord_valid_message() {
+    let (db, connector, mut api, _) = new_env().await;
+    let mut stream = api.ws_at("/api/account_status").await.unwrap();
+
+    let discord = DiscordMock::new();
+    discord.add_member("alice");
+    let discord_api = discord.start();
+
+    // The Discord handle is specified in the additional fields.
+    connector
+        .inject(WatcherMessage::new_judgement_request({
+            let mut req = JudgementRequest::alice();
+            req.accounts.insert(
+                AccountType::Additional,
+                r#"[["Discord","Alice"]]"#.to_string(),
+            );
+            req
+        }))
+        .await;
+    let states = connector.inserted_states().await;
+    let mut alice = states[0].clone();
+
+    let field = F::Discord("alice".to_string());
+    assert!(alice.fields.iter().any(|f| f.value == field));
+
+    // Subscribe to endpoint.
+    stream.send(IdentityContext::alice().to_ws()).await.unwrap();
+
+    // Check current state.
+    let resp: JsonResult<ResponseAccountState> = stream.next().await.into();
+    assert_eq!(
+        resp,
+        JsonResult::Ok(ResponseAccountState::with_no_notifications(alice.clone()))
+    );
+
+    // Send the challenge to the bot.
+    discord.send_dm(
+        "alice",
+        &alice.get_field(&field).expected_message().value.clone(),
+    );
+
+    start_discord_adapter(&db, &discord_api).await;
+
+    // Discord account of Alice is now verified.
+    alice
+        .get_field_mut(&field)
+        .expected_message_mut()
+        .set_verified();
+
+    let expected = ResponseAccountState {
+        state: alice.clone().into(),
+        notifications: vec![NotificationMessage::FieldVerified {
+            context: alice.context.clone(),
+            field: field.clone(),
+        }],
+    };
+
+    let resp: JsonResult<ResponseAccountState> = stream.next().await.into();
+    assert_eq!(resp, JsonResult::Ok(expected));
+
+    // Empty stream.
+    assert!(stream.next().now_or_never().is_none());
+}
+
+#[actix::test]
+async fn verify_discord_invalid_message() {
+    let (db, connector, mut api, _) = new_env().await;
+    let mut stream = api.ws_at("/api/account_status").await.unwrap();
+
+    let discord = DiscordMock::new();
+    discord.add_member("alice");
+    let discord_api = discord.start();
+
+    connector
+        .inject(WatcherMessage::new_judgement_request({
+            let mut req = JudgementRequest::alice();
+            req.accounts
+                .insert(AccountType::Discord, "alice".to_string());
+            req
+        }))
+        .await;
+    let states = connector.inserted_states().await;
+    let mut alice = states[0].clone();
+
+    let field = F::Discord("alice".to_string());
+
+    // Subscribe to endpoint.
+    stream.send(IdentityContext::alice().to_ws()).await.unwrap();
+
+    // Check current state.
+    let resp: JsonResult<ResponseAccountState> = stream.next().await.into();
+    assert_eq!(
+        resp,
+        JsonResult::Ok(ResponseAccountState::with_no_notifications(alice.clone()))
+    );
+
+    // Send an invalid challenge to the bot.
+    discord.send_dm("alice", &ExpectedMessage::random().value);
+
+    start_discord_adapter(&db, &discord_api).await;
+
+    // The message is only processed once.
+    *alice.get_field_mut(&field).failed_attempts_mut() = 1;
+
+    let expected = ResponseAccountState {
+        state: alice.clone().into(),
+        notifications: vec![NotificationMessage::FieldVerificationFailed {
+            context: alice.context.clone(),
+            field: field.clone(),
+            reason: FailureReason::WrongChallenge,
+        }],
+    };
+
+    let resp: JsonResult<ResponseAccountState> = stream.next().await.into();
+    assert_eq!(resp, JsonResult::Ok(expected));
+
+    // Empty stream.
+    assert!(stream.next().now_or_never().is_none());
+}
@@ -0,0 +1,363 @@
+use super::*;
+use crate::adapters::email::{AuthenticationPolicy, EmailClientBuilder};
+use crate::api::tests::TEST_LINK_SECRET;
+use crate::api::{EmailLink, VerifyChallenge};
+use crate::database::EmailCursor;
+use crate::primitives::{
+    AuthenticationCheck, ChallengeType, EmailAuthentication, ExpectedMessage, ExternalMessage,
+    ExternalMessageType, IdentityContext, MessageId, Timestamp,
+};
+use crate::LockoutConfig;
+use actix_http::StatusCode;
+
+#[actix::test]
+async fn email_cursor_shared_mailbox() {
+    let (db, _connector, _api, _inj) = new_env().await;
+    let mailbox = "registrar@email.com@localhost/INBOX";
+
+    // No messages processed yet.
+    assert!(db.fetch_email_cursor(mailbox).await.unwrap().is_none());
+
+    db.reset_email_cursor(mailbox, None, 1).await.unwrap();
+    let cursor = db.fetch_email_cursor(mailbox).await.unwrap().unwrap();
+    assert_eq!(
+        cursor,
+        EmailCursor {
+            uid_validity: 1,
+            last_uid: 0,
+        }
+    );
+
+    // Resetting concurrently (another instance) has no effect.
+    db.reset_email_cursor(mailbox, None, 1).await.unwrap();
+
+    // Each message can only be claimed once, even if multiple instances share
+    // the same mailbox.
+    assert!(db.claim_email_uid(mailbox, 1, 11).await.unwrap());
+    assert!(!db.claim_email_uid(mailbox, 1, 11).await.unwrap());
+    assert!(db.claim_email_uid(mailbox, 1, 12).await.unwrap());
+    assert!(!db.claim_email_uid(mailbox, 1, 11).await.unwrap());
+
+    // Messages of a different UIDVALIDITY are not claimed.
+    assert!(!db.claim_email_uid(mailbox, 2, 13).await.unwrap());
+
+    let cursor = db.fetch_email_cursor(mailbox).await.unwrap().unwrap();
+    assert_eq!(cursor.last_uid, 12);
+
+    // Reset after the UIDVALIDITY of the mailbox has changed.
+    db.reset_email_cursor(mailbox, Some(&cursor), 2)
+        .await
+        .unwrap();
+    let new_cursor = db.fetch_email_cursor(mailbox).await.unwrap().unwrap();
+    assert_eq!(
+        new_cursor,
+        EmailCursor {
+            uid_validity: 2,
+            last_uid: 0,
+        }
+    );
+
+    // The outdated cursor of another instance does not reset it again.
+    assert!(db.claim_email_uid(mailbox, 2, 1).await.unwrap());
+    db.reset_email_cursor(mailbox, Some(&cursor), 2)
+        .await
+        .unwrap();
+    assert_eq!(
+        db.fetch_email_cursor(mailbox)
+            .await
+            .unwrap()
+            .unwrap()
+            .last_uid,
+        1
+    );
+}
+
+#[actix::test]
+async fn email_authentication_recorded() {
+    let (db, connector, _api, _inj) = new_env().await;
+
+    connector
+        .inject(WatcherMessage::new_judgement_request(
+            JudgementRequest::alice(),
+        ))
+        .await;
+    let _ = connector.inserted_states().await;
+
+    let origin = ExternalMessageType::Email("alice@email.com".to_string());
+    let authentication = EmailAuthentication {
+        dkim: AuthenticationCheck::none(),
+        spf: AuthenticationCheck {
+            result: "fail".to_string(),
+            domain: Some("attacker.com".to_string()),
+            aligned: false,
+        },
+        dmarc: AuthenticationCheck::none(),
+        passed: false,
+        timestamp: Timestamp::now(),
+    };
+
+    db.set_email_authentication(&origin, &authentication)
+        .await
+        .unwrap();
+
+    let state = db
+        .fetch_judgement_state(&IdentityContext::alice())
+        .await
+        .unwrap()
+        .unwrap();
+    let field = state.get_field(&F::ALICE_EMAIL());
+    assert_eq!(field.authentication, Some(authentication));
+
+    // Other fields are not affected.
+    assert!(state
+        .fields
+        .iter()
+        .filter(|field| field.value != F::ALICE_EMAIL())
+        .all(|field| field.authentication.is_none()));
+
+    // Email fields already have a second challenge, which is not replaced.
+    // The field is only marked as untrusted.
+    db.require_second_challenge(&origin).await.unwrap();
+
+    let new_state = db
+        .fetch_judgement_state(&IdentityContext::alice())
+        .await
+        .unwrap()
+        .unwrap();
+
+    let mut expected = state;
+    expected.get_field_mut(&F::ALICE_EMAIL()).untrusted = true;
+    assert_eq!(new_state, expected);
+    assert!(matches!(
+        &new_state.get_field(&F::ALICE_EMAIL()).challenge,
+        ChallengeType::ExpectedMessage {
+            second: Some(_),
+            ..
+        }
+    ));
+}
+
+/// Opens the link and submits the confirmation form, like a user would.
+async fn open_link(api: &TestServer, link: &EmailLink) -> (StatusCode, String) {
+    let url = url::Url::parse(&link.to_url(&api.url("")).unwrap()).unwrap();
+    let path = format!("{}?{}", url.path(), url.query().unwrap());
+
+    // Opening the link only serves the confirmation page.
+    let mut res = api.get(path.as_str()).send().await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let body = res.body().await.unwrap();
+    assert!(String::from_utf8(body.to_vec())
+        .unwrap()
+        .contains(r#"<form method="post">"#));
+
+    let mut res = api.post(path.as_str()).send().await.unwrap();
+    let body = res.body().await.unwrap();
+    (res.status(), String::from_utf8(body.to_vec()).unwrap())
+}
+
+#[actix::test]
+async fn verify_email_link() {
+    let (db, connector, api, _inj) = new_env().await;
+
+    connector
+        .inject(WatcherMessage::new_judgement_request(
+            JudgementRequest::alice(),
+        ))
+        .await;
+    let alice = connector.inserted_states().await[0].clone();
+    let second = alice.get_field(&F::ALICE_EMAIL()).expected_second().clone();
+
+    // Invalid signature.
+    let link = EmailLink::new("other_secret", "alice@email.com", &second.value, 60);
+    let (status, body) = open_link(&api, &link).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body, "Invalid or expired link");
+
+    // Expired.
+    let link = EmailLink::new(TEST_LINK_SECRET, "alice@email.com", &second.value, 0);
+    let (status, _) = open_link(&api, &link).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // Valid link, which is not verified by merely opening it.
+    let link = EmailLink::new(TEST_LINK_SECRET, "alice@email.com", &second.value, 60);
+    let url = url::Url::parse(&link.to_url(&api.url("")).unwrap()).unwrap();
+    let res = api
+        .get(format!("{}?{}", url.path(), url.query().unwrap()))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let state = db
+        .fetch_judgement_state(&IdentityContext::alice())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(
+        !state
+            .get_field(&F::ALICE_EMAIL())
+            .expected_second()
+            .is_verified
+    );
+
+    let (status, _) = open_link(&api, &link).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let state = db
+        .fetch_judgement_state(&IdentityContext::alice())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(
+        state
+            .get_field(&F::ALICE_EMAIL())
+            .expected_second()
+            .is_verified
+    );
+
+    // Links can only be used once.
+    let (status, body) = open_link(&api, &link).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body, "The link was already used");
+}
+
+#[actix::test]
+async fn verify_email_link_while_locked() {
+    let (db, connector, api, injector) = new_env_with(|db| {
+        db.lockout(Some(LockoutConfig {
+            field_threshold: None,
+            identity_threshold: Some(2),
+            cooldown: 3600,
+        }))
+    })
+    .await;
+
+    connector
+        .inject(WatcherMessage::new_judgement_request(
+            JudgementRequest::alice(),
+        ))
+        .await;
+    let alice = connector.inserted_states().await[0].clone();
+    let second = alice.get_field(&F::ALICE_EMAIL()).expected_second().clone();
+
+    // Lock all fields of the identity. The pending second challenge of the
+    // email field is kept.
+    for id in 0u32..2 {
+        injector
+            .send(ExternalMessage {
+                origin: ExternalMessageType::Twitter("@alice".to_string()),
+                id: MessageId::from(id),
+                timestamp: Timestamp::now(),
+                values: ExpectedMessage::random().to_message_parts(),
+            })
+            .await;
+    }
+
+    let state = db
+        .fetch_judgement_state(&alice.context)
+        .await
+        .unwrap()
+        .unwrap();
+    let field = state.get_field(&F::ALICE_EMAIL());
+    assert!(field.is_locked());
+    assert_eq!(field.expected_second(), &second);
+
+    // The link is refused during the lockout.
+    let link = EmailLink::new(TEST_LINK_SECRET, "alice@email.com", &second.value, 60);
+    let (status, body) = open_link(&api, &link).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_ne!(body, "The link was already used");
+
+    // The link is still valid once the lockout is lifted.
+    assert!(db.unlock(&alice.context).await.unwrap());
+
+    let (status, _) = open_link(&api, &link).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let state = db
+        .fetch_judgement_state(&alice.context)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(
+        state
+            .get_field(&F::ALICE_EMAIL())
+            .expected_second()
+            .is_verified
+    );
+}
+
+/// Processes the first challenge of Alice, sent in an email which failed the
+/// authentication checks, and opens the verification link of the second
+/// challenge afterwards.
+async fn open_link_after_failed_authentication(
+    policy: AuthenticationPolicy,
+) -> (Database, ExpectedMessage, StatusCode) {
+    let (db, connector, api, _inj) = new_env().await;
+
+    connector
+        .inject(WatcherMessage::new_judgement_request(
+            JudgementRequest::alice(),
+        ))
+        .await;
+    let alice = connector.inserted_states().await[0].clone();
+    let field = alice.get_field(&F::ALICE_EMAIL());
+    let second = field.expected_second().clone();
+
+    let client = EmailClientBuilder::new(db.clone())
+        .smtp_server("localhost".to_string())
+        .imap_server("localhost".to_string())
+        .authentication_policy(Some(policy))
+        .authserv_id(Some("mx.registrar.com".to_string()))
+        .email_inbox("INBOX".to_string())
+        .email_user("registrar@email.com".to_string())
+        .email_password("password".to_string())
+        .build()
+        .unwrap();
+
+    let message = ExternalMessage {
+        origin: ExternalMessageType::Email("alice@email.com".to_string()),
+        id: MessageId::from(0u32),
+        timestamp: Timestamp::now(),
+        values: field.expected_message().to_message_parts(),
+    };
+
+    let authentication = EmailAuthentication {
+        dkim: AuthenticationCheck::none(),
+        spf: AuthenticationCheck::none(),
+        dmarc: AuthenticationCheck::none(),
+        passed: false,
+        timestamp: Timestamp::now(),
+    };
+
+    assert!(client
+        .apply_authentication_policy(&message, authentication)
+        .await
+        .unwrap());
+    db.verify_message(&message).await.unwrap();
+
+    let link = EmailLink::new(TEST_LINK_SECRET, "alice@email.com", &second.value, 60);
+    let (status, _) = open_link(&api, &link).await;
+
+    (db, second, status)
+}
+
+#[actix::test]
+async fn verify_email_link_failed_authentication() {
+    // Flagged messages are processed as usual.
+    let (_, _, status) = open_link_after_failed_authentication(AuthenticationPolicy::Flag).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // The link is refused, the second challenge must be entered manually.
+    let (db, second, status) =
+        open_link_after_failed_authentication(AuthenticationPolicy::RequireSecondChallenge).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    assert!(db
+        .verify_second_challenge(VerifyChallenge {
+            entry: F::ALICE_EMAIL(),
+            challenge: second.value,
+        })
+        .await
+        .unwrap());
+}
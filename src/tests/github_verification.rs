@@ -0,0 +1,121 @@
+use super::*;
+use crate::adapters::github::tests::GitHubMock;
+use crate::adapters::github::GitHubBuilder;
+use crate::primitives::{ExpectedMessage, JudgementState};
+
+/// Inserts the judgement request of Alice with the given GitHub handle.
+async fn insert_alice(connector: &ConnectorMocker, handle: &str) -> JudgementState {
+    connector
+        .inject(WatcherMessage::new_judgement_request({
+            let mut req = JudgementRequest::alice();
+            req.accounts.insert(AccountType::GitHub, handle.to_string());
+            req
+        }))
+        .await;
+
+    connector.inserted_states().await[0].clone()
+}
+
+/// Runs the adapter against the mock and returns the updated state of Alice.
+async fn run_github_adapter(
+    db: &Database,
+    github: &GitHubMock,
+    alice: &JudgementState,
+) -> JudgementState {
+    let api = github.start();
+    let github_client = GitHubBuilder::new(db.clone())
+        .api_base(Some(api.url("")))
+        .build()
+        .unwrap();
+
+    let listener = AdapterListener::new(db.clone()).await;
+    listener.start_message_adapter(github_client, 1).await;
+
+    // Give the adapter enough time to fetch the content multiple times.
+    sleep(Duration::from_secs(TEST_TIMEOUT)).await;
+
+    db.fetch_judgement_state(&alice.context)
+        .await
+        .unwrap()
+        .unwrap()
+}
+
+#[actix::test]
+async fn verify_github_readme() {
+    let (db, connector, _api, _) = new_env().await;
+    let alice = insert_alice(&connector, "https://github.com/Alice").await;
+    let field = F::GitHub("alice".to_string());
+
+    // The challenge is only published in the profile README, the gist is
+    // unrelated.
+    let github = GitHubMock::new();
+    github.add_gist("alice", "fn main() {}");
+    github.set_readme(
+        "alice",
+        &format!(
+            "# Alice\n\n{}\n",
+            alice.get_field(&field).expected_message().value
+        ),
+    );
+
+    let alice = run_github_adapter(&db, &github, &alice).await;
+    let field = alice.get_field(&field);
+    assert!(field.challenge.is_verified());
+    assert_eq!(field.failed_attempts, 0);
+}
+
+#[actix::test]
+async fn verify_github_gist_with_several_files() {
+    let (db, connector, _api, _) = new_env().await;
+    let alice = insert_alice(&connector, "alice").await;
+    let field = F::GitHub("alice".to_string());
+
+    // Only one of the files contains the challenge.
+    let github = GitHubMock::new();
+    github.add_gist_files(
+        "alice",
+        &[
+            "README.md",
+            &alice.get_field(&field).expected_message().value,
+            "notes.txt",
+        ],
+    );
+
+    let alice = run_github_adapter(&db, &github, &alice).await;
+    let field = alice.get_field(&field);
+    assert!(field.challenge.is_verified());
+    assert_eq!(field.failed_attempts, 0);
+}
+
+#[actix::test]
+async fn verify_github_invalid_content() {
+    let (db, connector, _api, _) = new_env().await;
+    let alice = insert_alice(&connector, "alice").await;
+    let field = F::GitHub("alice".to_string());
+
+    let github = GitHubMock::new();
+    github.add_gist("alice", &ExpectedMessage::random().value);
+
+    // The same content only counts as a single failed attempt, even though
+    // it was fetched multiple times.
+    let alice = run_github_adapter(&db, &github, &alice).await;
+    let field = alice.get_field(&field);
+    assert!(!field.challenge.is_verified());
+    assert_eq!(field.failed_attempts, 1);
+}
+
+#[actix::test]
+async fn verify_github_invalid_username() {
+    let (db, connector, _api, _) = new_env().await;
+    let alice = insert_alice(&connector, "alice/repos").await;
+    let field = F::GitHub("alice/repos".to_string());
+
+    // The username is never used in requests.
+    let github = GitHubMock::new();
+    let alice = run_github_adapter(&db, &github, &alice).await;
+    assert_eq!(github.requests(), 0);
+
+    let field = alice.get_field(&field);
+    assert!(!field.challenge.is_verified());
+    assert_eq!(field.failed_attempts, 0);
+}
@@ -31,6 +31,7 @@ async fn run_mocker() -> Result<()> {
             enabled: true,
             limit: 0.85,
         },
+        verification_link_secret: None,
     };
 
     info!("Starting mock adapter and session notifier instances");
@@ -38,7 +39,7 @@ async fn run_mocker() -> Result<()> {
     // Setup database
     let db = Database::new(&db_config.uri, &db_config.name).await?;
 
-    config_session_notifier(db.clone(), notifier_config).await?;
+    config_session_notifier(db.clone(), crate::tests::networks(), notifier_config).await?;
 
     // Setup message verifier and injector.
     let injector = MessageInjector::new();
@@ -4,7 +4,8 @@ use crate::api::JsonResult;
 use crate::connector::{AccountType, JudgementRequest, WatcherMessage};
 use crate::database::Database;
 use crate::notifier::run_session_notifier;
-use crate::primitives::IdentityFieldValue;
+use crate::primitives::{ChainName, IdentityFieldValue};
+use crate::NetworkConfig;
 use crate::{api::tests::run_test_server, connector::tests::ConnectorMocker};
 use actix_http::ws::{Frame, ProtocolError};
 use actix_test::TestServer;
@@ -16,10 +17,16 @@ use tokio::time::{sleep, Duration};
 
 mod api_judgement_state;
 mod background_tasks;
+mod discord_verification;
 mod display_name_verification;
+mod email_verification;
 mod explicit;
+mod github_verification;
 mod live_mocker;
+mod pgp_verification;
 mod process_admin_cmds;
+mod signature_verification;
+mod web_verification;
 
 // Convenience type
 pub type F = IdentityFieldValue;
@@ -49,6 +56,19 @@ impl<T: DeserializeOwned> From<Option<Result<Frame, ProtocolError>>> for JsonRes
     }
 }
 
+/// The networks of the test identities.
+pub fn networks() -> Vec<NetworkConfig> {
+    let network = |name: &str, ss58_prefix| NetworkConfig {
+        name: ChainName::from(name),
+        ss58_prefix,
+        watcher: String::new(),
+        watcher_version: Some(2),
+        default_judgement: None,
+    };
+
+    vec![network("polkadot", 0), network("kusama", 2)]
+}
+
 pub fn alice_judgement_request() -> WatcherMessage {
     WatcherMessage::new_judgement_request(JudgementRequest::alice())
 }
@@ -57,9 +77,8 @@ pub fn bob_judgement_request() -> WatcherMessage {
     WatcherMessage::new_judgement_request(JudgementRequest::bob())
 }
 
-// async fn new_env() -> (TestServer, ConnectorMocker, MessageInjector) {
-async fn new_env() -> (Database, ConnectorMocker, TestServer, MessageInjector) {
-    // Setup MongoDb database.
+/// Connects to a new, empty database.
+pub async fn new_db() -> Database {
     let random: u32 = thread_rng().gen_range(u32::MIN..u32::MAX);
     let db = Database::new(
         "mongodb://localhost:27017/",
@@ -68,6 +87,23 @@ async fn new_env() -> (Database, ConnectorMocker, TestServer, MessageInjector) {
     .await
     .unwrap();
 
+    db.create_indexes().await.unwrap();
+    db
+}
+
+// async fn new_env() -> (TestServer, ConnectorMocker, MessageInjector) {
+async fn new_env() -> (Database, ConnectorMocker, TestServer, MessageInjector) {
+    new_env_with(|db| db).await
+}
+
+// Applies additional configuration to the database, e.g. the lockout.
+async fn new_env_with<F>(configure: F) -> (Database, ConnectorMocker, TestServer, MessageInjector)
+where
+    F: FnOnce(Database) -> Database,
+{
+    // Setup MongoDb database.
+    let db = configure(new_db().await);
+
     // Setup API
     let (server, actor) = run_test_server(db.clone()).await;
 
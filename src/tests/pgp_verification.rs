@@ -0,0 +1,162 @@
+use super::*;
+use crate::api::pgp_verification::tests::PgpKey;
+use crate::api::pgp_verification::VerifyPgp;
+use crate::api::{JsonResult, ResponseAccountState};
+use crate::primitives::{FailureReason, IdentityContext, NotificationMessage};
+use actix_http::StatusCode;
+use futures::{FutureExt, SinkExt, StreamExt};
+
+#[actix::test]
+async fn verify_pgp_valid_signature() {
+    let (_db, connector, mut api, _) = new_env().await;
+    let mut stream = api.ws_at("/api/account_status").await.unwrap();
+
+    let key = PgpKey::generate();
+    let pgp = F::PGPFingerprint(key.fingerprint());
+
+    // Insert judgement request.
+    connector
+        .inject(WatcherMessage::new_judgement_request({
+            let mut req = JudgementRequest::alice();
+            req.accounts
+                .insert(AccountType::PGPFingerprint, key.fingerprint());
+            req
+        }))
+        .await;
+    let states = connector.inserted_states().await;
+    let mut alice = states[0].clone();
+
+    // Subscribe to endpoint.
+    stream.send(IdentityContext::alice().to_ws()).await.unwrap();
+
+    // Check current state.
+    let resp: JsonResult<ResponseAccountState> = stream.next().await.into();
+    assert_eq!(
+        resp,
+        JsonResult::Ok(ResponseAccountState::with_no_notifications(alice.clone()))
+    );
+
+    // Sign the challenge.
+    let req = VerifyPgp {
+        context: alice.context.clone(),
+        public_key: key.public_key(),
+        signed_message: key.sign(&alice.get_field(&pgp).expected_message().value),
+    };
+
+    // Send it to the API endpoint.
+    let mut res = api.post("/api/verify_pgp").send_json(&req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let resp: JsonResult<bool> = res.json().await.unwrap();
+    assert_eq!(resp, JsonResult::Ok(true));
+
+    // PGP fingerprint of Alice is now verified.
+    alice
+        .get_field_mut(&pgp)
+        .expected_message_mut()
+        .set_verified();
+
+    let expected = ResponseAccountState {
+        state: alice.clone().into(),
+        notifications: vec![NotificationMessage::FieldVerified {
+            context: alice.context.clone(),
+            field: pgp.clone(),
+        }],
+    };
+
+    let resp: JsonResult<ResponseAccountState> = stream.next().await.into();
+    assert_eq!(resp, JsonResult::Ok(expected));
+
+    // Empty stream.
+    assert!(stream.next().now_or_never().is_none());
+}
+
+#[actix::test]
+async fn verify_pgp_invalid_signature() {
+    let (_db, connector, mut api, _) = new_env().await;
+    let mut stream = api.ws_at("/api/account_status").await.unwrap();
+
+    let key = PgpKey::generate();
+    let other = PgpKey::generate();
+    let pgp = F::PGPFingerprint(key.fingerprint());
+
+    // Insert judgement request.
+    connector
+        .inject(WatcherMessage::new_judgement_request({
+            let mut req = JudgementRequest::alice();
+            req.accounts
+                .insert(AccountType::PGPFingerprint, key.fingerprint());
+            req
+        }))
+        .await;
+    let states = connector.inserted_states().await;
+    let mut alice = states[0].clone();
+
+    // Subscribe to endpoint.
+    stream.send(IdentityContext::alice().to_ws()).await.unwrap();
+
+    // Check current state.
+    let resp: JsonResult<ResponseAccountState> = stream.next().await.into();
+    assert_eq!(
+        resp,
+        JsonResult::Ok(ResponseAccountState::with_no_notifications(alice.clone()))
+    );
+
+    // Challenge signed by a key of a different fingerprint.
+    let req = VerifyPgp {
+        context: alice.context.clone(),
+        public_key: other.public_key(),
+        signed_message: other.sign(&alice.get_field(&pgp).expected_message().value),
+    };
+
+    let mut res = api.post("/api/verify_pgp").send_json(&req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let resp: JsonResult<bool> = res.json().await.unwrap();
+    assert!(matches!(resp, JsonResult::Err(_)));
+
+    // Valid signature, but invalid challenge.
+    let req = VerifyPgp {
+        context: alice.context.clone(),
+        public_key: key.public_key(),
+        signed_message: key.sign("INVALID"),
+    };
+
+    let mut res = api.post("/api/verify_pgp").send_json(&req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let resp: JsonResult<bool> = res.json().await.unwrap();
+    assert_eq!(resp, JsonResult::Ok(false));
+
+    *alice.get_field_mut(&pgp).failed_attempts_mut() = 1;
+
+    let expected = ResponseAccountState {
+        state: alice.clone().into(),
+        notifications: vec![NotificationMessage::FieldVerificationFailed {
+            context: alice.context.clone(),
+            field: pgp.clone(),
+            reason: FailureReason::WrongChallenge,
+        }],
+    };
+
+    let resp: JsonResult<ResponseAccountState> = stream.next().await.into();
+    assert_eq!(resp, JsonResult::Ok(expected));
+
+    // Empty stream.
+    assert!(stream.next().now_or_never().is_none());
+}
+
+#[actix::test]
+async fn verify_pgp_payload_too_large() {
+    let (_db, _connector, api, _) = new_env().await;
+
+    // Rejected before any GnuPG process is started.
+    let req = VerifyPgp {
+        context: IdentityContext::alice(),
+        public_key: "a".repeat(100 * 1024),
+        signed_message: String::new(),
+    };
+
+    let res = api.post("/api/verify_pgp").send_json(&req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+}
@@ -1,10 +1,13 @@
 use super::*;
 use crate::adapters::admin::{process_admin, Command, RawFieldName, Response};
 use crate::api::{JsonResult, ResponseAccountState};
+use crate::connector::{ClientCommand, Judgement};
 use crate::primitives::{
-    IdentityContext, IdentityFieldValue, JudgementStateBlanked, NotificationMessage,
+    ExternalMessage, ExternalMessageType, IdentityContext, IdentityFieldValue,
+    JudgementStateBlanked, MessageId, NotificationMessage, Timestamp,
 };
 use futures::{FutureExt, SinkExt, StreamExt};
+use tokio::time::{sleep, Duration};
 
 #[actix::test]
 async fn command_status() {
@@ -16,8 +19,79 @@ async fn command_status() {
     let alice = states[0].clone();
 
     // Request status.
-    let res = process_admin(&db, Command::Status(alice.context.address.clone())).await;
-    assert_eq!(res, Response::Status(JudgementStateBlanked::from(alice)));
+    let res = process_admin(
+        &db,
+        &networks(),
+        Command::Status(alice.context.address.clone().into()),
+    )
+    .await;
+    assert_eq!(
+        res,
+        Response::Status(Box::new(JudgementStateBlanked::from(alice)))
+    );
+}
+
+#[actix::test]
+async fn command_status_image_and_additional() {
+    let (db, connector, _api, _) = new_env().await;
+
+    // Insert judgement request.
+    connector
+        .inject(WatcherMessage::new_judgement_request({
+            let mut req = JudgementRequest::alice();
+            req.accounts
+                .insert(AccountType::Image, "0x1234".to_string());
+            req.accounts.insert(
+                AccountType::Additional,
+                r#"[["discord","alice#1234"],["github","alice"]]"#.to_string(),
+            );
+            req
+        }))
+        .await;
+    let states = connector.inserted_states().await;
+    let alice = states[0].clone();
+
+    // Request status.
+    let res = process_admin(
+        &db,
+        &networks(),
+        Command::Status(alice.context.address.clone().into()),
+    )
+    .await;
+    let state = match res {
+        Response::Status(state) => *state,
+        _ => panic!(),
+    };
+
+    // Submitted values are kept.
+    assert!(state
+        .fields
+        .iter()
+        .any(|field| field.value == IdentityFieldValue::Image("0x1234".to_string())));
+    assert!(state.fields.iter().any(|field| field.value
+        == IdentityFieldValue::Additional(vec![
+            ("discord".to_string(), "alice#1234".to_string()),
+            ("github".to_string(), "alice".to_string()),
+        ])));
+
+    // Manually verify.
+    let resp = process_admin(
+        &db,
+        &networks(),
+        Command::Verify(
+            alice.context.address.clone().into(),
+            vec![RawFieldName::Image, RawFieldName::Additional],
+        ),
+    )
+    .await;
+
+    assert_eq!(
+        resp,
+        Response::Verified(
+            alice.context.address.clone(),
+            vec![RawFieldName::Image, RawFieldName::Additional]
+        )
+    );
 }
 
 #[actix::test]
@@ -43,8 +117,9 @@ async fn command_verify_multiple_challenge_types() {
     // Manually verify.
     let resp = process_admin(
         &db,
+        &networks(),
         Command::Verify(
-            alice.context.address.clone(),
+            alice.context.address.clone().into(),
             vec![RawFieldName::DisplayName, RawFieldName::Email],
         ),
     )
@@ -101,7 +176,11 @@ async fn command_verify_multiple_challenge_types() {
     // Manually verify twitter field.
     let resp = process_admin(
         &db,
-        Command::Verify(alice.context.address.clone(), vec![RawFieldName::Twitter]),
+        &networks(),
+        Command::Verify(
+            alice.context.address.clone().into(),
+            vec![RawFieldName::Twitter],
+        ),
     )
     .await;
 
@@ -142,7 +221,7 @@ async fn command_verify_unsupported_field() {
         .inject(WatcherMessage::new_judgement_request({
             let mut req = JudgementRequest::alice();
             req.accounts
-                .insert(AccountType::Web, "alice.com".to_string());
+                .insert(AccountType::LegalName, "Alice Doe".to_string());
             req
         }))
         .await;
@@ -162,18 +241,22 @@ async fn command_verify_unsupported_field() {
     // Manually verify.
     let resp = process_admin(
         &db,
-        Command::Verify(alice.context.address.clone(), vec![RawFieldName::Web]),
+        &networks(),
+        Command::Verify(
+            alice.context.address.clone().into(),
+            vec![RawFieldName::LegalName],
+        ),
     )
     .await;
 
     assert_eq!(
         resp,
-        Response::Verified(alice.context.address.clone(), vec![RawFieldName::Web])
+        Response::Verified(alice.context.address.clone(), vec![RawFieldName::LegalName])
     );
 
-    // Web is now verified.
+    // Legal name is now verified.
     let is_verified = alice
-        .get_field_mut(&IdentityFieldValue::Web("alice.com".to_string()))
+        .get_field_mut(&IdentityFieldValue::LegalName("Alice Doe".to_string()))
         .expected_unsupported_mut();
     *is_verified = Some(true);
 
@@ -182,7 +265,7 @@ async fn command_verify_unsupported_field() {
         state: alice.clone().into(),
         notifications: vec![NotificationMessage::ManuallyVerified {
             context: alice.context.clone(),
-            field: RawFieldName::Web,
+            field: RawFieldName::LegalName,
         }],
     };
 
@@ -198,8 +281,24 @@ async fn command_verify_all() {
     let (db, connector, mut api, _) = new_env().await;
     let mut stream = api.ws_at("/api/account_status").await.unwrap();
 
+    let pgp =
+        IdentityFieldValue::PGPFingerprint("9A4E2C2B0D6F1E3A5B7C9D0E1F2A3B4C5D6E7F80".to_string());
+
     // Insert judgement request.
-    connector.inject(alice_judgement_request()).await;
+    connector
+        .inject(WatcherMessage::new_judgement_request({
+            let mut req = JudgementRequest::alice();
+            req.accounts.insert(
+                AccountType::PGPFingerprint,
+                "9A4E2C2B0D6F1E3A5B7C9D0E1F2A3B4C5D6E7F80".to_string(),
+            );
+            req.accounts
+                .insert(AccountType::Discord, "alice".to_string());
+            req.accounts
+                .insert(AccountType::GitHub, "alice".to_string());
+            req
+        }))
+        .await;
     let states = connector.inserted_states().await;
     let mut alice = states[0].clone();
 
@@ -216,7 +315,11 @@ async fn command_verify_all() {
     // Manually verify.
     let resp = process_admin(
         &db,
-        Command::Verify(alice.context.address.clone(), vec![RawFieldName::All]),
+        &networks(),
+        Command::Verify(
+            alice.context.address.clone().into(),
+            vec![RawFieldName::All],
+        ),
     )
     .await;
 
@@ -265,6 +368,21 @@ async fn command_verify_all() {
         .expected_message_mut()
         .set_verified();
 
+    alice
+        .get_field_mut(&pgp)
+        .expected_message_mut()
+        .set_verified();
+
+    alice
+        .get_field_mut(&F::Discord("alice".to_string()))
+        .expected_message_mut()
+        .set_verified();
+
+    alice
+        .get_field_mut(&F::GitHub("alice".to_string()))
+        .expected_message_mut()
+        .set_verified();
+
     assert!(alice.check_full_verification());
 
     let expected = ResponseAccountState {
@@ -309,7 +427,11 @@ async fn command_verify_missing_field() {
     // Manually verify a field that does not exist.
     let resp = process_admin(
         &db,
-        Command::Verify(alice.context.address.clone(), vec![RawFieldName::Email]),
+        &networks(),
+        Command::Verify(
+            alice.context.address.clone().into(),
+            vec![RawFieldName::Email],
+        ),
     )
     .await;
 
@@ -318,3 +440,351 @@ async fn command_verify_missing_field() {
     // Empty stream.
     assert!(stream.next().now_or_never().is_none());
 }
+
+#[actix::test]
+async fn command_verify_pending_second_challenge() {
+    let (db, _connector, _api, injector) = new_env().await;
+
+    // Twitter accounts require a second challenge.
+    let connector =
+        ConnectorMocker::with_second_challenges(db.clone(), vec![RawFieldName::Twitter]);
+
+    // Insert judgement request.
+    connector.inject(alice_judgement_request()).await;
+    let states = connector.inserted_states().await;
+    let alice = states[0].clone();
+
+    // Verify the first challenge.
+    injector
+        .send(ExternalMessage {
+            origin: ExternalMessageType::Twitter("@alice".to_string()),
+            id: MessageId::from(0u32),
+            timestamp: Timestamp::now(),
+            values: alice
+                .get_field(&F::ALICE_TWITTER())
+                .expected_message()
+                .to_message_parts(),
+        })
+        .await;
+
+    // Manually verify the pending second challenge.
+    let resp = process_admin(
+        &db,
+        &networks(),
+        Command::Verify(
+            alice.context.address.clone().into(),
+            vec![RawFieldName::Twitter],
+        ),
+    )
+    .await;
+    assert_eq!(
+        resp,
+        Response::Verified(alice.context.address.clone(), vec![RawFieldName::Twitter])
+    );
+
+    let state = db
+        .fetch_judgement_state(&alice.context)
+        .await
+        .unwrap()
+        .unwrap();
+    let field = state.get_field(&F::ALICE_TWITTER());
+    assert!(field.expected_message().is_verified);
+    assert!(field.expected_second().is_verified);
+}
+
+#[actix::test]
+async fn command_erroneous() {
+    let (db, mut connector, _api, _) = new_env().await;
+
+    // Insert judgement request.
+    connector.inject(alice_judgement_request()).await;
+    let states = connector.inserted_states().await;
+    let alice = states[0].clone();
+
+    // Mark as erroneous.
+    let resp = process_admin(
+        &db,
+        &networks(),
+        Command::Erroneous(alice.context.address.clone().into()),
+    )
+    .await;
+    assert_eq!(
+        resp,
+        Response::MarkedErroneous(alice.context.address.clone())
+    );
+
+    let state = db
+        .fetch_judgement_state(&alice.context)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(state.is_erroneous);
+    assert!(!state.is_fully_verified);
+
+    // Wait for the judgement candidates task.
+    sleep(Duration::from_secs(3)).await;
+
+    let (out, counter) = connector.outgoing();
+    assert!(counter.provide_judgement > 0);
+    assert!(out.iter().any(|msg| matches!(
+        msg,
+        ClientCommand::ProvideJudgement(context, Judgement::Erroneous) if context == &alice.context
+    )));
+    assert!(!out.iter().any(|msg| matches!(
+        msg,
+        ClientCommand::ProvideJudgement(_, Judgement::Reasonable)
+    )));
+}
+
+#[actix::test]
+async fn command_erroneous_judgement_submitted() {
+    let (db, connector, _api, _) = new_env().await;
+
+    // Insert judgement request.
+    connector.inject(alice_judgement_request()).await;
+    let states = connector.inserted_states().await;
+    let alice = states[0].clone();
+
+    // Verify the identity and submit the judgement.
+    let resp = process_admin(
+        &db,
+        &networks(),
+        Command::Verify(
+            alice.context.address.clone().into(),
+            vec![RawFieldName::All],
+        ),
+    )
+    .await;
+    assert_eq!(resp, Response::FullyVerified(alice.context.address.clone()));
+    db.set_judged(&alice.context).await.unwrap();
+
+    // Marking as erroneous is refused.
+    let resp = process_admin(
+        &db,
+        &networks(),
+        Command::Erroneous(alice.context.address.clone().into()),
+    )
+    .await;
+    assert_eq!(
+        resp,
+        Response::JudgementAlreadySubmitted(alice.context.address.clone())
+    );
+
+    let state = db
+        .fetch_judgement_state(&alice.context)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(!state.is_erroneous);
+    assert!(state.judgement_submitted);
+}
+
+#[actix::test]
+async fn command_level() {
+    let (db, mut connector, _api, _) = new_env().await;
+
+    // Insert judgement request.
+    connector.inject(alice_judgement_request()).await;
+    let states = connector.inserted_states().await;
+    let alice = states[0].clone();
+
+    // Choose judgement level.
+    let resp = process_admin(
+        &db,
+        &networks(),
+        Command::Level(alice.context.address.clone().into(), Judgement::KnownGood),
+    )
+    .await;
+    assert_eq!(
+        resp,
+        Response::JudgementLevel(alice.context.address.clone(), Judgement::KnownGood)
+    );
+
+    let state = db
+        .fetch_judgement_state(&alice.context)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(state.judgement_level, Some(Judgement::KnownGood));
+
+    // Not issued before the identity is fully verified.
+    sleep(Duration::from_secs(3)).await;
+    let (_out, counter) = connector.outgoing();
+    assert_eq!(counter.provide_judgement, 0);
+}
+
+#[actix::test]
+async fn command_level_judgement_submitted() {
+    let (db, connector, _api, _) = new_env().await;
+
+    // Insert judgement request.
+    connector.inject(alice_judgement_request()).await;
+    let states = connector.inserted_states().await;
+    let alice = states[0].clone();
+
+    // Verify the identity and submit the judgement.
+    let resp = process_admin(
+        &db,
+        &networks(),
+        Command::Verify(
+            alice.context.address.clone().into(),
+            vec![RawFieldName::All],
+        ),
+    )
+    .await;
+    assert_eq!(resp, Response::FullyVerified(alice.context.address.clone()));
+    db.set_judged(&alice.context).await.unwrap();
+
+    // Choosing the judgement level is refused.
+    let resp = process_admin(
+        &db,
+        &networks(),
+        Command::Level(alice.context.address.clone().into(), Judgement::KnownGood),
+    )
+    .await;
+    assert_eq!(
+        resp,
+        Response::JudgementAlreadySubmitted(alice.context.address.clone())
+    );
+
+    let state = db
+        .fetch_judgement_state(&alice.context)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(state.judgement_level, None);
+}
+
+#[actix::test]
+async fn command_level_erroneous() {
+    let (db, connector, _api, _) = new_env().await;
+
+    // Insert judgement request.
+    connector.inject(alice_judgement_request()).await;
+    let states = connector.inserted_states().await;
+    let alice = states[0].clone();
+
+    let resp = process_admin(
+        &db,
+        &networks(),
+        Command::Erroneous(alice.context.address.clone().into()),
+    )
+    .await;
+    assert_eq!(
+        resp,
+        Response::MarkedErroneous(alice.context.address.clone())
+    );
+
+    // Choosing the judgement level is refused.
+    let resp = process_admin(
+        &db,
+        &networks(),
+        Command::Level(alice.context.address.clone().into(), Judgement::KnownGood),
+    )
+    .await;
+    assert_eq!(
+        resp,
+        Response::AlreadyErroneous(alice.context.address.clone())
+    );
+
+    let state = db
+        .fetch_judgement_state(&alice.context)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(state.judgement_level, None);
+    assert!(state.is_erroneous);
+}
+
+#[actix::test]
+async fn command_level_unsupported_by_watcher() {
+    let (db, connector, _api, _) = new_env().await;
+
+    // Insert judgement request.
+    connector.inject(alice_judgement_request()).await;
+    let states = connector.inserted_states().await;
+    let alice = states[0].clone();
+
+    // Watchers of version 1 only support reasonable judgements.
+    let networks: Vec<NetworkConfig> = networks()
+        .into_iter()
+        .map(|mut network| {
+            network.watcher_version = None;
+            network
+        })
+        .collect();
+
+    let resp = process_admin(
+        &db,
+        &networks,
+        Command::Level(alice.context.address.clone().into(), Judgement::KnownGood),
+    )
+    .await;
+    assert_eq!(
+        resp,
+        Response::UnsupportedJudgement(alice.context.chain.clone(), Judgement::KnownGood)
+    );
+
+    let resp = process_admin(
+        &db,
+        &networks,
+        Command::Level(alice.context.address.clone().into(), Judgement::Reasonable),
+    )
+    .await;
+    assert_eq!(
+        resp,
+        Response::JudgementLevel(alice.context.address.clone(), Judgement::Reasonable)
+    );
+
+    let state = db
+        .fetch_judgement_state(&alice.context)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(state.judgement_level, Some(Judgement::Reasonable));
+}
+
+#[actix::test]
+async fn judgement_level_unsupported_after_config_change() {
+    let (db, _connector, _api, _) = new_env().await;
+
+    // The Watcher was downgraded after the level was chosen.
+    let mut network = networks().remove(0);
+    network.watcher_version = None;
+    let mut connector = ConnectorMocker::with_network(db.clone(), network, vec![]);
+
+    // Insert judgement request.
+    connector.inject(alice_judgement_request()).await;
+    let states = connector.inserted_states().await;
+    let alice = states[0].clone();
+
+    assert!(db
+        .set_judgement_level(&alice.context, Judgement::KnownGood)
+        .await
+        .unwrap());
+
+    let resp = process_admin(
+        &db,
+        &networks(),
+        Command::Verify(
+            alice.context.address.clone().into(),
+            vec![RawFieldName::All],
+        ),
+    )
+    .await;
+    assert_eq!(resp, Response::FullyVerified(alice.context.address.clone()));
+
+    // Wait for the judgement candidates task.
+    sleep(Duration::from_secs(3)).await;
+
+    // The default judgement is issued instead.
+    let (out, _counter) = connector.outgoing();
+    assert!(out.iter().any(|msg| matches!(
+        msg,
+        ClientCommand::ProvideJudgement(context, Judgement::Reasonable) if context == &alice.context
+    )));
+    assert!(!out.iter().any(|msg| matches!(
+        msg,
+        ClientCommand::ProvideJudgement(_, Judgement::KnownGood)
+    )));
+}
@@ -0,0 +1,186 @@
+use super::*;
+use crate::adapters::admin::RawFieldName;
+use crate::api::signature_verification::tests::encode_address;
+use crate::api::signature_verification::VerifySignature;
+use crate::api::{JsonResult, ResponseAccountState};
+use crate::primitives::NotificationMessage;
+use crate::ChallengeConfig;
+use actix_http::StatusCode;
+use futures::{FutureExt, SinkExt, StreamExt};
+use schnorrkel::Keypair;
+
+const SIGNING_CONTEXT: &[u8] = b"substrate";
+
+fn judgement_request(keypair: &Keypair) -> WatcherMessage {
+    WatcherMessage::new_judgement_request({
+        let mut req = JudgementRequest::alice();
+        req.address = encode_address(&keypair.public.to_bytes());
+        req
+    })
+}
+
+#[actix::test]
+async fn verify_signature_valid() {
+    let (_db, connector, mut api, _) = new_env().await;
+    let mut stream = api.ws_at("/api/account_status").await.unwrap();
+
+    let keypair = Keypair::generate();
+
+    // Insert judgement request.
+    connector.inject(judgement_request(&keypair)).await;
+    let states = connector.inserted_states().await;
+    let mut alice = states[0].clone();
+
+    // Subscribe to endpoint.
+    stream.send(alice.context.to_ws()).await.unwrap();
+
+    // Check current state.
+    let resp: JsonResult<ResponseAccountState> = stream.next().await.into();
+    assert_eq!(
+        resp,
+        JsonResult::Ok(ResponseAccountState::with_no_notifications(alice.clone()))
+    );
+
+    // Sign the challenge.
+    let challenge = alice.signature_challenge.clone().unwrap();
+    let signature = keypair.sign_simple(SIGNING_CONTEXT, challenge.value.as_bytes());
+    let req = VerifySignature {
+        context: alice.context.clone(),
+        signature: format!("0x{}", hex::encode(signature.to_bytes())),
+    };
+
+    // Send it to the API endpoint.
+    let mut res = api
+        .post("/api/verify_signature")
+        .send_json(&req)
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let resp: JsonResult<bool> = res.json().await.unwrap();
+    assert_eq!(resp, JsonResult::Ok(true));
+
+    // The signature challenge is now verified.
+    alice.signature_challenge.as_mut().unwrap().set_verified();
+
+    let expected = ResponseAccountState {
+        state: alice.clone().into(),
+        notifications: vec![NotificationMessage::SignatureVerified {
+            context: alice.context.clone(),
+        }],
+    };
+
+    let resp: JsonResult<ResponseAccountState> = stream.next().await.into();
+    assert_eq!(resp, JsonResult::Ok(expected));
+
+    // Empty stream.
+    assert!(stream.next().now_or_never().is_none());
+}
+
+#[actix::test]
+async fn verify_signature_invalid_key() {
+    let (_db, connector, mut api, _) = new_env().await;
+    let mut stream = api.ws_at("/api/account_status").await.unwrap();
+
+    let keypair = Keypair::generate();
+
+    // Insert judgement request.
+    connector.inject(judgement_request(&keypair)).await;
+    let states = connector.inserted_states().await;
+    let alice = states[0].clone();
+
+    // Subscribe to endpoint.
+    stream.send(alice.context.to_ws()).await.unwrap();
+
+    let resp: JsonResult<ResponseAccountState> = stream.next().await.into();
+    assert_eq!(
+        resp,
+        JsonResult::Ok(ResponseAccountState::with_no_notifications(alice.clone()))
+    );
+
+    // Sign the challenge with a different key.
+    let challenge = alice.signature_challenge.clone().unwrap();
+    let signature = Keypair::generate().sign_simple(SIGNING_CONTEXT, challenge.value.as_bytes());
+    let req = VerifySignature {
+        context: alice.context.clone(),
+        signature: hex::encode(signature.to_bytes()),
+    };
+
+    let mut res = api
+        .post("/api/verify_signature")
+        .send_json(&req)
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let resp: JsonResult<bool> = res.json().await.unwrap();
+    assert!(matches!(resp, JsonResult::Err(_)));
+
+    // No notification is sent.
+    assert!(stream.next().now_or_never().is_none());
+}
+
+#[actix::test]
+async fn signature_required_for_judgement() {
+    let (db, connector, api, _) = new_env_with(|db| {
+        db.challenge(Some(ChallengeConfig {
+            ttl: None,
+            format: None,
+            require_signature: Some(true),
+        }))
+    })
+    .await;
+
+    let keypair = Keypair::generate();
+
+    // Insert judgement request.
+    connector.inject(judgement_request(&keypair)).await;
+    let states = connector.inserted_states().await;
+    let alice = states[0].clone();
+
+    // Verify all fields manually.
+    for field in &[
+        RawFieldName::DisplayName,
+        RawFieldName::Email,
+        RawFieldName::Twitter,
+        RawFieldName::Matrix,
+    ] {
+        db.verify_manually(&alice.context, field, true)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    // The judgement is not issued without the signature.
+    let state = db
+        .fetch_judgement_state(&alice.context)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(state.check_full_verification());
+    assert!(!state.is_fully_verified);
+
+    // Sign the challenge.
+    let challenge = alice.signature_challenge.clone().unwrap();
+    let signature = keypair.sign_simple(SIGNING_CONTEXT, challenge.value.as_bytes());
+    let req = VerifySignature {
+        context: alice.context.clone(),
+        signature: hex::encode(signature.to_bytes()),
+    };
+
+    let mut res = api
+        .post("/api/verify_signature")
+        .send_json(&req)
+        .await
+        .unwrap();
+
+    let resp: JsonResult<bool> = res.json().await.unwrap();
+    assert_eq!(resp, JsonResult::Ok(true));
+
+    let state = db
+        .fetch_judgement_state(&alice.context)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(state.is_fully_verified);
+}
@@ -0,0 +1,214 @@
+use super::*;
+use crate::adapters::web::tests::start_dns_stub;
+use crate::adapters::web::WebClientBuilder;
+use crate::api::{JsonResult, ResponseAccountState};
+use crate::primitives::{ExpectedMessage, FailureReason, IdentityContext, NotificationMessage};
+use actix_web::{web, App, HttpResponse};
+use futures::{FutureExt, SinkExt, StreamExt};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+async fn serve_challenge(content: web::Data<Mutex<String>>) -> HttpResponse {
+    HttpResponse::Ok().body(content.lock().unwrap().clone())
+}
+
+/// Stand-in for the website of the user, serving the challenge file.
+fn challenge_server(content: Arc<Mutex<String>>) -> TestServer {
+    actix_test::start(move || {
+        App::new()
+            .app_data(web::Data::from(Arc::clone(&content)))
+            .route(
+                "/.well-known/polkadot-identity.txt",
+                web::get().to(serve_challenge),
+            )
+    })
+}
+
+async fn start_web_adapter(db: &Database, dns_records: HashMap<String, Vec<String>>) {
+    let web_client = WebClientBuilder::new(db.clone())
+        .scheme("http")
+        .allow_private_addresses()
+        .dns_resolver(Some(
+            start_dns_stub(Arc::new(Mutex::new(dns_records))).to_string(),
+        ))
+        .build()
+        .unwrap();
+
+    let listener = AdapterListener::new(db.clone()).await;
+    listener.start_message_adapter(web_client, 1).await;
+
+    // Give the adapter enough time to fetch and process the challenge file.
+    sleep(Duration::from_secs(TEST_TIMEOUT)).await;
+}
+
+#[actix::test]
+async fn verify_web_valid_challenge() {
+    let (db, connector, mut api, _) = new_env().await;
+    let mut stream = api.ws_at("/api/account_status").await.unwrap();
+
+    let content = Arc::new(Mutex::new(String::new()));
+    let website = challenge_server(Arc::clone(&content));
+    let web = F::Web(website.addr().to_string());
+
+    // Insert judgement request.
+    connector
+        .inject(WatcherMessage::new_judgement_request({
+            let mut req = JudgementRequest::alice();
+            req.accounts
+                .insert(AccountType::Web, website.addr().to_string());
+            req
+        }))
+        .await;
+    let states = connector.inserted_states().await;
+    let mut alice = states[0].clone();
+
+    // Subscribe to endpoint.
+    stream.send(IdentityContext::alice().to_ws()).await.unwrap();
+
+    // Check current state.
+    let resp: JsonResult<ResponseAccountState> = stream.next().await.into();
+    assert_eq!(
+        resp,
+        JsonResult::Ok(ResponseAccountState::with_no_notifications(alice.clone()))
+    );
+
+    // Upload the challenge file.
+    *content.lock().unwrap() = alice.get_field(&web).expected_message().value.clone();
+
+    start_web_adapter(&db, HashMap::new()).await;
+
+    // Web field of Alice is now verified.
+    alice
+        .get_field_mut(&web)
+        .expected_message_mut()
+        .set_verified();
+
+    let expected = ResponseAccountState {
+        state: alice.clone().into(),
+        notifications: vec![NotificationMessage::FieldVerified {
+            context: alice.context.clone(),
+            field: web.clone(),
+        }],
+    };
+
+    let resp: JsonResult<ResponseAccountState> = stream.next().await.into();
+    assert_eq!(resp, JsonResult::Ok(expected));
+
+    // Empty stream.
+    assert!(stream.next().now_or_never().is_none());
+}
+
+#[actix::test]
+async fn verify_web_invalid_challenge() {
+    let (db, connector, mut api, _) = new_env().await;
+    let mut stream = api.ws_at("/api/account_status").await.unwrap();
+
+    let content = Arc::new(Mutex::new(String::new()));
+    let website = challenge_server(Arc::clone(&content));
+    let web = F::Web(website.addr().to_string());
+
+    // Insert judgement request.
+    connector
+        .inject(WatcherMessage::new_judgement_request({
+            let mut req = JudgementRequest::alice();
+            req.accounts
+                .insert(AccountType::Web, website.addr().to_string());
+            req
+        }))
+        .await;
+    let states = connector.inserted_states().await;
+    let mut alice = states[0].clone();
+
+    // Subscribe to endpoint.
+    stream.send(IdentityContext::alice().to_ws()).await.unwrap();
+
+    // Check current state.
+    let resp: JsonResult<ResponseAccountState> = stream.next().await.into();
+    assert_eq!(
+        resp,
+        JsonResult::Ok(ResponseAccountState::with_no_notifications(alice.clone()))
+    );
+
+    // Upload an invalid challenge file.
+    *content.lock().unwrap() = ExpectedMessage::random().value;
+
+    start_web_adapter(&db, HashMap::new()).await;
+
+    // The same file content only counts as a single failed attempt, even
+    // though it was fetched multiple times.
+    *alice.get_field_mut(&web).failed_attempts_mut() = 1;
+
+    let expected = ResponseAccountState {
+        state: alice.clone().into(),
+        notifications: vec![NotificationMessage::FieldVerificationFailed {
+            context: alice.context.clone(),
+            field: web.clone(),
+            reason: FailureReason::WrongChallenge,
+        }],
+    };
+
+    let resp: JsonResult<ResponseAccountState> = stream.next().await.into();
+    assert_eq!(resp, JsonResult::Ok(expected));
+
+    // Empty stream.
+    assert!(stream.next().now_or_never().is_none());
+}
+
+#[actix::test]
+async fn verify_web_valid_dns_record() {
+    let (db, connector, mut api, _) = new_env().await;
+    let mut stream = api.ws_at("/api/account_status").await.unwrap();
+
+    // No challenge file is served on this domain.
+    let web = F::Web("alice.invalid".to_string());
+
+    // Insert judgement request.
+    connector
+        .inject(WatcherMessage::new_judgement_request({
+            let mut req = JudgementRequest::alice();
+            req.accounts
+                .insert(AccountType::Web, "alice.invalid".to_string());
+            req
+        }))
+        .await;
+    let states = connector.inserted_states().await;
+    let mut alice = states[0].clone();
+
+    // Subscribe to endpoint.
+    stream.send(IdentityContext::alice().to_ws()).await.unwrap();
+
+    // Check current state.
+    let resp: JsonResult<ResponseAccountState> = stream.next().await.into();
+    assert_eq!(
+        resp,
+        JsonResult::Ok(ResponseAccountState::with_no_notifications(alice.clone()))
+    );
+
+    // Create the TXT record.
+    let records = HashMap::from([(
+        "_polkadot-identity.alice.invalid.".to_string(),
+        vec![alice.get_field(&web).expected_message().value.clone()],
+    )]);
+
+    start_web_adapter(&db, records).await;
+
+    // Web field of Alice is now verified.
+    alice
+        .get_field_mut(&web)
+        .expected_message_mut()
+        .set_verified();
+
+    let expected = ResponseAccountState {
+        state: alice.clone().into(),
+        notifications: vec![NotificationMessage::FieldVerified {
+            context: alice.context.clone(),
+            field: web.clone(),
+        }],
+    };
+
+    let resp: JsonResult<ResponseAccountState> = stream.next().await.into();
+    assert_eq!(resp, JsonResult::Ok(expected));
+
+    // Empty stream.
+    assert!(stream.next().now_or_never().is_none());
+}
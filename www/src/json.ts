@@ -13,6 +13,12 @@ export interface State {
     is_fully_verified: boolean;
     completion_timestamp?: any;
     fields: Field[];
+    // Signed with the key of the address.
+    signature_challenge?: Expected;
+    // Judged as fraudulent.
+    is_erroneous?: boolean;
+    // Chosen by an admin, e.g. "knownGood".
+    judgement_level?: string;
 }
 
 export interface Context {
@@ -24,6 +30,7 @@ export interface Field {
     value: FieldValue;
     challenge: Challenge;
     failed_attempts: number;
+    locked_until?: number;
 }
 
 export interface FieldValue {
@@ -61,6 +68,19 @@ export interface NotificationFieldContext {
     field: FieldValue;
 }
 
+export interface FieldVerificationFailed {
+    context: Context;
+    field: FieldValue;
+    reason: string;
+}
+
+export interface LockedOut {
+    context: Context;
+    field?: FieldValue;
+    // UNIX timestamp in seconds.
+    until: number;
+}
+
 export interface ManuallyVerified {
     context: Context;
     field: string;
@@ -1,5 +1,5 @@
 import { capitalizeFirstLetter } from "./content.js";
-import { Notification, NotificationFieldContext, ManuallyVerified } from "./json";
+import { Notification, NotificationFieldContext, FieldVerificationFailed, LockedOut, ManuallyVerified } from "./json";
 
 export class NotificationHandler {
     notify_idx: number
@@ -100,9 +100,9 @@ function notificationTypeResolver(notification: Notification): [string, string]
             ]
         }
         case "field_verification_failed": {
-            let data = notification.value as NotificationFieldContext;
+            let data = notification.value as FieldVerificationFailed;
             return [
-                `${capitalizeFirstLetter(data.field.type)} account "${data.field.value}" failed to get verified. Invalid challenge.`,
+                `${capitalizeFirstLetter(data.field.type)} account "${data.field.value}" failed to get verified. ${failureReasonResolver(data.reason)}`,
                 "bg-danger text-light"
             ]
         }
@@ -127,6 +127,12 @@ function notificationTypeResolver(notification: Notification): [string, string]
                 "bg-info text-dark"
             ]
         }
+        case "signature_verified": {
+            return [
+                `The signature of the address owner is valid.`,
+                "bg-success text-light"
+            ]
+        }
         case "identity_fully_verified": {
             return [
                 `<strong>Verification process completed!</strong> Judgement will be issued in a couple of minutes.`,
@@ -139,6 +145,24 @@ function notificationTypeResolver(notification: Notification): [string, string]
                 "bg-success text-light"
             ]
         }
+        case "locked_out": {
+            let data = notification.value as LockedOut;
+            let until = new Date(data.until * 1000).toLocaleString();
+            let target = data.field
+                ? `${capitalizeFirstLetter(data.field.type)} account "${data.field.value}" is`
+                : `All accounts are`;
+            return [
+                `Too many failed attempts. ${target} locked until ${until}, a new challenge has been generated.`,
+                "bg-danger text-light"
+            ]
+        }
+        case "challenge_regenerated": {
+            let data = notification.value as NotificationFieldContext;
+            return [
+                `A new challenge has been generated for ${capitalizeFirstLetter(data.field.type)} account "${data.field.value}", the previous challenge is no longer valid.`,
+                "bg-info text-dark"
+            ]
+        }
         case "manually_verified": {
             let data = notification.value as ManuallyVerified;
             return [
@@ -156,4 +180,21 @@ function notificationTypeResolver(notification: Notification): [string, string]
             throw new Error("unrecognized notification");
         }
     }
-}
\ No newline at end of file
+}
+
+function failureReasonResolver(reason: string): string {
+    switch (reason) {
+        case "unrelated_account": {
+            return `The challenge was sent from an account that is not part of the identity.`
+        }
+        case "stale_challenge": {
+            return `The challenge was already used, please check for the additional challenge.`
+        }
+        case "already_verified": {
+            return `The account is already verified.`
+        }
+        default: {
+            return `Invalid challenge.`
+        }
+    }
+}